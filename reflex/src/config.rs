@@ -0,0 +1,199 @@
+use crate::logger::Filter;
+use crate::protocol::proxy;
+use crate::protocol::vision_stream::VisionEndpoint;
+use crate::protocol::wayland::wl_registry;
+use crate::token::read_token;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Globals advertised when none are given on the command line.
+const DEFAULT_GLOBALS: [&str; 3] = ["wl_compositor", "wl_shm", "xdg_wm_base"];
+
+const DEFAULT_VISION: &str = "unix:/mnt/c/Temp/reflex.unix";
+
+// The log filter in proxy mode when none is given.
+const PROXY_LOG_FILTER: &str = "info,protocol::trace=trace";
+
+pub struct Config {
+    // (interface, version) of each global, in the order they are announced.
+    pub globals: Vec<(String, u32)>,
+    // Socket name or path; the first free wayland-N when not given.
+    pub socket_name: Option<String>,
+    pub vision: VisionEndpoint,
+    // Pre-shared token presented to vision, required for tcp endpoints.
+    pub vision_token: Option<Arc<Vec<u8>>>,
+    // What is logged, as "<level>[,<module path>[@<client id>]=<level>]...".
+    pub log_filter: Filter,
+    // Logs go to stdout when not given.
+    pub log_file: Option<PathBuf>,
+    // A file whose contents replace log_filter whenever it changes.
+    pub log_level_file: Option<PathBuf>,
+    // A capture file to record the session into.
+    pub record: Option<PathBuf>,
+    // A capture file to replay instead of serving clients.
+    pub replay: Option<PathBuf>,
+    // The socket of a compositor to pass clients through to, instead of
+    // serving them.
+    pub proxy: Option<PathBuf>,
+}
+
+pub fn usage() -> String {
+    let interfaces: Vec<&str> = wl_registry::SUPPORTED_GLOBALS
+        .iter()
+        .map(|(interface, _)| *interface)
+        .collect();
+    format!(
+        "usage: ultimate-desktop-reflex [--socket <name>] [--global <interface>[:<version>]]...\n\
+         \x20      [--vision unix:<path>|tcp:<host>:<port>] [--vision-token-file <path>]\n\
+         \x20      [--log-level <level>[,<module>[@<client>]=<level>]...] [--log-file <path>]\n\
+         \x20      [--log-level-file <path>] [--record <path> | --replay <path>]\n\
+         \x20      [--proxy <compositor socket name or path>]\n\
+         levels: off, error, warn, info, debug, trace\n\
+         the vision token is sent in plaintext: use tcp only on a trusted network or a tunnel\n\
+         supported interfaces: {}",
+        interfaces.join(", ")
+    )
+}
+
+impl Config {
+    pub fn from_args<I>(args: I) -> Result<Config, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut globals = Vec::new();
+        let mut socket_name = None;
+        let mut vision = VisionEndpoint::parse(DEFAULT_VISION)?;
+        let mut vision_token = None;
+        let mut log_filter = Filter::parse("info")?;
+        let mut log_file = None;
+        let mut log_level_file = None;
+        let mut record = None;
+        let mut replay = None;
+        let mut proxy = None;
+        let mut log_level_given = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--global" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--global requires a value".to_owned())?;
+                    globals.push(parse_global(&value)?);
+                }
+                "--socket" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--socket requires a value".to_owned())?;
+                    socket_name = Some(value);
+                }
+                "--vision" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--vision requires a value".to_owned())?;
+                    vision = VisionEndpoint::parse(&value)?;
+                }
+                "--vision-token-file" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--vision-token-file requires a value".to_owned())?;
+                    vision_token = Some(Arc::new(read_token(&value)?));
+                }
+                "--log-level" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--log-level requires a value".to_owned())?;
+                    log_filter = Filter::parse(&value)?;
+                    log_level_given = true;
+                }
+                "--log-file" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--log-file requires a value".to_owned())?;
+                    log_file = Some(PathBuf::from(value));
+                }
+                "--log-level-file" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--log-level-file requires a value".to_owned())?;
+                    log_level_file = Some(PathBuf::from(value));
+                }
+                "--record" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--record requires a value".to_owned())?;
+                    record = Some(PathBuf::from(value));
+                }
+                "--replay" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--replay requires a value".to_owned())?;
+                    replay = Some(PathBuf::from(value));
+                }
+                "--proxy" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--proxy requires a value".to_owned())?;
+                    proxy = Some(proxy::socket_path(&value)?);
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+
+        if globals.is_empty() {
+            for interface in DEFAULT_GLOBALS.iter() {
+                globals.push(parse_global(interface)?);
+            }
+        }
+        if let VisionEndpoint::Tcp(..) = vision {
+            if vision_token.is_none() {
+                return Err("--vision-token-file is required for tcp endpoints".to_owned());
+            }
+        }
+        if record.is_some() && replay.is_some() {
+            return Err("--record and --replay cannot be used together".to_owned());
+        }
+        if proxy.is_some() {
+            if record.is_some() || replay.is_some() {
+                return Err("--proxy cannot be used with --record or --replay".to_owned());
+            }
+            // What goes through is what the proxy is for.
+            if !log_level_given {
+                log_filter = Filter::parse(PROXY_LOG_FILTER)?;
+            }
+        }
+        Ok(Config {
+            globals,
+            socket_name,
+            vision,
+            vision_token,
+            log_filter,
+            log_file,
+            log_level_file,
+            record,
+            replay,
+            proxy,
+        })
+    }
+}
+
+// "<interface>" or "<interface>:<version>". Without a version the highest
+// one implemented by reflex is advertised.
+fn parse_global(value: &str) -> Result<(String, u32), String> {
+    let mut parts = value.splitn(2, ':');
+    let interface = parts.next().unwrap_or("");
+    let supported_version = wl_registry::supported_version(interface)
+        .ok_or_else(|| format!("unsupported global: {}", interface))?;
+    let version = match parts.next() {
+        Some(version) => version
+            .parse::<u32>()
+            .map_err(|err| format!("invalid version for {}: {}", interface, err))?,
+        None => supported_version,
+    };
+    if version == 0 || version > supported_version {
+        return Err(format!(
+            "invalid version for {}: have {}, supported 1 to {}",
+            interface, version, supported_version
+        ));
+    }
+    Ok((interface.to_owned(), version))
+}
@@ -1,17 +1,24 @@
 use crate::protocol::connection_stream::ConnectionStream;
-use crate::protocol::event_sink::EventSink;
+use crate::protocol::event_sink::{EventSink, Hangup};
 use crate::protocol::fd_drop::FdDrop;
+use crate::protocol::fd_queue::FdQueue;
 use crate::protocol::raw_event::RawEvent;
 use crate::protocol::request_stream::RequestStream;
-use byteorder::{NativeEndian, ReadBytesExt};
+use byteorder::{ByteOrder, NativeEndian, ReadBytesExt};
 use futures::future::Future;
 use futures::future::{loop_fn, Loop};
 use futures::sink::Sink;
 use futures::stream::Stream;
+use protocol::capture::{Capture, Clock};
+use protocol::control::{self, ControlMessage};
 use protocol::event::Event;
+use protocol::link::{self, LinkInfo};
+use protocol::object_map::ObjectMap;
 use protocol::request::Request;
 use protocol::resource::Resource;
 use protocol::session::Session;
+use protocol::vision_link::{LinkFrame, VisionLink};
+use protocol::vision_stream::{VisionEndpoint, VisionStream};
 use protocol::wayland::wl_compositor::WlCompositor;
 use protocol::wayland::wl_data_device_manager::WlDataDeviceManager;
 use protocol::wayland::wl_display;
@@ -19,13 +26,24 @@ use protocol::wayland::wl_display::WlDisplay;
 use protocol::wayland::wl_registry::WlRegistry;
 use protocol::wayland::wl_shm::WlShm;
 use protocol::xdg_shell::xdg_wm_base::XdgWmBase;
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::sync::{Arc, RwLock};
-use tokio::io::{AsyncRead, ReadHalf};
-use tokio::net::UnixStream;
-use tokio::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadHalf, WriteHalf};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::timer::Delay;
 
+mod config;
+#[path = "../../shared/logger.rs"]
+mod logger;
+mod playback;
 mod protocol;
+#[allow(dead_code)]
+#[path = "../../shared/token.rs"]
+mod token;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 struct Global {
@@ -37,14 +55,30 @@ struct Global {
     xdg_wm_base: Arc<RwLock<XdgWmBase>>,
 }
 
+impl Global {
+    fn new(globals: &[(String, u32)]) -> Global {
+        Global {
+            wl_display: Arc::new(RwLock::new(WlDisplay {})),
+            wl_compositor: Arc::new(RwLock::new(WlCompositor {})),
+            wl_registry: Arc::new(RwLock::new(WlRegistry::new(globals))),
+            wl_shm: Arc::new(RwLock::new(WlShm {})),
+            wl_data_device_manager: Arc::new(RwLock::new(WlDataDeviceManager {})),
+            xdg_wm_base: Arc::new(RwLock::new(XdgWmBase {})),
+        }
+    }
+}
+
 fn handle_client_input(
     mut session: Session,
     request: Request,
 ) -> Box<dyn Future<Item = Session, Error = ()> + Send> {
-    session.fds.extend(request.fds);
-    let res = if let Some(x) = session.resources.remove(&request.sender_object_id) {
+    if let Some(capture) = session.vision.capture() {
+        capture.request(session.client_id, &request);
+    }
+    let res = if let Some(x) = session.resources.take(request.sender_object_id) {
         x
     } else {
+        session.fatal_error = true;
         let tx = session.tx.clone();
         let error = wl_display::events::Error {
             sender_object_id: 1,
@@ -60,6 +94,13 @@ fn handle_client_input(
         return f;
     };
 
+    protocol::trace::request(
+        &session.resources,
+        request.sender_object_id,
+        request.opcode,
+        &request.args,
+        &session.fds,
+    );
     let f: Box<dyn Future<Item = Session, Error = ()> + Send> =
         protocol::resource::dispatch_request(
             res,
@@ -71,122 +112,477 @@ fn handle_client_input(
     f
 }
 
-fn handle_client(
-    stream: UnixStream,
-    global: Global,
-    fd: i32,
-) -> Box<dyn Future<Item = (), Error = std::io::Error> + Send> {
-    let (r0, w0) = stream.split();
-    let fd_drop = Arc::new(FdDrop::new(fd));
-    let tokio_registration = Arc::new(tokio::reactor::Registration::new());
-    tokio_registration
-        .register(&mio::unix::EventedFd(&fd))
-        .expect("register request fd");
-    let reader0 = RequestStream::new(fd, fd_drop.clone(), tokio_registration.clone());
-    let writer0 = EventSink::new(fd, fd_drop.clone(), tokio_registration.clone());
-    let (tx0, rx0) = tokio::sync::mpsc::channel::<Box<dyn Event + Send>>(48000);
-    let output_session = rx0
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Oops!"))
-        .forward(writer0)
-        .map_err(|_| ())
-        .and_then(|_| Ok(()));
-    tokio::spawn(output_session);
-
-    let r_loop = loop_fn((tx0.clone(), r0), |(tx, r)| {
+// Route the frames vision sends to the client they are tagged with.
+fn read_vision(
+    r0: ReadHalf<VisionStream>,
+    vision: VisionLink,
+) -> impl Future<Item = (), Error = ()> {
+    loop_fn(r0, move |r| {
+        let vision = vision.clone();
         let mut header_buf = Vec::new();
-        header_buf.resize(12, 0);
+        header_buf.resize(16, 0);
         tokio::io::read_exact(r, header_buf)
-            .map_err(|_| ())
+            .map_err(|err| log::warn!("[Vision] read err: {:?}", err))
             .and_then(|(r1, buf1)| {
                 let mut cursor = std::io::Cursor::new(&buf1);
                 let response_type = cursor.read_u32::<NativeEndian>().unwrap();
-                let message_size = if response_type == 0 {
-                    let _sender_object_id = cursor.read_u32::<NativeEndian>().unwrap();
-                    let message_size_and_opcode = cursor.read_u32::<NativeEndian>().unwrap();
-                    (message_size_and_opcode >> 16) as usize
-                } else {
-                    cursor.read_u32::<NativeEndian>().unwrap() as usize
+                let _client_id = cursor.read_u32::<NativeEndian>().unwrap();
+                let body_size = match response_type {
+                    link::RESPONSE_EVENT => {
+                        let _sender_object_id = cursor.read_u32::<NativeEndian>().unwrap();
+                        let message_size_and_opcode = cursor.read_u32::<NativeEndian>().unwrap();
+                        let message_size = (message_size_and_opcode >> 16) as usize;
+                        if message_size < 8 {
+                            log::warn!(
+                                "[Vision] invalid message size {}, closing the link",
+                                message_size
+                            );
+                            return futures::future::Either::A(futures::future::err(()));
+                        }
+                        message_size - 8
+                    }
+                    link::RESPONSE_CONTROL => {
+                        let _kind = cursor.read_u32::<NativeEndian>().unwrap();
+                        let payload_size = cursor.read_u32::<NativeEndian>().unwrap() as usize;
+                        if payload_size > control::MAX_CONTROL_PAYLOAD {
+                            log::warn!(
+                                "[Vision] control message of {} bytes, closing the link",
+                                payload_size
+                            );
+                            return futures::future::Either::A(futures::future::err(()));
+                        }
+                        payload_size
+                    }
+                    _ => {
+                        log::warn!(
+                            "[Vision] unknown response_type={}, closing the link",
+                            response_type
+                        );
+                        return futures::future::Either::A(futures::future::err(()));
+                    }
                 };
                 let mut buf2: Vec<u8> = Vec::new();
-                buf2.resize(message_size - 8, 0);
-                tokio::io::read_exact(r1, buf2)
-                    .map_err(|_| ())
-                    .and_then(|(r2, buf3)| futures::future::ok((r2, buf1, buf3)))
+                buf2.resize(body_size, 0);
+                futures::future::Either::B(
+                    tokio::io::read_exact(r1, buf2)
+                        .map_err(|err| log::warn!("[Vision] read err: {:?}", err))
+                        .and_then(|(r2, buf3)| futures::future::ok((r2, buf1, buf3))),
+                )
             })
-            .and_then(|(r1, buf1, buf2)| {
-                let mut cursor = std::io::Cursor::new(&buf1);
-                let response_type = cursor.read_u32::<NativeEndian>().unwrap();
-                let mut data = Vec::new();
-                data.extend_from_slice(&buf1[4..]);
-                data.extend_from_slice(&buf2[..]);
-                println!("[Vision Event] type={} data={:?}", response_type, &data);
-                let f: Box<
-                    dyn futures::future::Future<
-                            Item = Loop<_, (Sender<Box<dyn Event + Send>>, ReadHalf<UnixStream>)>,
-                            Error = (),
-                        > + Send,
-                > = if response_type == 0 {
-                    Box::new(
-                        tx.send(Box::new(RawEvent { data }))
-                            .map_err(|_| ())
-                            .and_then(|tx1| Ok(Loop::Continue((tx1, r1)))),
-                    )
-                } else {
-                    Box::new(futures::future::ok(Loop::Continue((tx, r1))))
-                };
-                f
+            .and_then(move |(r1, buf1, buf2)| {
+                if let Some(capture) = vision.capture() {
+                    capture.vision(NativeEndian::read_u32(&buf1[4..]), &buf1, &buf2);
+                }
+                if let Some(frame_done) = route_vision_frame(&vision, &buf1, &buf2) {
+                    tokio::spawn(frame_done);
+                }
+                Ok(Loop::Continue(r1))
             })
-    });
-    tokio::spawn(r_loop);
+    })
+}
+
+// Route a frame from vision, header and body, to the client it is tagged
+// with. What a FRAME_DONE leaves to do is handed back for the caller to run.
+fn route_vision_frame(
+    vision: &VisionLink,
+    header: &[u8],
+    body: &[u8],
+) -> Option<impl Future<Item = (), Error = ()>> {
+    let mut cursor = std::io::Cursor::new(header);
+    let response_type = cursor.read_u32::<NativeEndian>().unwrap();
+    let client_id = cursor.read_u32::<NativeEndian>().unwrap();
+    let _client = logger::enter_client(client_id);
+    let mut frame_done = None;
+    let events: Vec<Box<dyn Event + Send>> = if response_type == link::RESPONSE_CONTROL {
+        let kind = cursor.read_u32::<NativeEndian>().unwrap();
+        match ControlMessage::decode(kind, body) {
+            Ok(ControlMessage::FrameDone { surface_id }) => {
+                frame_done = Some(logger::with_client(
+                    client_id,
+                    vision.frame_done(client_id, surface_id),
+                ));
+                Vec::new()
+            }
+            Ok(ControlMessage::SyncDone { serial }) => {
+                vision.sync_done(client_id, serial);
+                Vec::new()
+            }
+            Ok(message) => {
+                log::info!("[Vision Control] client={} {:?}", client_id, message);
+                vision.control(message)
+            }
+            Err(err) => {
+                log::warn!("[Vision] invalid control message: {}", err);
+                Vec::new()
+            }
+        }
+    } else {
+        let mut data = Vec::new();
+        data.extend_from_slice(&header[8..]);
+        data.extend_from_slice(body);
+        to_local_ids(&mut data);
+        log::trace!("[Vision Event] client={} data={:?}", client_id, &data);
+        vec![Box::new(RawEvent { data })]
+    };
+    let mut tx = match vision.client(client_id) {
+        Some(tx) => tx,
+        None => {
+            if !events.is_empty() {
+                log::warn!("[Vision] client {} is gone, events dropped", client_id);
+            }
+            return frame_done;
+        }
+    };
+    // Never wait for a client: one that does not read its events must not
+    // hold up those of the others. It is disconnected instead, as it would
+    // be out of step with vision once events are dropped.
+    for event in events {
+        if let Err(err) = tx.try_send(event) {
+            if err.is_full() {
+                log::warn!(
+                    "[Vision] client {} is not reading, disconnecting",
+                    client_id
+                );
+                if let Some(hangup) = vision.hangup(client_id) {
+                    hangup.hang_up();
+                }
+            } else {
+                log::warn!("[Vision] client {} is gone, events dropped", client_id);
+            }
+            break;
+        }
+    }
+    frame_done
+}
+
+// Connect and say hello, retrying with backoff until vision is up.
+fn connect_vision(
+    endpoint: VisionEndpoint,
+    token: Option<Arc<Vec<u8>>>,
+) -> impl Future<Item = (VisionStream, LinkInfo), Error = ()> {
+    loop_fn(MIN_RECONNECT_DELAY, move |delay| {
+        VisionStream::connect(&endpoint, token.clone())
+            .and_then(link::hello)
+            .then(
+                move |result| -> Box<
+                    dyn Future<Item = Loop<(VisionStream, LinkInfo), Duration>, Error = ()> + Send,
+                > {
+                    match result {
+                        Ok((stream, link_info)) => {
+                            log::info!(
+                                "[Vision] link version={} features={:#x} capabilities={:#x}",
+                                link_info.version,
+                                link_info.features,
+                                link_info.capabilities
+                            );
+                            if link_info.capabilities & link::CAPABILITY_WINDOWS == 0 {
+                                log::warn!("[Vision] the display cannot show windows");
+                            }
+                            Box::new(futures::future::ok(Loop::Break((stream, link_info))))
+                        }
+                        Err(err) => {
+                            log::warn!("[Vision] connect failed: {}, retrying in {:?}", err, delay);
+                            let next_delay = std::cmp::min(delay * 2, MAX_RECONNECT_DELAY);
+                            Box::new(
+                                Delay::new(Instant::now() + delay)
+                                    .map_err(|err| log::warn!("[Vision] timer err: {:?}", err))
+                                    .map(move |_| Loop::Continue(next_delay)),
+                            )
+                        }
+                    }
+                },
+            )
+    })
+}
+
+type FrameWriter = (
+    Receiver<LinkFrame>,
+    WriteHalf<VisionStream>,
+    VecDeque<Vec<u8>>,
+);
+
+// Write the replay and then the queued frames to one connection, skipping
+// those the replay already covers. Hands the queue back when the connection
+// is lost, or nothing once every sender is gone.
+fn write_frames(
+    frames_rx: Receiver<LinkFrame>,
+    w0: WriteHalf<VisionStream>,
+    replay: Vec<Vec<u8>>,
+    replayed: u64,
+    generation: u64,
+) -> impl Future<Item = Option<Receiver<LinkFrame>>, Error = ()> {
+    loop_fn(
+        (frames_rx, w0, VecDeque::from(replay)),
+        move |(rx, w, mut pending)| -> Box<
+            dyn Future<Item = Loop<Option<Receiver<LinkFrame>>, FrameWriter>, Error = ()> + Send,
+        > {
+            if let Some(frame) = pending.pop_front() {
+                return Box::new(
+                    tokio::io::write_all(w, frame).then(move |result| match result {
+                        Ok((w, _)) => Ok(Loop::Continue((rx, w, pending))),
+                        Err(err) => {
+                            log::warn!("[Vision] write err: {:?}", err);
+                            Ok(Loop::Break(Some(rx)))
+                        }
+                    }),
+                );
+            }
+            Box::new(rx.into_future().then(move |result| match result {
+                Ok((Some(LinkFrame::Data(Some(seq), _)), rx)) if seq <= replayed => {
+                    Ok(Loop::Continue((rx, w, pending)))
+                }
+                Ok((Some(LinkFrame::Data(_, frame)), rx)) => {
+                    pending.push_back(frame);
+                    Ok(Loop::Continue((rx, w, pending)))
+                }
+                Ok((Some(LinkFrame::Closed(closed)), rx)) => {
+                    if closed == generation {
+                        Ok(Loop::Break(Some(rx)))
+                    } else {
+                        Ok(Loop::Continue((rx, w, pending)))
+                    }
+                }
+                Ok((None, _)) | Err(_) => Ok(Loop::Break(None)),
+            }))
+        },
+    )
+}
+
+// Keep a link to vision up for as long as reflex runs. Clients do not notice
+// vision restarting: their frames wait in the queue while it is away, and
+// each new connection starts with a replay of the journal so that vision can
+// rebuild their windows.
+fn run_vision_link(
+    endpoint: VisionEndpoint,
+    token: Option<Arc<Vec<u8>>>,
+    frames_rx0: Receiver<LinkFrame>,
+    vision: VisionLink,
+) -> impl Future<Item = (), Error = ()> {
+    loop_fn((frames_rx0, 0), move |(frames_rx, generation)| {
+        let generation = generation + 1;
+        let vision = vision.clone();
+        connect_vision(endpoint.clone(), token.clone())
+            .and_then(move |(stream, link_info)| {
+                let (r0, w0) = stream.split();
+                let reader_vision = vision.clone();
+                tokio::spawn(read_vision(r0, vision.clone()).then(move |_| {
+                    log::info!("[Vision] link closed");
+                    reader_vision.closed(generation)
+                }));
+                let (replay, replayed) = vision.replay(link_info.features);
+                log::info!("[Vision] replaying {} frames", replay.len());
+                write_frames(frames_rx, w0, replay, replayed, generation)
+            })
+            .map(move |frames_rx| match frames_rx {
+                Some(frames_rx) => Loop::Continue((frames_rx, generation)),
+                None => Loop::Break(()),
+            })
+    })
+}
+
+// Events from vision name objects by link id. Besides the sender, only the
+// events of wl_display (error and delete_id) carry an object id, as their
+// first argument.
+fn to_local_ids(data: &mut [u8]) {
+    let sender_object_id = link::to_local_id(NativeEndian::read_u32(&data[0..]));
+    NativeEndian::write_u32(&mut data[0..], sender_object_id);
+    if sender_object_id == 1 && data.len() >= 12 {
+        let object_id = link::to_local_id(NativeEndian::read_u32(&data[8..]));
+        NativeEndian::write_u32(&mut data[8..], object_id);
+    }
+}
 
-    let mut session0 = Session {
+// A session with only the wl_display of the client.
+fn new_session(
+    vision: VisionLink,
+    global: Global,
+    client_id: u32,
+    resources: ObjectMap,
+    tx: Sender<Box<dyn Event + Send>>,
+    fds: FdQueue,
+) -> Session {
+    let mut session = Session {
+        client_id,
         wl_display: global.wl_display,
         wl_registry: global.wl_registry,
         wl_compositor: global.wl_compositor,
         wl_shm: global.wl_shm,
         wl_data_device_manager: global.wl_data_device_manager,
         xdg_wm_base: global.xdg_wm_base,
-        resources: HashMap::new(),
-        tx: tx0,
-        fds: Vec::new(),
-        unix_stream: w0,
+        resources,
+        tx,
+        fds,
+        callback_data: 0,
+        fatal_error: false,
+        vision,
     };
-
-    session0
+    session
         .resources
-        .insert(1, Resource::WlDisplay(session0.wl_display.clone()));
-    let input_session0: Box<dyn Future<Item = (), Error = ()> + Send> = Box::new(
-        reader0
-            .fold(session0, handle_client_input)
-            .map(|_| ())
-            //.map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Oops!")),
-            .then(|_| futures::future::ok(())),
+        .insert(1, Resource::WlDisplay(session.wl_display.clone()))
+        .expect("insert wl_display");
+    session
+}
+
+fn handle_client(vision: VisionLink, global: Global, fd: i32) {
+    let (tx0, rx0) = tokio::sync::mpsc::channel::<Box<dyn Event + Send>>(48000);
+    let fd_drop = Arc::new(FdDrop::new(fd));
+    let client_id = match vision.add_client(tx0.clone(), Hangup::new(fd, fd_drop.clone())) {
+        Some(client_id) => client_id,
+        None => {
+            log::warn!("[Session] too many clients");
+            return;
+        }
+    };
+    log::info!("[Session] client {} connected", client_id);
+    if let Some(capture) = vision.capture() {
+        capture.connected(client_id);
+    }
+
+    let tokio_registration = Arc::new(tokio::reactor::Registration::new());
+    tokio_registration
+        .register(&mio::unix::EventedFd(&fd))
+        .expect("register request fd");
+    let fds = match vision.capture() {
+        Some(capture) => FdQueue::recorded(capture.clone(), client_id),
+        None => FdQueue::default(),
+    };
+    let reader0 = RequestStream::new(fd, fd_drop.clone(), tokio_registration.clone(), fds.clone());
+    let resources0 = ObjectMap::new();
+    let writer0 = EventSink::new(
+        fd,
+        fd_drop.clone(),
+        tokio_registration.clone(),
+        resources0.versions(),
+        client_id,
+        vision.capture().cloned(),
     );
+    let output_session = rx0
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Oops!"))
+        .forward(writer0)
+        .map_err(move |err| log::warn!("[Session] client {} disconnected: {}", client_id, err))
+        .and_then(|_| Ok(()));
+    tokio::spawn(logger::with_client(client_id, output_session));
 
-    tokio::spawn(input_session0);
+    let session0 = new_session(vision.clone(), global, client_id, resources0, tx0, fds);
+    let input_session0: Box<dyn Future<Item = (), Error = ()> + Send> = Box::new(
+        vision
+            .connect_client(client_id)
+            .and_then(|_| {
+                loop_fn((reader0, session0), |(reader, session)| {
+                    reader.into_future().then(
+                        |result| -> Box<
+                            dyn Future<Item = Loop<Session, (RequestStream, Session)>, Error = ()>
+                                + Send,
+                        > {
+                            match result {
+                                Ok((Some(request), reader)) => {
+                                    Box::new(handle_client_input(session, request).map(|session| {
+                                        if session.fatal_error {
+                                            log::warn!("[Session] disconnect after fatal error");
+                                            Loop::Break(session)
+                                        } else {
+                                            Loop::Continue((reader, session))
+                                        }
+                                    }))
+                                }
+                                Ok((None, _)) | Err(_) => {
+                                    Box::new(futures::future::ok(Loop::Break(session)))
+                                }
+                            }
+                        },
+                    )
+                })
+            })
+            .and_then(|session| {
+                if let Some(capture) = session.vision.capture() {
+                    capture.disconnected(session.client_id);
+                }
+                session.teardown()
+            }),
+    );
 
-    Box::new(futures::future::ok(()))
+    tokio::spawn(logger::with_client(client_id, input_session0));
 }
 
 fn main() {
-    let socket_path = "/tmp/temp.unix";
-    let _ = std::fs::remove_file(socket_path);
-
-    let global = Global {
-        wl_display: Arc::new(RwLock::new(WlDisplay {})),
-        wl_compositor: Arc::new(RwLock::new(WlCompositor {})),
-        wl_registry: Arc::new(RwLock::new(WlRegistry {})),
-        wl_shm: Arc::new(RwLock::new(WlShm {})),
-        wl_data_device_manager: Arc::new(RwLock::new(WlDataDeviceManager {})),
-        xdg_wm_base: Arc::new(RwLock::new(XdgWmBase {})),
+    let config = match config::Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, config::usage());
+            std::process::exit(2);
+        }
+    };
+    let log_filter = match logger::init(config.log_filter.clone(), config.log_file.as_deref()) {
+        Ok(log_filter) => log_filter,
+        Err(err) => {
+            eprintln!("failed to open the log file: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(path) = &config.replay {
+        std::process::exit(playback::run(path));
+    }
+    let global = Global::new(&config.globals);
+    let capture = match &config.record {
+        Some(path) => match Capture::create(path, &config.globals) {
+            Ok(capture) => Some(capture),
+            Err(err) => {
+                log::error!("[Main] failed to create the capture file: {}", err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
     };
 
-    let listener = ConnectionStream::bind(socket_path.to_string()).for_each(move |fd| {
-        let inner_global = global.clone();
-        UnixStream::connect("/mnt/c/Temp/reflex.unix")
-            .and_then(move |stream| handle_client(stream, inner_global, fd))
+    let (connection_stream, display_name) =
+        match ConnectionStream::bind(config.socket_name.as_deref()) {
+            Ok(bound) => bound,
+            Err(err) => {
+                log::error!("[Main] failed to create the wayland socket: {}", err);
+                std::process::exit(1);
+            }
+        };
+    if let Err(err) = connection_stream.remove_on_signal() {
+        log::warn!("[Main] failed to install signal handlers: {}", err);
+    }
+    println!("WAYLAND_DISPLAY={}", display_name);
+
+    if let Some(upstream) = config.proxy {
+        log::info!("[Proxy] passing clients through to {}", upstream.display());
+        let mut next_client_id = 1;
+        let proxy = connection_stream.for_each(move |fd| {
+            protocol::proxy::handle_client(fd, &upstream, next_client_id);
+            next_client_id += 1;
+            Ok(())
+        });
+        tokio::run(proxy.map_err(|err| log::error!("[Main] err {:?}", err)));
+        log::info!("Exit");
+        return;
+    }
+
+    // One link to vision carries every client.
+    log::info!("[Vision] endpoint {}", config.vision);
+    let (frames_tx, frames_rx) = tokio::sync::mpsc::channel::<LinkFrame>(48000);
+    let vision = VisionLink::new(
+        frames_tx,
+        capture,
+        Clock::System,
+        global.wl_registry.clone(),
+    );
+    let endpoint = config.vision.clone();
+    let token = config.vision_token.clone();
+    let log_level_file = config.log_level_file.clone();
+    let listener = futures::future::lazy(move || {
+        if let Some(path) = log_level_file {
+            tokio::spawn(logger::watch_level_file(path, log_filter));
+        }
+        tokio::spawn(run_vision_link(endpoint, token, frames_rx, vision.clone()));
+        connection_stream.for_each(move |fd| {
+            handle_client(vision.clone(), global.clone(), fd);
+            Ok(())
+        })
     });
 
-    tokio::run(listener.map_err(|_| ()));
-    println!("Exit");
+    tokio::run(listener.map_err(|err| log::error!("[Main] err {:?}", err)));
+    log::info!("Exit");
 }
@@ -0,0 +1,422 @@
+use crate::protocol::capture::{self, Clock, Entry, FdSnapshot, Record};
+use crate::protocol::control;
+use crate::protocol::event::Event;
+use crate::protocol::event_sink;
+use crate::protocol::fd_queue::FdQueue;
+use crate::protocol::link;
+use crate::protocol::object_map::ObjectMap;
+use crate::protocol::session::Session;
+use crate::protocol::vision_link::{LinkFrame, VisionLink};
+use crate::{handle_client_input, logger, new_session, route_vision_frame, Global};
+use byteorder::{ByteOrder, NativeEndian};
+use bytes::BytesMut;
+use futures::future::{loop_fn, Future, Loop};
+use futures::stream::Stream;
+use futures::sync::oneshot;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Receiver;
+
+// The size of the header of a frame from vision, see read_vision.
+const VISION_HEADER_SIZE: usize = 16;
+
+type Sent = (u32, usize, oneshot::Receiver<Vec<u8>>);
+type Step = Loop<Replay, (Replay, std::vec::IntoIter<Record>)>;
+
+// What a replay goes through the records with.
+struct Replay {
+    vision: VisionLink,
+    global: Global,
+    sessions: HashMap<u32, Session>,
+    // The number of connections of each client id so far.
+    connections: HashMap<u32, usize>,
+    // What was sent to each connection, by client id and connection number.
+    sent: Vec<Sent>,
+    next_file: usize,
+}
+
+// Replay a capture recorded with --record, with the globals it was recorded
+// with. The records go through in order and one at a time: the requests
+// through handle_client_input and the frames from vision through
+// route_vision_frame, while a stand-in for vision answers the syncs. What
+// the clients are sent is then checked against what they were sent when the
+// capture was recorded. The exit code is 0 when every byte is the same.
+//
+// The pixels of a pool are those it had when the client sent it, so that
+// what goes to vision may differ; only what goes to the clients is checked.
+pub fn run(path: &Path) -> i32 {
+    let records = match capture::load(path) {
+        Ok(records) => records,
+        Err(err) => {
+            log::error!("[Replay] failed to read the capture: {}", err);
+            return 1;
+        }
+    };
+    let global = match records.first() {
+        Some(Record {
+            entry: Entry::Globals(globals),
+            ..
+        }) => Global::new(globals),
+        _ => {
+            log::error!("[Replay] failed to read the capture: the globals are missing");
+            return 1;
+        }
+    };
+    let (times, expected) = recorded(&records);
+    log::info!("[Replay] {} records", records.len());
+
+    let (frames_tx, frames_rx) = tokio::sync::mpsc::channel::<LinkFrame>(48000);
+    let vision = VisionLink::new(
+        frames_tx,
+        None,
+        Clock::Replay(Arc::new(Mutex::new(times))),
+        global.wl_registry.clone(),
+    );
+    let mut runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            log::error!("[Replay] failed to start the runtime: {}", err);
+            return 1;
+        }
+    };
+    runtime.spawn(stand_in(frames_rx, vision.clone()));
+    let replay = Replay {
+        vision,
+        global,
+        sessions: HashMap::new(),
+        connections: HashMap::new(),
+        sent: Vec::new(),
+        next_file: 0,
+    };
+    let sent = runtime.block_on(replay_records(replay, records));
+    let _ = runtime.shutdown_now().wait();
+    match sent {
+        Ok(sent) => compare(&expected, &sent),
+        Err(()) => {
+            log::error!("[Replay] the replay failed");
+            1
+        }
+    }
+}
+
+// The times given to frame callbacks by client, and the bytes sent by
+// client id and connection number.
+type Recorded = (HashMap<u32, VecDeque<u32>>, HashMap<(u32, usize), Vec<u8>>);
+
+fn recorded(records: &[Record]) -> Recorded {
+    let mut times: HashMap<u32, VecDeque<u32>> = HashMap::new();
+    let mut expected: HashMap<(u32, usize), Vec<u8>> = HashMap::new();
+    let mut connections: HashMap<u32, usize> = HashMap::new();
+    for record in records {
+        let client_id = record.client_id;
+        match &record.entry {
+            Entry::Connected => *connections.entry(client_id).or_insert(0) += 1,
+            Entry::Time(time) => times.entry(client_id).or_default().push_back(*time),
+            Entry::Events(bytes) => {
+                let connection = connections.get(&client_id).cloned().unwrap_or(0);
+                expected
+                    .entry((client_id, connection))
+                    .or_default()
+                    .extend_from_slice(bytes);
+            }
+            _ => {}
+        }
+    }
+    (times, expected)
+}
+
+fn replay_records(
+    replay: Replay,
+    records: Vec<Record>,
+) -> impl Future<Item = HashMap<(u32, usize), Vec<u8>>, Error = ()> {
+    loop_fn(
+        (replay, records.into_iter()),
+        |(replay, mut records)| -> Box<dyn Future<Item = Step, Error = ()> + Send> {
+            match records.next() {
+                // Lazy, so that the record is handled under its client.
+                Some(record) => Box::new(
+                    logger::with_client(
+                        record.client_id,
+                        futures::future::lazy(move || replay_record(replay, record)),
+                    )
+                    .map(|replay| Loop::Continue((replay, records))),
+                ),
+                None => Box::new(futures::future::ok(Loop::Break(replay))),
+            }
+        },
+    )
+    .and_then(finish)
+}
+
+fn replay_record(
+    mut replay: Replay,
+    record: Record,
+) -> Box<dyn Future<Item = Replay, Error = ()> + Send> {
+    let client_id = record.client_id;
+    log::debug!("[Replay] record at {}us", record.time_us);
+    match record.entry {
+        Entry::Connected => {
+            let (tx, rx) = tokio::sync::mpsc::channel::<Box<dyn Event + Send>>(48000);
+            replay.vision.insert_client(client_id, tx.clone());
+            let connection = {
+                let connection = replay.connections.entry(client_id).or_insert(0);
+                *connection += 1;
+                *connection
+            };
+            let resources = ObjectMap::new();
+            let (sent_tx, sent_rx) = oneshot::channel();
+            tokio::spawn(logger::with_client(
+                client_id,
+                collect(rx, resources.versions(), sent_tx),
+            ));
+            replay.sent.push((client_id, connection, sent_rx));
+            let session = new_session(
+                replay.vision.clone(),
+                replay.global.clone(),
+                client_id,
+                resources,
+                tx,
+                FdQueue::default(),
+            );
+            replay.sessions.insert(client_id, session);
+            Box::new(replay.vision.connect_client(client_id).map(move |_| replay))
+        }
+        Entry::Disconnected => match replay.sessions.remove(&client_id) {
+            Some(session) => Box::new(session.teardown().map(move |_| replay)),
+            None => Box::new(futures::future::ok(replay)),
+        },
+        Entry::Fd(snapshot) => {
+            if let Some(session) = replay.sessions.get(&client_id) {
+                match recreate_fd(&snapshot, replay.next_file) {
+                    Ok(fd) => {
+                        if session.fds.push(fd).is_err() {
+                            log::warn!("[Replay] client {} holds too many fds", client_id);
+                        }
+                    }
+                    Err(err) => log::warn!("[Replay] fd not recreated: {}", err),
+                }
+                replay.next_file += 1;
+            }
+            Box::new(futures::future::ok(replay))
+        }
+        Entry::Request(request) => match replay.sessions.remove(&client_id) {
+            Some(session) => Box::new(handle_client_input(session, request).map(move |session| {
+                replay.sessions.insert(client_id, session);
+                replay
+            })),
+            None => {
+                log::warn!("[Replay] request of unknown client {}", client_id);
+                Box::new(futures::future::ok(replay))
+            }
+        },
+        Entry::Vision(frame) => {
+            if frame.len() < VISION_HEADER_SIZE || is_sync_done(&frame) {
+                return Box::new(futures::future::ok(replay));
+            }
+            let (header, body) = frame.split_at(VISION_HEADER_SIZE);
+            match route_vision_frame(&replay.vision, header, body) {
+                Some(frame_done) => Box::new(frame_done.map(move |_| replay)),
+                None => Box::new(futures::future::ok(replay)),
+            }
+        }
+        Entry::LinkUp { features } => {
+            let _ = replay.vision.replay(features);
+            Box::new(futures::future::ok(replay))
+        }
+        Entry::LinkDown => Box::new(replay.vision.closed(0).then(move |_| Ok(replay))),
+        Entry::Time(_) | Entry::Events(_) | Entry::Globals(_) => {
+            Box::new(futures::future::ok(replay))
+        }
+    }
+}
+
+// Tear down the clients still there when the capture ends, and wait for
+// what was sent to each of them.
+fn finish(mut replay: Replay) -> impl Future<Item = HashMap<(u32, usize), Vec<u8>>, Error = ()> {
+    let teardowns: Vec<_> = replay
+        .sessions
+        .drain()
+        .map(|(_, session)| session.teardown())
+        .collect();
+    let sent = std::mem::take(&mut replay.sent);
+    drop(replay);
+    futures::future::join_all(teardowns).and_then(move |_| {
+        futures::future::join_all(sent.into_iter().map(|(client_id, connection, rx)| {
+            rx.map(move |bytes| ((client_id, connection), bytes))
+                .map_err(|_| ())
+        }))
+        .map(|sent| sent.into_iter().collect())
+    })
+}
+
+// Encode the events of a connection as its EventSink would.
+fn collect(
+    rx: Receiver<Box<dyn Event + Send>>,
+    versions: crate::protocol::object_map::ObjectVersions,
+    sent: oneshot::Sender<Vec<u8>>,
+) -> impl Future<Item = (), Error = ()> {
+    rx.map_err(|_| ())
+        .fold(BytesMut::new(), move |mut bytes, event| {
+            if let Err(err) = event_sink::encode(&mut bytes, &versions, event.as_ref()) {
+                log::warn!("[Replay] encode err: {}", err);
+            }
+            Ok::<_, ()>(bytes)
+        })
+        .map(move |bytes| {
+            let _ = sent.send(bytes.to_vec());
+        })
+}
+
+// Stands in for vision: every sync is done at once, and the other frames
+// are dropped. The SYNC_DONE frames of the capture are skipped instead.
+fn stand_in(frames: Receiver<LinkFrame>, vision: VisionLink) -> impl Future<Item = (), Error = ()> {
+    frames.map_err(|_| ()).for_each(move |frame| {
+        if let LinkFrame::Data(_, frame) = frame {
+            if frame.len() >= link::REQUEST_HEADER_SIZE + 4
+                && NativeEndian::read_u32(&frame[0..]) == link::REQUEST_SYNC
+            {
+                let client_id = NativeEndian::read_u32(&frame[4..]);
+                let serial = NativeEndian::read_u32(&frame[link::REQUEST_HEADER_SIZE..]);
+                vision.sync_done(client_id, serial);
+            }
+        }
+        Ok(())
+    })
+}
+
+fn is_sync_done(frame: &[u8]) -> bool {
+    NativeEndian::read_u32(&frame[0..]) == link::RESPONSE_CONTROL
+        && NativeEndian::read_u32(&frame[8..]) == control::KIND_SYNC_DONE
+}
+
+// An fd like the one the client sent: an unlinked file with the contents
+// of the snapshot, or /dev/null.
+fn recreate_fd(snapshot: &FdSnapshot, n: usize) -> io::Result<RawFd> {
+    let contents = match snapshot {
+        FdSnapshot::File(contents) => contents,
+        FdSnapshot::Other => return Ok(File::open("/dev/null")?.into_raw_fd()),
+    };
+    let path = std::env::temp_dir().join(format!("reflex-replay-{}-{}", std::process::id(), n));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    file.write_all(contents)?;
+    Ok(file.into_raw_fd())
+}
+
+// Print how each connection went, and return the exit code.
+fn compare(
+    expected: &HashMap<(u32, usize), Vec<u8>>,
+    sent: &HashMap<(u32, usize), Vec<u8>>,
+) -> i32 {
+    let mut keys: Vec<&(u32, usize)> = expected.keys().chain(sent.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let mut code = 0;
+    for key in keys {
+        let (client_id, connection) = key;
+        let expected = expected.get(key).map(Vec::as_slice).unwrap_or(&[]);
+        let sent = sent.get(key).map(Vec::as_slice).unwrap_or(&[]);
+        let same = expected
+            .iter()
+            .zip(sent.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if same == expected.len() && same == sent.len() {
+            println!(
+                "client {} connection {}: {} bytes, same",
+                client_id,
+                connection,
+                sent.len()
+            );
+        } else {
+            println!(
+                "client {} connection {}: differs at byte {}, {} bytes recorded, {} sent",
+                client_id,
+                connection,
+                same,
+                expected.len(),
+                sent.len()
+            );
+            code = 1;
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::capture::Capture;
+    use crate::protocol::request::Request;
+    use crate::protocol::wayland::wl_registry;
+    use bytes::Bytes;
+
+    fn globals() -> Vec<(String, u32)> {
+        vec![("wl_compositor".to_owned(), 4), ("wl_shm".to_owned(), 1)]
+    }
+
+    // The globals a client binding its registry as wl_registry@2 is sent.
+    fn registry_events() -> Vec<u8> {
+        let mut bytes = BytesMut::new();
+        for (i, (interface, version)) in globals().into_iter().enumerate() {
+            wl_registry::events::Global {
+                sender_object_id: 2,
+                name: i as u32 + 1,
+                interface,
+                version,
+            }
+            .encode(&mut bytes)
+            .unwrap();
+        }
+        bytes.to_vec()
+    }
+
+    // A session of one client that gets its registry, as recorded if
+    // events were what reflex sent.
+    fn replay(name: &str, events: &[u8]) -> i32 {
+        let path = std::env::temp_dir().join(format!(
+            "reflex-playback-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let capture = Capture::create(&path, &globals()).unwrap();
+        capture.connected(1);
+        let mut args = vec![0; 4];
+        NativeEndian::write_u32(&mut args, 2);
+        capture.request(
+            1,
+            &Request {
+                sender_object_id: 1,
+                opcode: 1,
+                args: Bytes::from(args),
+            },
+        );
+        capture.events(1, events);
+        capture.disconnected(1);
+        drop(capture);
+        let code = run(&path);
+        std::fs::remove_file(&path).unwrap();
+        code
+    }
+
+    #[test]
+    fn same() {
+        assert_eq!(replay("same", &registry_events()), 0);
+    }
+
+    #[test]
+    fn differs() {
+        let mut events = registry_events();
+        let last = events.len() - 1;
+        events[last] ^= 1;
+        assert_eq!(replay("differs", &events), 1);
+    }
+}
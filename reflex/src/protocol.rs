@@ -1,11 +1,26 @@
+pub mod capture;
 pub mod connection_stream;
+pub mod control;
 pub mod event;
 pub mod event_sink;
 pub mod fd_drop;
+pub mod fd_queue;
+pub mod flow;
+pub mod link;
+pub mod object_map;
+pub mod pixels;
+pub mod proxy;
 pub mod raw_event;
+pub mod replay;
 pub mod request;
 pub mod request_stream;
 pub mod resource;
+#[allow(dead_code)]
+#[path = "../../shared/rle.rs"]
+pub mod rle;
 pub mod session;
+pub mod trace;
+pub mod vision_link;
+pub mod vision_stream;
 pub mod wayland;
 pub mod xdg_shell;
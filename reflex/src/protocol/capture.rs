@@ -0,0 +1,461 @@
+use super::pixels;
+use super::request::Request;
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// A capture file holds what came into reflex during a session, so that the
+// session can be replayed without its clients or vision, and what went out
+// to the clients, to check the replay against:
+//
+//   "UDCP", version: u32, then records of
+//   kind: u32, client id: u32, microseconds since the start: u64,
+//   payload size: u32, payload
+//
+// all little endian.
+const MAGIC: &[u8; 4] = b"UDCP";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 8;
+const RECORD_HEADER_SIZE: usize = 20;
+
+const KIND_CONNECTED: u32 = 1;
+const KIND_DISCONNECTED: u32 = 2;
+// An fd the client sent, see FdSnapshot.
+const KIND_FD: u32 = 3;
+// sender object id: u32, opcode: u32, arguments
+const KIND_REQUEST: u32 = 4;
+// A frame from vision as it came, header and body.
+const KIND_VISION: u32 = 5;
+// features: u32
+const KIND_LINK_UP: u32 = 6;
+const KIND_LINK_DOWN: u32 = 7;
+// The time given to frame callbacks, milliseconds: u32.
+const KIND_TIME: u32 = 8;
+// Encoded events as they were sent to the client.
+const KIND_EVENTS: u32 = 9;
+// The first record, the globals as advertised: for each,
+// version: u32, interface size: u32, interface
+const KIND_GLOBALS: u32 = 10;
+
+const FD_FILE: u32 = 0;
+const FD_OTHER: u32 = 1;
+
+// The contents of a regular file, such as an shm pool, as they were when it
+// was received. Pipes and sockets are not kept.
+#[derive(Debug)]
+pub enum FdSnapshot {
+    File(Vec<u8>),
+    Other,
+}
+
+#[derive(Debug)]
+pub enum Entry {
+    Connected,
+    Disconnected,
+    Fd(FdSnapshot),
+    Request(Request),
+    Vision(Vec<u8>),
+    LinkUp { features: u32 },
+    LinkDown,
+    Time(u32),
+    Events(Vec<u8>),
+    Globals(Vec<(String, u32)>),
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub time_us: u64,
+    // 0 for the records of the link.
+    pub client_id: u32,
+    pub entry: Entry,
+}
+
+// Writes the records of a session. Every record goes out with one write, so
+// that a capture cut short by a crash still reads up to the crash.
+#[derive(Clone)]
+pub struct Capture {
+    file: Arc<Mutex<File>>,
+    start: Instant,
+    failed: Arc<AtomicBool>,
+}
+
+impl Capture {
+    pub fn create(path: &Path, globals: &[(String, u32)]) -> io::Result<Capture> {
+        let mut file = File::create(path)?;
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(MAGIC);
+        LittleEndian::write_u32(&mut header[4..], VERSION);
+        file.write_all(&header)?;
+        let capture = Capture {
+            file: Arc::new(Mutex::new(file)),
+            start: Instant::now(),
+            failed: Arc::new(AtomicBool::new(false)),
+        };
+        let mut payload = Vec::new();
+        for (interface, version) in globals {
+            let mut header = [0; 8];
+            LittleEndian::write_u32(&mut header[0..], *version);
+            LittleEndian::write_u32(&mut header[4..], interface.len() as u32);
+            payload.extend_from_slice(&header);
+            payload.extend_from_slice(interface.as_bytes());
+        }
+        capture.write(KIND_GLOBALS, 0, &payload);
+        Ok(capture)
+    }
+
+    pub fn connected(&self, client_id: u32) {
+        self.write(KIND_CONNECTED, client_id, &[]);
+    }
+
+    pub fn disconnected(&self, client_id: u32) {
+        self.write(KIND_DISCONNECTED, client_id, &[]);
+    }
+
+    pub fn fd(&self, client_id: u32, fd: RawFd) {
+        let payload = match snapshot(fd) {
+            Ok(Some(contents)) => {
+                let mut payload = vec![0; 4];
+                LittleEndian::write_u32(&mut payload, FD_FILE);
+                payload.extend_from_slice(&contents);
+                payload
+            }
+            Ok(None) => {
+                let mut payload = vec![0; 4];
+                LittleEndian::write_u32(&mut payload, FD_OTHER);
+                payload
+            }
+            Err(err) => {
+                log::warn!("[Capture] fd {} not snapshotted: {}", fd, err);
+                let mut payload = vec![0; 4];
+                LittleEndian::write_u32(&mut payload, FD_OTHER);
+                payload
+            }
+        };
+        self.write(KIND_FD, client_id, &payload);
+    }
+
+    pub fn request(&self, client_id: u32, request: &Request) {
+        let mut payload = vec![0; 8];
+        LittleEndian::write_u32(&mut payload[0..], request.sender_object_id);
+        LittleEndian::write_u32(&mut payload[4..], u32::from(request.opcode));
+        payload.extend_from_slice(&request.args);
+        self.write(KIND_REQUEST, client_id, &payload);
+    }
+
+    pub fn vision(&self, client_id: u32, header: &[u8], body: &[u8]) {
+        let mut payload = Vec::with_capacity(header.len() + body.len());
+        payload.extend_from_slice(header);
+        payload.extend_from_slice(body);
+        self.write(KIND_VISION, client_id, &payload);
+    }
+
+    pub fn link_up(&self, features: u32) {
+        let mut payload = [0; 4];
+        LittleEndian::write_u32(&mut payload, features);
+        self.write(KIND_LINK_UP, 0, &payload);
+    }
+
+    pub fn link_down(&self) {
+        self.write(KIND_LINK_DOWN, 0, &[]);
+    }
+
+    pub fn time(&self, client_id: u32, time: u32) {
+        let mut payload = [0; 4];
+        LittleEndian::write_u32(&mut payload, time);
+        self.write(KIND_TIME, client_id, &payload);
+    }
+
+    pub fn events(&self, client_id: u32, bytes: &[u8]) {
+        self.write(KIND_EVENTS, client_id, bytes);
+    }
+
+    fn write(&self, kind: u32, client_id: u32, payload: &[u8]) {
+        let elapsed = self.start.elapsed();
+        let time_us = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+        let mut record = vec![0; RECORD_HEADER_SIZE];
+        LittleEndian::write_u32(&mut record[0..], kind);
+        LittleEndian::write_u32(&mut record[4..], client_id);
+        LittleEndian::write_u64(&mut record[8..], time_us);
+        LittleEndian::write_u32(&mut record[16..], payload.len() as u32);
+        record.extend_from_slice(payload);
+        if let Err(err) = self.file.lock().unwrap().write_all(&record) {
+            if !self.failed.swap(true, Ordering::SeqCst) {
+                log::error!("[Capture] write err: {}", err);
+            }
+        }
+    }
+}
+
+// The contents of fd if it is a regular file, read without moving its
+// offset, which the client shares.
+fn snapshot(fd: RawFd) -> io::Result<Option<Vec<u8>>> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        return Ok(None);
+    }
+    let mut contents = vec![0; stat.st_size as usize];
+    pixels::read_at(fd, &mut contents, 0)?;
+    Ok(Some(contents))
+}
+
+// Every record of a capture file. A record cut short at the end, as left by
+// a crash, is dropped.
+pub fn load(path: &Path) -> io::Result<Vec<Record>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+        return Err(invalid("not a capture file".to_owned()));
+    }
+    let version = LittleEndian::read_u32(&data[4..]);
+    if version != VERSION {
+        return Err(invalid(format!("unsupported capture version {}", version)));
+    }
+
+    let mut records = Vec::new();
+    let mut rest = &data[HEADER_SIZE..];
+    while rest.len() >= RECORD_HEADER_SIZE {
+        let kind = LittleEndian::read_u32(&rest[0..]);
+        let client_id = LittleEndian::read_u32(&rest[4..]);
+        let time_us = LittleEndian::read_u64(&rest[8..]);
+        let size = LittleEndian::read_u32(&rest[16..]) as usize;
+        if rest.len() - RECORD_HEADER_SIZE < size {
+            log::warn!("[Capture] the last record is cut short");
+            break;
+        }
+        let payload = &rest[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + size];
+        rest = &rest[RECORD_HEADER_SIZE + size..];
+        let entry = match kind {
+            KIND_CONNECTED => Entry::Connected,
+            KIND_DISCONNECTED => Entry::Disconnected,
+            KIND_FD if size >= 4 => match LittleEndian::read_u32(payload) {
+                FD_FILE => Entry::Fd(FdSnapshot::File(payload[4..].to_vec())),
+                _ => Entry::Fd(FdSnapshot::Other),
+            },
+            KIND_REQUEST if size >= 8 => Entry::Request(Request {
+                sender_object_id: LittleEndian::read_u32(&payload[0..]),
+                opcode: LittleEndian::read_u32(&payload[4..]) as u16,
+                args: Bytes::from(&payload[8..]),
+            }),
+            KIND_VISION => Entry::Vision(payload.to_vec()),
+            KIND_LINK_UP if size >= 4 => Entry::LinkUp {
+                features: LittleEndian::read_u32(payload),
+            },
+            KIND_LINK_DOWN => Entry::LinkDown,
+            KIND_TIME if size >= 4 => Entry::Time(LittleEndian::read_u32(payload)),
+            KIND_EVENTS => Entry::Events(payload.to_vec()),
+            KIND_GLOBALS => Entry::Globals(read_globals(payload)?),
+            _ => {
+                return Err(invalid(format!(
+                    "invalid record kind={} size={} at {}us",
+                    kind, size, time_us
+                )))
+            }
+        };
+        records.push(Record {
+            time_us,
+            client_id,
+            entry,
+        });
+    }
+    Ok(records)
+}
+
+fn read_globals(mut payload: &[u8]) -> io::Result<Vec<(String, u32)>> {
+    let mut globals = Vec::new();
+    while !payload.is_empty() {
+        if payload.len() < 8 {
+            return Err(invalid("invalid globals".to_owned()));
+        }
+        let version = LittleEndian::read_u32(&payload[0..]);
+        let size = LittleEndian::read_u32(&payload[4..]) as usize;
+        if payload.len() - 8 < size {
+            return Err(invalid("invalid globals".to_owned()));
+        }
+        let interface = String::from_utf8_lossy(&payload[8..8 + size]).into_owned();
+        globals.push((interface, version));
+        payload = &payload[8 + size..];
+    }
+    Ok(globals)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Where the time carried by frame callbacks comes from: the system clock,
+// or when replaying, the times of the capture, by client.
+#[derive(Clone)]
+pub enum Clock {
+    System,
+    Replay(Arc<Mutex<HashMap<u32, VecDeque<u32>>>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "reflex-capture-test-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn globals() -> Vec<(String, u32)> {
+        vec![("wl_compositor".to_owned(), 4), ("wl_shm".to_owned(), 1)]
+    }
+
+    fn write_session(path: &Path) {
+        let capture = Capture::create(path, &globals()).unwrap();
+        capture.link_up(3);
+        capture.connected(1);
+        let pool_path = path.with_extension("pool");
+        let mut pool = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&pool_path)
+            .unwrap();
+        pool.write_all(b"pixels").unwrap();
+        std::fs::remove_file(&pool_path).unwrap();
+        capture.fd(1, pool.as_raw_fd());
+        capture.fd(1, File::open("/dev/null").unwrap().as_raw_fd());
+        capture.request(
+            1,
+            &Request {
+                sender_object_id: 1,
+                opcode: 1,
+                args: Bytes::from(&[2, 0, 0, 0][..]),
+            },
+        );
+        capture.vision(1, &[1, 2], &[3]);
+        capture.time(1, 16);
+        capture.events(1, &[4, 5, 6]);
+        capture.disconnected(1);
+        capture.link_down();
+    }
+
+    fn check_session(records: &[Record]) {
+        let entries: Vec<&Entry> = records.iter().map(|record| &record.entry).collect();
+        match entries[0] {
+            Entry::Globals(loaded) => assert_eq!(loaded, &globals()),
+            entry => panic!("{:?}", entry),
+        }
+        match entries[1] {
+            Entry::LinkUp { features: 3 } => {}
+            entry => panic!("{:?}", entry),
+        }
+        match entries[2] {
+            Entry::Connected => {}
+            entry => panic!("{:?}", entry),
+        }
+        match entries[3] {
+            Entry::Fd(FdSnapshot::File(contents)) => assert_eq!(contents, b"pixels"),
+            entry => panic!("{:?}", entry),
+        }
+        match entries[4] {
+            Entry::Fd(FdSnapshot::Other) => {}
+            entry => panic!("{:?}", entry),
+        }
+        match entries[5] {
+            Entry::Request(request) => {
+                assert_eq!(request.sender_object_id, 1);
+                assert_eq!(request.opcode, 1);
+                assert_eq!(&request.args[..], &[2, 0, 0, 0]);
+            }
+            entry => panic!("{:?}", entry),
+        }
+        match entries[6] {
+            Entry::Vision(frame) => assert_eq!(frame, &[1, 2, 3]),
+            entry => panic!("{:?}", entry),
+        }
+        match entries[7] {
+            Entry::Time(16) => {}
+            entry => panic!("{:?}", entry),
+        }
+        match entries[8] {
+            Entry::Events(bytes) => assert_eq!(bytes, &[4, 5, 6]),
+            entry => panic!("{:?}", entry),
+        }
+        match entries[9] {
+            Entry::Disconnected => {}
+            entry => panic!("{:?}", entry),
+        }
+        for (i, record) in records.iter().enumerate().take(10) {
+            assert_eq!(record.client_id, if i < 2 { 0 } else { 1 });
+        }
+        assert!(records
+            .windows(2)
+            .all(|pair| pair[0].time_us <= pair[1].time_us));
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+        write_session(&path);
+        let records = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 11);
+        check_session(&records);
+        match records[10].entry {
+            Entry::LinkDown => {}
+            ref entry => panic!("{:?}", entry),
+        }
+    }
+
+    #[test]
+    fn cut_short() {
+        let path = temp_path("cut-short");
+        write_session(&path);
+        // Events of 100 bytes of which 10 were written before a crash.
+        let mut record = vec![0; RECORD_HEADER_SIZE + 10];
+        LittleEndian::write_u32(&mut record[0..], KIND_EVENTS);
+        LittleEndian::write_u32(&mut record[4..], 1);
+        LittleEndian::write_u32(&mut record[16..], 100);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&record)
+            .unwrap();
+        let records = load(&path).unwrap();
+        assert_eq!(records.len(), 11);
+        check_session(&records);
+
+        // The link down record without its last byte.
+        let size = std::fs::metadata(&path).unwrap().len() - record.len() as u64;
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(size - 1)
+            .unwrap();
+        let records = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 10);
+        check_session(&records);
+    }
+
+    #[test]
+    fn not_a_capture() {
+        let path = temp_path("not-a-capture");
+        std::fs::write(&path, b"UDCX\x01\0\0\0").unwrap();
+        let result = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
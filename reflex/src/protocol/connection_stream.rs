@@ -1,46 +1,182 @@
 use futures::stream::Stream;
+use nix::fcntl::{flock, open, FlockArg, OFlag};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 use nix::sys::socket::*;
+use nix::sys::stat::Mode;
+use nix::unistd::close;
+use std::ffi::CString;
 use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicPtr, Ordering};
 use tokio::prelude::Async;
 
+// libwayland gives up after wayland-31 as well.
+const MAX_DISPLAYS: u32 = 32;
+
+// Paths removed by the signal handler. They are leaked CStrings because the
+// handler may only call async-signal-safe functions.
+static SIGNAL_SOCKET_PATH: AtomicPtr<libc::c_char> = AtomicPtr::new(std::ptr::null_mut());
+static SIGNAL_LOCK_PATH: AtomicPtr<libc::c_char> = AtomicPtr::new(std::ptr::null_mut());
+
 pub struct ConnectionStream {
     fd: RawFd,
     tokio_registration: tokio::reactor::Registration,
+    socket_lock: SocketLock,
+}
+
+// The socket file and its lock file, which are removed when dropped.
+struct SocketLock {
+    socket_path: PathBuf,
+    lock_path: PathBuf,
+    lock_fd: RawFd,
+}
+
+impl Drop for SocketLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+        let _ = fs::remove_file(&self.lock_path);
+        let _ = close(self.lock_fd);
+    }
+}
+
+fn nix_error(err: nix::Error) -> io::Error {
+    match err.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        None => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
+
+// Take the lock like libwayland does, so that two compositors never share a
+// socket name.
+fn lock(socket_path: &Path) -> io::Result<SocketLock> {
+    let mut lock_path = socket_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    let lock_fd = open(
+        &lock_path,
+        OFlag::O_CREAT | OFlag::O_CLOEXEC | OFlag::O_RDWR,
+        Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IRGRP | Mode::S_IWGRP,
+    )
+    .map_err(nix_error)?;
+    if flock(lock_fd, FlockArg::LockExclusiveNonblock).is_err() {
+        let _ = close(lock_fd);
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is held by another compositor", lock_path.display()),
+        ));
+    }
+    let socket_lock = SocketLock {
+        socket_path: socket_path.to_owned(),
+        lock_path,
+        lock_fd,
+    };
+
+    // The lock is ours, so a socket file left behind is stale.
+    match fs::remove_file(socket_path) {
+        Ok(()) => Ok(socket_lock),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(socket_lock),
+        Err(err) => Err(err),
+    }
+}
+
+extern "C" fn remove_socket_and_exit(signal: libc::c_int) {
+    for path in &[&SIGNAL_SOCKET_PATH, &SIGNAL_LOCK_PATH] {
+        let path = path.load(Ordering::SeqCst);
+        if !path.is_null() {
+            unsafe {
+                libc::unlink(path);
+            }
+        }
+    }
+    unsafe {
+        libc::_exit(128 + signal);
+    }
 }
 
 impl ConnectionStream {
-    pub fn bind(path: String) -> ConnectionStream {
-        let unix_addr = UnixAddr::new(path.as_bytes()).unwrap();
-        println!("unix_addr={:?}", unix_addr);
+    // Listen on $XDG_RUNTIME_DIR/<name>, or on the first free wayland-N when
+    // no name is given. The name to put in WAYLAND_DISPLAY is returned too.
+    pub fn bind(name: Option<&str>) -> io::Result<(ConnectionStream, String)> {
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+
+        if let Some(name) = name {
+            let connection_stream = ConnectionStream::bind_path(runtime_dir.join(name))?;
+            return Ok((connection_stream, name.to_owned()));
+        }
+        for n in 0..MAX_DISPLAYS {
+            let name = format!("wayland-{}", n);
+            match ConnectionStream::bind_path(runtime_dir.join(&name)) {
+                Ok(connection_stream) => return Ok((connection_stream, name)),
+                Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("no free socket in {}", runtime_dir.display()),
+        ))
+    }
+
+    fn bind_path(path: PathBuf) -> io::Result<ConnectionStream> {
+        let socket_lock = lock(&path)?;
+        let unix_addr = UnixAddr::new(&path).map_err(nix_error)?;
+        log::debug!("unix_addr={:?}", unix_addr);
         let sock_addr = SockAddr::Unix(unix_addr);
-        println!("sa={:?} f={:?}", sock_addr, sock_addr.family());
 
         let fd = socket(
             AddressFamily::Unix,
             SockType::Stream,
-            SockFlag::SOCK_NONBLOCK,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
             None,
         )
-        .unwrap();
-
-        bind(fd, &sock_addr).expect("bind");
-        listen(fd, 1024).expect("listen");
+        .map_err(nix_error)?;
+        if let Err(err) = bind(fd, &sock_addr).and_then(|()| listen(fd, 1024)) {
+            let _ = close(fd);
+            return Err(nix_error(err));
+        }
 
-        let mut perms = fs::metadata(&path).unwrap().permissions();
-        perms.set_mode(0700);
-        fs::set_permissions(&path, perms).unwrap();
+        let mut perms = fs::metadata(&path)?.permissions();
+        perms.set_mode(0o700);
+        fs::set_permissions(&path, perms)?;
 
         let tokio_registration = tokio::reactor::Registration::new();
-        tokio_registration
-            .register(&mio::unix::EventedFd(&fd))
-            .expect("register request fd");
+        tokio_registration.register(&mio::unix::EventedFd(&fd))?;
 
-        ConnectionStream {
+        Ok(ConnectionStream {
             fd,
             tokio_registration,
+            socket_lock,
+        })
+    }
+
+    // Drop does not run when the process is killed, so remove the socket and
+    // the lock file from a signal handler as well.
+    pub fn remove_on_signal(&self) -> io::Result<()> {
+        for (path, target) in &[
+            (&self.socket_lock.socket_path, &SIGNAL_SOCKET_PATH),
+            (&self.socket_lock.lock_path, &SIGNAL_LOCK_PATH),
+        ] {
+            let path = CString::new(path.as_os_str().as_bytes())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            target.store(path.into_raw(), Ordering::SeqCst);
+        }
+
+        let action = SigAction::new(
+            SigHandler::Handler(remove_socket_and_exit),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        for signal in &[Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+            unsafe { sigaction(*signal, &action) }.map_err(nix_error)?;
         }
+        Ok(())
     }
 }
 
@@ -49,38 +185,40 @@ impl Stream for ConnectionStream {
     type Error = std::io::Error;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        println!("[Connection] poll");
+        log::trace!("[Connection] poll");
 
         match self.tokio_registration.poll_read_ready() {
             Ok(Async::Ready(ready)) if ready.is_readable() => {
-                println!("[Connection] read ready");
+                log::trace!("[Connection] read ready");
                 ()
             }
             Ok(Async::Ready(_)) => {
-                println!("[Connection] read ready ERROR");
+                log::trace!("[Connection] read ready ERROR");
                 //return Ok(Async::NotReady);
             }
             Ok(Async::NotReady) => {
-                println!("[Connection] read not ready");
+                log::trace!("[Connection] read not ready");
                 //return Ok(Async::NotReady);
             }
             Err(err) => {
-                println!("[Connection] err {:?}", err);
+                log::warn!("[Connection] err {:?}", err);
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
             }
         }
 
-        match accept(self.fd) {
+        // Client sockets are polled on the shared runtime and must never
+        // block it.
+        match accept4(self.fd, SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC) {
             Ok(client_fd) => {
-                println!("[Connection] ready");
+                log::trace!("[Connection] ready");
                 return Ok(Async::Ready(Some(client_fd)));
             }
             Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {
-                println!("[Connection] EAGAIN not ready");
+                log::trace!("[Connection] EAGAIN not ready");
                 return Ok(Async::NotReady);
             }
             Err(err) => {
-                println!("[Connection] err2: {:?}", err);
+                log::warn!("[Connection] err2: {:?}", err);
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
             }
         };
@@ -0,0 +1,732 @@
+use super::event::Event;
+use super::link::{to_local_id, LOCAL_ID_BITS, RESPONSE_CONTROL};
+use super::wayland::wl_keyboard;
+use super::wayland::wl_pointer;
+use super::wayland::wl_registry::WlRegistry;
+use super::xdg_shell::xdg_surface;
+use super::xdg_shell::xdg_toplevel;
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+
+// Control messages tell reflex what happened on the host display, as opposed
+// to Wayland events that vision relays for a client. Framing after the
+// response type: kind, payload length, payload, all native-endian.
+pub const CONTROL_HEADER_SIZE: usize = 8;
+pub const MAX_CONTROL_PAYLOAD: usize = 64 * 1024;
+
+pub const KIND_WINDOW_CLOSED: u32 = 0;
+pub const KIND_WINDOW_RESIZED: u32 = 1;
+pub const KIND_FOCUS_CHANGED: u32 = 2;
+pub const KIND_POINTER_MOTION: u32 = 3;
+pub const KIND_POINTER_BUTTON: u32 = 4;
+pub const KIND_KEY: u32 = 5;
+pub const KIND_OUTPUT_CHANGED: u32 = 6;
+pub const KIND_CLIPBOARD_CHANGED: u32 = 7;
+pub const KIND_FRAME_DONE: u32 = 8;
+pub const KIND_SYNC_DONE: u32 = 9;
+pub const KIND_KEYBOARD_FOCUS: u32 = 10;
+pub const KIND_POINTER_FOCUS: u32 = 11;
+
+// Object ids are link ids, see link::to_link_id.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlMessage {
+    // The close button of the host window was pressed.
+    WindowClosed {
+        toplevel_id: u32,
+    },
+    WindowResized {
+        xdg_surface_id: u32,
+        toplevel_id: u32,
+        width: i32,
+        height: i32,
+    },
+    FocusChanged {
+        xdg_surface_id: u32,
+        toplevel_id: u32,
+        focused: bool,
+    },
+    // surface_x and surface_y are wl_fixed (24.8).
+    PointerMotion {
+        pointer_id: u32,
+        time: u32,
+        surface_x: u32,
+        surface_y: u32,
+    },
+    PointerButton {
+        pointer_id: u32,
+        time: u32,
+        button: u32,
+        state: u32,
+    },
+    Key {
+        keyboard_id: u32,
+        time: u32,
+        key: u32,
+        state: u32,
+    },
+    // A monitor of the host was plugged, unplugged or changed its mode.
+    // refresh is in mHz.
+    OutputChanged {
+        output_id: u32,
+        connected: bool,
+        width: i32,
+        height: i32,
+        refresh: i32,
+        scale: i32,
+    },
+    ClipboardChanged {
+        mime_types: Vec<String>,
+    },
+    // The pixels last sent for the surface are on screen; gives back its
+    // credit, see flow.rs in reflex.
+    FrameDone {
+        surface_id: u32,
+    },
+    // The answer to a SYNC request: the frames of the client sent before it
+    // have been handled, and their events come before this.
+    SyncDone {
+        serial: u32,
+    },
+    // The wl_keyboard has the keyboard focus on surface_id from now on, or
+    // on no surface when it is 0.
+    KeyboardFocus {
+        keyboard_id: u32,
+        surface_id: u32,
+    },
+    // The same for the wl_pointer, which is at surface_x and surface_y
+    // (wl_fixed) on the surface.
+    PointerFocus {
+        pointer_id: u32,
+        surface_id: u32,
+        surface_x: u32,
+        surface_y: u32,
+    },
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl ControlMessage {
+    pub fn kind(&self) -> u32 {
+        match self {
+            ControlMessage::WindowClosed { .. } => KIND_WINDOW_CLOSED,
+            ControlMessage::WindowResized { .. } => KIND_WINDOW_RESIZED,
+            ControlMessage::FocusChanged { .. } => KIND_FOCUS_CHANGED,
+            ControlMessage::PointerMotion { .. } => KIND_POINTER_MOTION,
+            ControlMessage::PointerButton { .. } => KIND_POINTER_BUTTON,
+            ControlMessage::Key { .. } => KIND_KEY,
+            ControlMessage::OutputChanged { .. } => KIND_OUTPUT_CHANGED,
+            ControlMessage::ClipboardChanged { .. } => KIND_CLIPBOARD_CHANGED,
+            ControlMessage::FrameDone { .. } => KIND_FRAME_DONE,
+            ControlMessage::SyncDone { .. } => KIND_SYNC_DONE,
+            ControlMessage::KeyboardFocus { .. } => KIND_KEYBOARD_FOCUS,
+            ControlMessage::PointerFocus { .. } => KIND_POINTER_FOCUS,
+        }
+    }
+
+    // The whole frame including the response type, as vision writes it.
+    #[allow(dead_code)]
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = self.encode_payload();
+        let mut dst = Vec::with_capacity(4 + CONTROL_HEADER_SIZE + payload.len());
+        dst.write_u32::<NativeEndian>(RESPONSE_CONTROL).unwrap();
+        dst.write_u32::<NativeEndian>(self.kind()).unwrap();
+        dst.write_u32::<NativeEndian>(payload.len() as u32).unwrap();
+        dst.extend_from_slice(&payload);
+        dst
+    }
+
+    fn encode_payload(&self) -> Vec<u8> {
+        let mut dst = Vec::new();
+        let words: Vec<u32> = match self {
+            ControlMessage::WindowClosed { toplevel_id } => vec![*toplevel_id],
+            ControlMessage::WindowResized {
+                xdg_surface_id,
+                toplevel_id,
+                width,
+                height,
+            } => vec![*xdg_surface_id, *toplevel_id, *width as u32, *height as u32],
+            ControlMessage::FocusChanged {
+                xdg_surface_id,
+                toplevel_id,
+                focused,
+            } => vec![*xdg_surface_id, *toplevel_id, *focused as u32],
+            ControlMessage::PointerMotion {
+                pointer_id,
+                time,
+                surface_x,
+                surface_y,
+            } => vec![*pointer_id, *time, *surface_x, *surface_y],
+            ControlMessage::PointerButton {
+                pointer_id,
+                time,
+                button,
+                state,
+            } => vec![*pointer_id, *time, *button, *state],
+            ControlMessage::Key {
+                keyboard_id,
+                time,
+                key,
+                state,
+            } => vec![*keyboard_id, *time, *key, *state],
+            ControlMessage::OutputChanged {
+                output_id,
+                connected,
+                width,
+                height,
+                refresh,
+                scale,
+            } => vec![
+                *output_id,
+                *connected as u32,
+                *width as u32,
+                *height as u32,
+                *refresh as u32,
+                *scale as u32,
+            ],
+            ControlMessage::FrameDone { surface_id } => vec![*surface_id],
+            ControlMessage::SyncDone { serial } => vec![*serial],
+            ControlMessage::KeyboardFocus {
+                keyboard_id,
+                surface_id,
+            } => vec![*keyboard_id, *surface_id],
+            ControlMessage::PointerFocus {
+                pointer_id,
+                surface_id,
+                surface_x,
+                surface_y,
+            } => vec![*pointer_id, *surface_id, *surface_x, *surface_y],
+            ControlMessage::ClipboardChanged { mime_types } => {
+                dst.write_u32::<NativeEndian>(mime_types.len() as u32)
+                    .unwrap();
+                for mime_type in mime_types {
+                    dst.write_u32::<NativeEndian>(mime_type.len() as u32)
+                        .unwrap();
+                    dst.extend_from_slice(mime_type.as_bytes());
+                }
+                return dst;
+            }
+        };
+        for word in words {
+            dst.write_u32::<NativeEndian>(word).unwrap();
+        }
+        dst
+    }
+
+    pub fn decode(kind: u32, payload: &[u8]) -> io::Result<ControlMessage> {
+        let mut cursor = Cursor::new(payload);
+        let message = match kind {
+            KIND_WINDOW_CLOSED => ControlMessage::WindowClosed {
+                toplevel_id: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_WINDOW_RESIZED => ControlMessage::WindowResized {
+                xdg_surface_id: cursor.read_u32::<NativeEndian>()?,
+                toplevel_id: cursor.read_u32::<NativeEndian>()?,
+                width: cursor.read_i32::<NativeEndian>()?,
+                height: cursor.read_i32::<NativeEndian>()?,
+            },
+            KIND_FOCUS_CHANGED => ControlMessage::FocusChanged {
+                xdg_surface_id: cursor.read_u32::<NativeEndian>()?,
+                toplevel_id: cursor.read_u32::<NativeEndian>()?,
+                focused: cursor.read_u32::<NativeEndian>()? != 0,
+            },
+            KIND_POINTER_MOTION => ControlMessage::PointerMotion {
+                pointer_id: cursor.read_u32::<NativeEndian>()?,
+                time: cursor.read_u32::<NativeEndian>()?,
+                surface_x: cursor.read_u32::<NativeEndian>()?,
+                surface_y: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_POINTER_BUTTON => ControlMessage::PointerButton {
+                pointer_id: cursor.read_u32::<NativeEndian>()?,
+                time: cursor.read_u32::<NativeEndian>()?,
+                button: cursor.read_u32::<NativeEndian>()?,
+                state: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_KEY => ControlMessage::Key {
+                keyboard_id: cursor.read_u32::<NativeEndian>()?,
+                time: cursor.read_u32::<NativeEndian>()?,
+                key: cursor.read_u32::<NativeEndian>()?,
+                state: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_OUTPUT_CHANGED => ControlMessage::OutputChanged {
+                output_id: cursor.read_u32::<NativeEndian>()?,
+                connected: cursor.read_u32::<NativeEndian>()? != 0,
+                width: cursor.read_i32::<NativeEndian>()?,
+                height: cursor.read_i32::<NativeEndian>()?,
+                refresh: cursor.read_i32::<NativeEndian>()?,
+                scale: cursor.read_i32::<NativeEndian>()?,
+            },
+            KIND_FRAME_DONE => ControlMessage::FrameDone {
+                surface_id: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_SYNC_DONE => ControlMessage::SyncDone {
+                serial: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_KEYBOARD_FOCUS => ControlMessage::KeyboardFocus {
+                keyboard_id: cursor.read_u32::<NativeEndian>()?,
+                surface_id: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_POINTER_FOCUS => ControlMessage::PointerFocus {
+                pointer_id: cursor.read_u32::<NativeEndian>()?,
+                surface_id: cursor.read_u32::<NativeEndian>()?,
+                surface_x: cursor.read_u32::<NativeEndian>()?,
+                surface_y: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_CLIPBOARD_CHANGED => {
+                let count = cursor.read_u32::<NativeEndian>()? as usize;
+                let mut mime_types = Vec::new();
+                for _ in 0..count {
+                    let len = cursor.read_u32::<NativeEndian>()? as usize;
+                    if len > payload.len() {
+                        return Err(invalid_data(format!("mime type of {} bytes", len)));
+                    }
+                    let mut buf = vec![0; len];
+                    cursor.read_exact(&mut buf)?;
+                    let mime_type = String::from_utf8(buf)
+                        .map_err(|err| invalid_data(format!("mime type: {}", err)))?;
+                    mime_types.push(mime_type);
+                }
+                ControlMessage::ClipboardChanged { mime_types }
+            }
+            _ => return Err(invalid_data(format!("unknown control kind {}", kind))),
+        };
+        if cursor.position() as usize != payload.len() {
+            return Err(invalid_data(format!(
+                "control kind {} has {} trailing bytes",
+                kind,
+                payload.len() - cursor.position() as usize
+            )));
+        }
+        Ok(message)
+    }
+}
+
+static NEXT_SERIAL: AtomicU32 = AtomicU32::new(1);
+
+pub fn next_serial() -> u32 {
+    NEXT_SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+struct WindowState {
+    width: i32,
+    height: i32,
+    activated: bool,
+}
+
+// What reflex remembers about the host windows, so that a configure carries
+// both the latest size and the latest focus, and which surface each
+// keyboard and pointer is on, so that it leaves one before entering the
+// next. Everything is keyed by link id.
+pub struct ControlState {
+    windows: HashMap<u32, WindowState>,
+    // Keyboards are in from their first focus change on, when they get the
+    // keymap; 0 for no surface.
+    keyboard_focus: HashMap<u32, u32>,
+    pointer_focus: HashMap<u32, u32>,
+    wl_registry: Arc<RwLock<WlRegistry>>,
+}
+
+impl ControlState {
+    pub fn new(wl_registry: Arc<RwLock<WlRegistry>>) -> ControlState {
+        ControlState {
+            windows: HashMap::new(),
+            keyboard_focus: HashMap::new(),
+            pointer_focus: HashMap::new(),
+            wl_registry,
+        }
+    }
+
+    // Forget the windows, keyboards and pointers of a client that is gone,
+    // as its id goes to the next client.
+    pub fn remove_client(&mut self, client_id: u32) {
+        let other = |link_id: &u32| *link_id >> LOCAL_ID_BITS != client_id;
+        self.windows.retain(|id, _| other(id));
+        self.keyboard_focus.retain(|id, _| other(id));
+        self.pointer_focus.retain(|id, _| other(id));
+    }
+
+    // Forget everything on a new connection, whose host windows start over.
+    pub fn reset(&mut self) {
+        self.windows.clear();
+        self.keyboard_focus.clear();
+        self.pointer_focus.clear();
+    }
+
+    // Translate a control message into the events the client it is tagged
+    // with has to see.
+    pub fn handle(&mut self, message: ControlMessage) -> Vec<Box<dyn Event + Send>> {
+        match message {
+            ControlMessage::WindowClosed { toplevel_id } => {
+                self.windows.remove(&toplevel_id);
+                vec![Box::new(xdg_toplevel::events::Close {
+                    sender_object_id: to_local_id(toplevel_id),
+                })]
+            }
+            ControlMessage::WindowResized {
+                xdg_surface_id,
+                toplevel_id,
+                width,
+                height,
+            } => {
+                let window = self.window(toplevel_id);
+                window.width = width;
+                window.height = height;
+                self.configure(xdg_surface_id, toplevel_id)
+            }
+            ControlMessage::FocusChanged {
+                xdg_surface_id,
+                toplevel_id,
+                focused,
+            } => {
+                self.window(toplevel_id).activated = focused;
+                self.configure(xdg_surface_id, toplevel_id)
+            }
+            ControlMessage::PointerMotion {
+                pointer_id,
+                time,
+                surface_x,
+                surface_y,
+            } => vec![
+                Box::new(wl_pointer::events::Motion {
+                    sender_object_id: to_local_id(pointer_id),
+                    time,
+                    surface_x,
+                    surface_y,
+                }),
+                Box::new(wl_pointer::events::Frame {
+                    sender_object_id: to_local_id(pointer_id),
+                }),
+            ],
+            ControlMessage::PointerButton {
+                pointer_id,
+                time,
+                button,
+                state,
+            } => vec![
+                Box::new(wl_pointer::events::Button {
+                    sender_object_id: to_local_id(pointer_id),
+                    serial: next_serial(),
+                    time,
+                    button,
+                    state,
+                }),
+                Box::new(wl_pointer::events::Frame {
+                    sender_object_id: to_local_id(pointer_id),
+                }),
+            ],
+            ControlMessage::Key {
+                keyboard_id,
+                time,
+                key,
+                state,
+            } => vec![Box::new(wl_keyboard::events::Key {
+                sender_object_id: to_local_id(keyboard_id),
+                serial: next_serial(),
+                time,
+                key,
+                state,
+            })],
+            ControlMessage::OutputChanged {
+                output_id,
+                connected,
+                width,
+                height,
+                refresh,
+                scale,
+            } => {
+                log::info!(
+                    "[Control] output {} connected={} {}x{}@{} scale={}",
+                    output_id,
+                    connected,
+                    width,
+                    height,
+                    refresh,
+                    scale
+                );
+                self.wl_registry
+                    .write()
+                    .unwrap()
+                    .output_changed(output_id, connected);
+                Vec::new()
+            }
+            ControlMessage::ClipboardChanged { mime_types } => {
+                // Nothing offers a wl_data_source yet; the selection stays
+                // with the host.
+                log::info!("[Control] clipboard changed: {:?}", mime_types);
+                Vec::new()
+            }
+            ControlMessage::KeyboardFocus {
+                keyboard_id,
+                surface_id,
+            } => self.keyboard_focus(keyboard_id, surface_id),
+            ControlMessage::PointerFocus {
+                pointer_id,
+                surface_id,
+                surface_x,
+                surface_y,
+            } => self.pointer_focus(pointer_id, surface_id, surface_x, surface_y),
+            // Handled by VisionLink::frame_done and VisionLink::sync_done.
+            ControlMessage::FrameDone { .. } | ControlMessage::SyncDone { .. } => Vec::new(),
+        }
+    }
+
+    fn keyboard_focus(&mut self, keyboard_id: u32, surface_id: u32) -> Vec<Box<dyn Event + Send>> {
+        let mut events: Vec<Box<dyn Event + Send>> = Vec::new();
+        let focus = match self.keyboard_focus.insert(keyboard_id, surface_id) {
+            Some(focus) => focus,
+            None => {
+                match no_keymap(keyboard_id) {
+                    Ok(keymap) => events.push(keymap),
+                    Err(err) => log::warn!("[Control] no keymap for {:#x}: {}", keyboard_id, err),
+                }
+                0
+            }
+        };
+        if focus == surface_id {
+            return events;
+        }
+        if focus != 0 {
+            events.push(Box::new(wl_keyboard::events::Leave {
+                sender_object_id: to_local_id(keyboard_id),
+                serial: next_serial(),
+                surface: to_local_id(focus),
+            }));
+        }
+        if surface_id != 0 {
+            events.push(Box::new(wl_keyboard::events::Enter {
+                sender_object_id: to_local_id(keyboard_id),
+                serial: next_serial(),
+                surface: to_local_id(surface_id),
+                keys: Vec::new(),
+            }));
+        }
+        events
+    }
+
+    fn pointer_focus(
+        &mut self,
+        pointer_id: u32,
+        surface_id: u32,
+        surface_x: u32,
+        surface_y: u32,
+    ) -> Vec<Box<dyn Event + Send>> {
+        let focus = self
+            .pointer_focus
+            .insert(pointer_id, surface_id)
+            .unwrap_or(0);
+        if focus == surface_id {
+            return Vec::new();
+        }
+        let mut events: Vec<Box<dyn Event + Send>> = Vec::new();
+        if focus != 0 {
+            events.push(Box::new(wl_pointer::events::Leave {
+                sender_object_id: to_local_id(pointer_id),
+                serial: next_serial(),
+                surface: to_local_id(focus),
+            }));
+        }
+        if surface_id != 0 {
+            events.push(Box::new(wl_pointer::events::Enter {
+                sender_object_id: to_local_id(pointer_id),
+                serial: next_serial(),
+                surface: to_local_id(surface_id),
+                surface_x,
+                surface_y,
+            }));
+        }
+        events.push(Box::new(wl_pointer::events::Frame {
+            sender_object_id: to_local_id(pointer_id),
+        }));
+        events
+    }
+
+    fn window(&mut self, toplevel_id: u32) -> &mut WindowState {
+        self.windows.entry(toplevel_id).or_insert(WindowState {
+            width: 0,
+            height: 0,
+            activated: false,
+        })
+    }
+
+    // A toplevel configure followed by the xdg_surface configure that
+    // completes it.
+    fn configure(&self, xdg_surface_id: u32, toplevel_id: u32) -> Vec<Box<dyn Event + Send>> {
+        let window = &self.windows[&toplevel_id];
+        let mut states = Vec::new();
+        if window.activated {
+            states
+                .write_u32::<NativeEndian>(xdg_toplevel::enums::State::Activated as u32)
+                .unwrap();
+        }
+        vec![
+            Box::new(xdg_toplevel::events::Configure {
+                sender_object_id: to_local_id(toplevel_id),
+                width: window.width,
+                height: window.height,
+                states,
+            }),
+            Box::new(xdg_surface::events::Configure {
+                sender_object_id: to_local_id(xdg_surface_id),
+                serial: next_serial(),
+            }),
+        ]
+    }
+}
+
+// Keys come from vision as raw keycodes, so there is no keymap to give;
+// no_keymap still takes an fd.
+fn no_keymap(keyboard_id: u32) -> io::Result<Box<dyn Event + Send>> {
+    Ok(Box::new(wl_keyboard::events::Keymap {
+        sender_object_id: to_local_id(keyboard_id),
+        format: wl_keyboard::enums::KeymapFormat::NoKeymap as u32,
+        fd: File::open("/dev/null")?,
+        size: 0,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::link::to_link_id;
+    use byteorder::ByteOrder;
+    use bytes::BytesMut;
+
+    const KEYMAP: u32 = 0;
+    const KEYBOARD_ENTER: u32 = 1;
+    const KEYBOARD_LEAVE: u32 = 2;
+    const POINTER_ENTER: u32 = 0;
+    const POINTER_LEAVE: u32 = 1;
+    const POINTER_FRAME: u32 = 5;
+
+    fn control_state() -> ControlState {
+        ControlState::new(Arc::new(RwLock::new(WlRegistry::new(&[]))))
+    }
+
+    // The opcode of every event, with the surface of enter and leave, which
+    // comes after the serial, and the number of fds.
+    fn summary(events: Vec<Box<dyn Event + Send>>) -> Vec<(u32, u32, usize)> {
+        events
+            .iter()
+            .map(|event| {
+                let mut bytes = BytesMut::new();
+                event.encode(&mut bytes).unwrap();
+                let opcode = NativeEndian::read_u32(&bytes[4..]) & 0xffff;
+                let surface = if bytes.len() >= 16 {
+                    NativeEndian::read_u32(&bytes[12..])
+                } else {
+                    0
+                };
+                (opcode, surface, event.fds().len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keyboard_focus() {
+        let mut control_state = control_state();
+        let keyboard_id = to_link_id(1, 5);
+        let focus = |control_state: &mut ControlState, surface: u32| {
+            summary(control_state.handle(ControlMessage::KeyboardFocus {
+                keyboard_id,
+                surface_id: if surface == 0 {
+                    0
+                } else {
+                    to_link_id(1, surface)
+                },
+            }))
+        };
+
+        assert_eq!(
+            focus(&mut control_state, 3),
+            vec![(KEYMAP, 0, 1), (KEYBOARD_ENTER, 3, 0)]
+        );
+        assert_eq!(focus(&mut control_state, 3), vec![]);
+        assert_eq!(
+            focus(&mut control_state, 4),
+            vec![(KEYBOARD_LEAVE, 3, 0), (KEYBOARD_ENTER, 4, 0)]
+        );
+        assert_eq!(focus(&mut control_state, 0), vec![(KEYBOARD_LEAVE, 4, 0)]);
+        assert_eq!(focus(&mut control_state, 0), vec![]);
+    }
+
+    #[test]
+    fn removed_client() {
+        let mut control_state = control_state();
+        let focus = ControlMessage::KeyboardFocus {
+            keyboard_id: to_link_id(1, 5),
+            surface_id: to_link_id(1, 3),
+        };
+        summary(control_state.handle(focus.clone()));
+        control_state.remove_client(2);
+        assert_eq!(summary(control_state.handle(focus.clone())), vec![]);
+
+        // The next client with the id gets the keymap again.
+        control_state.remove_client(1);
+        assert_eq!(
+            summary(control_state.handle(focus)),
+            vec![(KEYMAP, 0, 1), (KEYBOARD_ENTER, 3, 0)]
+        );
+    }
+
+    #[test]
+    fn pointer_focus() {
+        let mut control_state = control_state();
+        let pointer_id = to_link_id(1, 6);
+        let focus = |control_state: &mut ControlState, surface: u32| {
+            summary(control_state.handle(ControlMessage::PointerFocus {
+                pointer_id,
+                surface_id: if surface == 0 {
+                    0
+                } else {
+                    to_link_id(1, surface)
+                },
+                surface_x: 10 << 8,
+                surface_y: 20 << 8,
+            }))
+        };
+
+        assert_eq!(
+            focus(&mut control_state, 3),
+            vec![(POINTER_ENTER, 3, 0), (POINTER_FRAME, 0, 0)]
+        );
+        assert_eq!(focus(&mut control_state, 3), vec![]);
+        assert_eq!(
+            focus(&mut control_state, 4),
+            vec![
+                (POINTER_LEAVE, 3, 0),
+                (POINTER_ENTER, 4, 0),
+                (POINTER_FRAME, 0, 0)
+            ]
+        );
+        assert_eq!(
+            focus(&mut control_state, 0),
+            vec![(POINTER_LEAVE, 4, 0), (POINTER_FRAME, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn focus_round_trip() {
+        for message in &[
+            ControlMessage::KeyboardFocus {
+                keyboard_id: 0x0010_0005,
+                surface_id: 0x0010_0003,
+            },
+            ControlMessage::PointerFocus {
+                pointer_id: 0x0010_0006,
+                surface_id: 0,
+                surface_x: 1,
+                surface_y: 2,
+            },
+        ] {
+            let frame = message.encode();
+            let kind = NativeEndian::read_u32(&frame[4..]);
+            let decoded = ControlMessage::decode(kind, &frame[4 + CONTROL_HEADER_SIZE..]).unwrap();
+            assert_eq!(&decoded, message);
+        }
+    }
+}
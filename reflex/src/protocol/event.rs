@@ -1,5 +1,19 @@
+use crate::protocol::trace::Interface;
 use bytes::BytesMut;
+use std::os::unix::io::RawFd;
 
 pub trait Event {
     fn encode(&self, dst: &mut BytesMut) -> Result<(), std::io::Error>;
+
+    // The interface of the sender, for tracing events whose sender is not
+    // an object of the client, such as wl_callback.
+    fn interface(&self) -> Option<&'static Interface> {
+        None
+    }
+
+    // The fds that go with the event, as ancillary data. They stay the
+    // event's; the sink sends copies.
+    fn fds(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
 }
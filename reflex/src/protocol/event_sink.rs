@@ -1,24 +1,43 @@
+use crate::protocol::capture::Capture;
 use crate::protocol::event::Event;
 use crate::protocol::fd_drop::FdDrop;
+use crate::protocol::object_map::ObjectVersions;
+use crate::protocol::trace;
+use byteorder::{ByteOrder, NativeEndian};
 use bytes::BytesMut;
 use futures::sink::Sink;
 use futures::AsyncSink;
-use nix::fcntl::fcntl;
-use nix::fcntl::FcntlArg::F_GETFD;
+use nix::fcntl::{fcntl, FcntlArg};
 use nix::sys::socket::*;
+use nix::sys::uio::IoVec;
 use std::os::unix::io::RawFd;
 use std::sync::Arc;
 use tokio::prelude::Async;
 
+// Used when the size of the socket buffer cannot be read.
+const DEFAULT_SEND_SIZE: usize = 64 * 1024;
+// The encoded events a client may leave unread before the sink stops taking
+// more.
+pub const MAX_PENDING_BYTES: usize = 1024 * 1024;
+// The fds one sendmsg of libwayland carries at most.
+pub const MAX_FDS: usize = 28;
+
+// Writes events in batches: events are encoded into one buffer as they come,
+// and go out with a single sendmsg of up to what the socket buffer takes.
+// A client that stops reading fills the buffer, then the queue of its
+// events, and is hung up by whoever cannot wait for it, see Hangup.
 pub struct EventSink {
     fd: RawFd,
     _fd_drop: Arc<FdDrop>,
     tokio_registration: Arc<tokio::reactor::Registration>,
-    pending_bytes: Vec<u8>,
+    pending_bytes: BytesMut,
+    // Copies of the fds of the events in pending_bytes, closed once sent.
     pending_fds: Vec<RawFd>,
-    pending_events: Vec<Box<dyn Event + Send>>,
+    versions: ObjectVersions,
     //  _tokio_stream: Arc<UnixStream>,
-    closed: bool,
+    send_size: usize,
+    client_id: u32,
+    capture: Option<Capture>,
 }
 
 impl EventSink {
@@ -26,19 +45,81 @@ impl EventSink {
         fd: RawFd,
         fd_drop: Arc<FdDrop>,
         tokio_registration: Arc<tokio::reactor::Registration>,
+        versions: ObjectVersions,
         //tokio_stream: Arc<UnixStream>,
+        client_id: u32,
+        capture: Option<Capture>,
     ) -> EventSink {
+        let send_size = getsockopt(fd, sockopt::SndBuf).unwrap_or(DEFAULT_SEND_SIZE);
         EventSink {
             fd,
             _fd_drop: fd_drop,
             tokio_registration,
-            pending_bytes: Vec::new(),
+            pending_bytes: BytesMut::with_capacity(send_size),
             pending_fds: Vec::new(),
-            pending_events: Vec::new(),
+            versions,
             //_tokio_stream: tokio_stream,
-            closed: false,
+            send_size,
+            client_id,
+            capture,
+        }
+    }
+}
+
+// Disconnects a client from outside its sink, when events for it cannot even
+// be queued: the socket is shut down, so that the request stream sees the
+// end too and the session is torn down as usual.
+#[derive(Clone)]
+pub struct Hangup {
+    fd: RawFd,
+    _fd_drop: Arc<FdDrop>,
+}
+
+impl Hangup {
+    pub(crate) fn new(fd: RawFd, fd_drop: Arc<FdDrop>) -> Hangup {
+        Hangup {
+            fd,
+            _fd_drop: fd_drop,
         }
     }
+
+    pub fn hang_up(&self) {
+        let _ = shutdown(self.fd, Shutdown::Both);
+    }
+}
+
+// Append an event to what goes out to the client, unless the object it is
+// from was bound with a version that does not have it.
+pub fn encode(
+    dst: &mut BytesMut,
+    versions: &ObjectVersions,
+    item: &dyn Event,
+) -> std::io::Result<()> {
+    let start = dst.len();
+    if let Err(err) = item.encode(dst) {
+        log::error!("[Sink] encode err {:?}", err);
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
+    }
+    if !is_supported(versions, &dst[start..]) {
+        log::debug!("[Sink] drop event unsupported by object version");
+        dst.truncate(start);
+    } else {
+        trace::event(versions, item.interface(), &dst[start..]);
+    }
+    Ok(())
+}
+
+// Events newer than the version the object was bound with must not be sent.
+fn is_supported(versions: &ObjectVersions, bytes: &[u8]) -> bool {
+    if bytes.len() < 8 {
+        return true;
+    }
+    let sender_object_id = NativeEndian::read_u32(&bytes[0..]);
+    let opcode = (NativeEndian::read_u32(&bytes[4..]) & 0xffff) as u16;
+    match versions.read().unwrap().get(&sender_object_id) {
+        Some(object_version) => (object_version.event_since)(opcode) <= object_version.version,
+        None => true,
+    }
 }
 
 impl Sink for EventSink {
@@ -49,84 +130,157 @@ impl Sink for EventSink {
         &mut self,
         item: Self::SinkItem,
     ) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
-        self.pending_events.push(item);
-        println!("[Sink] start_send events.len={}", self.pending_events.len());
+        // Events that are already queued come in without poll_complete in
+        // between, so write out what the socket takes as the buffer fills.
+        if self.pending_bytes.len() >= self.send_size {
+            self.poll_complete()?;
+            if self.pending_bytes.len() > MAX_PENDING_BYTES {
+                return Ok(AsyncSink::NotReady(item));
+            }
+        }
+        let start = self.pending_bytes.len();
+        encode(&mut self.pending_bytes, &self.versions, item.as_ref())?;
+        if self.pending_bytes.len() > start {
+            for fd in item.fds() {
+                let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+                self.pending_fds.push(copy);
+            }
+            if let Some(capture) = &self.capture {
+                capture.events(self.client_id, &self.pending_bytes[start..]);
+            }
+        }
         Ok(AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Result<Async<()>, Self::SinkError> {
         loop {
-            println!(
-                "[Sink] poll complete events.len={}",
-                self.pending_events.len()
-            );
-            match self.tokio_registration.poll_write_ready() {
-                Ok(Async::Ready(ready)) if ready.is_writable() => {
-                    println!("[Sink] write ready");
-                    ()
-                }
-                Ok(Async::Ready(_)) => {
-                    println!("[Sink] write ready ERROR");
-                    // return Ok(Async::NotReady);
-                }
-                Ok(Async::NotReady) => {
-                    println!("[Sink] write not ready");
-                    //return Ok(Async::NotReady);
-                }
+            if self.pending_bytes.is_empty() {
+                return Ok(Async::Ready(()));
+            }
+
+            let ready = match self.tokio_registration.poll_write_ready() {
+                Ok(Async::Ready(ready)) => ready.is_writable(),
+                Ok(Async::NotReady) => false,
                 Err(err) => {
-                    println!("[Sink] write ERROR {:?}", err);
+                    log::error!("[Sink] write ERROR {:?}", err);
                     return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
                 }
-            }
+            };
 
-            let mut bytes = BytesMut::new();
-            if self.pending_bytes.len() == 0 && self.pending_fds.len() == 0 {
-                println!("[Sink] pending bytes is empty");
-                if self.pending_events.len() == 0 {
-                    println!("[Sink] pending event is empty");
-                    return Ok(Async::Ready(()));
-                } else {
-                    let rest = self.pending_events.split_off(1);
-                    let first = self.pending_events.pop().expect("pending events error");
-                    self.pending_events = rest;
-
-                    match first.encode(&mut bytes) {
-                        Ok(()) => {
-                            println!("[Sink] encoded");
-                        }
-                        Err(err) => {
-                            println!("[Sink] err3 {:?}", err);
-                            return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
-                        }
+            // The fds go with the first bytes not sent yet, so never after
+            // the events they belong to. While more are pending than one
+            // sendmsg carries, a byte at a time goes with them.
+            let fd_count = std::cmp::min(self.pending_fds.len(), MAX_FDS);
+            let len = if self.pending_fds.len() > MAX_FDS {
+                1
+            } else {
+                std::cmp::min(self.pending_bytes.len(), self.send_size)
+            };
+            let iov = [IoVec::from_slice(&self.pending_bytes[..len])];
+            let sent = if fd_count == 0 {
+                sendmsg(self.fd, &iov, &[], MsgFlags::empty(), None)
+            } else {
+                let cmsgs = [ControlMessage::ScmRights(&self.pending_fds[..fd_count])];
+                sendmsg(self.fd, &iov, &cmsgs, MsgFlags::empty(), None)
+            };
+            let sent_bytes = match sent {
+                Ok(sent_bytes) => sent_bytes,
+                Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {
+                    // A readiness that found the socket full was spent, so
+                    // poll again to be woken by the next one.
+                    if ready {
+                        continue;
                     }
+                    return Ok(Async::NotReady);
                 }
-            }
-
-            self.pending_bytes.extend(bytes);
-            println!("[Sink] write {:?}", &self.pending_bytes);
-            let flags = fcntl(self.fd, F_GETFD);
-            println!("[Sink] flags={:?}", flags);
-            let sent_bytes = match send(self.fd, &self.pending_bytes[..], MsgFlags::empty()) {
-                Ok(sent_bytes) => sent_bytes,
-                Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => return Ok(Async::NotReady),
                 Err(err) => {
-                    println!("[Sink] err2 {:?}", err);
+                    log::warn!("[Sink] write err {:?}", err);
                     return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
                 }
             };
-
-            println!("[Sink] write {} bytes written", sent_bytes);
-            if self.pending_bytes.len() <= sent_bytes {
-                self.pending_bytes.clear();
-            } else if sent_bytes > 0 {
-                self.pending_bytes = self.pending_bytes.split_off(sent_bytes);
-            }
-            println!("[Sink] new pending_bytes={:?}", self.pending_bytes);
+            log::debug!(
+                "[Sink] wrote {} of {} bytes",
+                sent_bytes,
+                self.pending_bytes.len()
+            );
+            self.pending_bytes.advance(sent_bytes);
+            close_fds(self.pending_fds.drain(..fd_count));
         }
     }
 
     fn close(&mut self) -> Result<Async<()>, Self::SinkError> {
-        self.closed = true;
-        Ok(Async::Ready(()))
+        self.poll_complete()
+    }
+}
+
+impl Drop for EventSink {
+    fn drop(&mut self) {
+        close_fds(self.pending_fds.drain(..));
+    }
+}
+
+fn close_fds<I: Iterator<Item = RawFd>>(fds: I) {
+    for fd in fds {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::object_map::ObjectMap;
+    use crate::protocol::raw_event::RawEvent;
+    use futures::future::{lazy, Future};
+    use futures::stream::{self, Stream};
+    use std::io::Read;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn queued_burst() {
+        let (ours, mut peer) = UnixStream::pair().unwrap();
+        ours.set_nonblocking(true).unwrap();
+        let fd = ours.into_raw_fd();
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            peer.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        // Twice as many bytes as the sink may hold, all queued at once.
+        let events: Vec<Box<dyn Event + Send>> = (0..2 * MAX_PENDING_BYTES / 1024)
+            .map(|i| -> Box<dyn Event + Send> {
+                Box::new(RawEvent {
+                    data: vec![i as u8; 1024],
+                })
+            })
+            .collect();
+        let expected: Vec<u8> = (0..events.len())
+            .flat_map(|i| vec![i as u8; 1024])
+            .collect();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(lazy(move || {
+                let tokio_registration = Arc::new(tokio::reactor::Registration::new());
+                tokio_registration
+                    .register(&mio::unix::EventedFd(&fd))
+                    .unwrap();
+                let sink = EventSink::new(
+                    fd,
+                    Arc::new(FdDrop::new(fd)),
+                    tokio_registration,
+                    ObjectMap::new().versions(),
+                    1,
+                    None,
+                );
+                stream::iter_ok::<_, std::io::Error>(events)
+                    .forward(sink)
+                    .map(|_| ())
+            }))
+            .unwrap();
+        assert!(reader.join().unwrap() == expected);
     }
 }
@@ -0,0 +1,89 @@
+use super::capture::Capture;
+use std::collections::VecDeque;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// The fds one client may hold at once, queued or owned by its objects.
+pub const MAX_CLIENT_FDS: usize = 256;
+
+// An fd received from a client. It is closed when dropped, and counts
+// against the cap of the client until then.
+#[derive(Debug)]
+pub struct OwnedFd {
+    fd: RawFd,
+    held: Arc<AtomicUsize>,
+}
+
+impl OwnedFd {
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for OwnedFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+        self.held.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// The fds a client has sent that no request has taken yet, in the order they
+// arrived. The stream puts them in as they come with the bytes, and the
+// decoders take one for every fd argument of a request, so that each request
+// gets its own whatever the batches the fds were received in.
+#[derive(Clone, Default)]
+pub struct FdQueue {
+    fds: Arc<Mutex<VecDeque<OwnedFd>>>,
+    // The fds of the client that are still open.
+    held: Arc<AtomicUsize>,
+    // Where the fds are recorded, with the id of the client.
+    capture: Option<(Capture, u32)>,
+}
+
+impl FdQueue {
+    pub fn recorded(capture: Capture, client_id: u32) -> FdQueue {
+        FdQueue {
+            capture: Some((capture, client_id)),
+            ..FdQueue::default()
+        }
+    }
+
+    // Takes ownership of fd, or closes it if the client is over its cap.
+    pub fn push(&self, fd: RawFd) -> Result<(), ()> {
+        let held = self.held.fetch_add(1, Ordering::SeqCst) + 1;
+        let fd = OwnedFd {
+            fd,
+            held: self.held.clone(),
+        };
+        if held > MAX_CLIENT_FDS {
+            return Err(());
+        }
+        if let Some((capture, client_id)) = &self.capture {
+            capture.fd(*client_id, fd.as_raw_fd());
+        }
+        self.fds.lock().unwrap().push_back(fd);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<OwnedFd> {
+        self.fds.lock().unwrap().pop_front()
+    }
+
+    // The queued fds in order, for tracing.
+    pub fn raw_fds(&self) -> Vec<RawFd> {
+        self.fds
+            .lock()
+            .unwrap()
+            .iter()
+            .map(OwnedFd::as_raw_fd)
+            .collect()
+    }
+
+    // Close the fds that were sent for no request.
+    pub fn clear(&self) {
+        self.fds.lock().unwrap().clear();
+    }
+}
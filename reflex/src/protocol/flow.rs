@@ -0,0 +1,199 @@
+use super::pixels::{self, Rect, TILE_SIZE};
+use super::wayland::wl_buffer::WlBuffer;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+
+// Flow control of the pixels sent to vision. Every surface has one credit:
+// a PIXELS frame spends it and vision hands it back with FRAME_DONE once the
+// frame is on screen. Pixels committed in the meantime are held, the tiles
+// of a later commit replacing those of an earlier one, and go out together
+// when the credit comes back. Frame callbacks are answered with the
+// FRAME_DONE of the frame that carries their commit, so that a client draws
+// no faster than vision shows its frames.
+#[derive(Default)]
+pub struct FlowControl {
+    // (client id, surface link id) -> state
+    surfaces: HashMap<(u32, u32), SurfaceFlow>,
+}
+
+#[derive(Default)]
+struct SurfaceFlow {
+    // The callbacks to answer when the frame in flight is done, or None
+    // while the surface has its credit.
+    in_flight: Option<Vec<u32>>,
+    held_callbacks: Vec<u32>,
+    held: Option<Tiles>,
+}
+
+// The rows of the tiles of a surface by (y, x), so that they go out in
+// order, with the buffer they were last read from.
+pub struct Tiles {
+    buffer: WlBuffer,
+    tiles: BTreeMap<(i32, i32), Vec<u8>>,
+}
+
+pub type Pixels = (WlBuffer, Vec<(Rect, Vec<u8>)>);
+
+// What to do after a commit or a FRAME_DONE.
+#[derive(Default)]
+pub struct FlowAction {
+    pub send: Option<Pixels>,
+    // Frame callbacks to answer now.
+    pub done: Vec<u32>,
+}
+
+impl FlowControl {
+    // A commit with the pixels it changed, if it attached a buffer, and its
+    // frame callbacks.
+    pub fn commit(
+        &mut self,
+        client_id: u32,
+        surface_id: u32,
+        pixels: Option<Pixels>,
+        callbacks: Vec<u32>,
+    ) -> FlowAction {
+        let surface = self.surfaces.entry((client_id, surface_id)).or_default();
+        let pixels = pixels.filter(|(_, rects)| !rects.is_empty());
+        if surface.in_flight.is_some() {
+            surface.held_callbacks.extend(callbacks);
+            if let Some((buffer, rects)) = pixels {
+                surface.hold(buffer, rects);
+            }
+            return FlowAction::default();
+        }
+        match pixels {
+            Some(pixels) => {
+                surface.in_flight = Some(callbacks);
+                FlowAction {
+                    send: Some(pixels),
+                    done: Vec::new(),
+                }
+            }
+            None => FlowAction {
+                send: None,
+                done: callbacks,
+            },
+        }
+    }
+
+    pub fn frame_done(&mut self, client_id: u32, surface_id: u32) -> FlowAction {
+        let surface = match self.surfaces.get_mut(&(client_id, surface_id)) {
+            Some(surface) => surface,
+            None => return FlowAction::default(),
+        };
+        let mut done = surface.in_flight.take().unwrap_or_default();
+        let callbacks = mem::take(&mut surface.held_callbacks);
+        match surface.held.take() {
+            Some(tiles) => {
+                surface.in_flight = Some(callbacks);
+                FlowAction {
+                    send: Some(tiles.into_pixels()),
+                    done,
+                }
+            }
+            None => {
+                done.extend(callbacks);
+                FlowAction { send: None, done }
+            }
+        }
+    }
+
+    pub fn remove_surface(&mut self, client_id: u32, surface_id: u32) {
+        self.surfaces.remove(&(client_id, surface_id));
+    }
+
+    pub fn remove_client(&mut self, client_id: u32) {
+        self.surfaces.retain(|(id, _), _| *id != client_id);
+    }
+
+    // A new link starts with every credit back, and the replay brings all
+    // the pixels that were held. Returns the callbacks to answer by client.
+    pub fn reset(&mut self) -> HashMap<u32, Vec<u32>> {
+        let mut done: HashMap<u32, Vec<u32>> = HashMap::new();
+        for ((client_id, _), surface) in self.surfaces.drain() {
+            let callbacks = done.entry(client_id).or_default();
+            callbacks.extend(surface.in_flight.unwrap_or_default());
+            callbacks.extend(surface.held_callbacks);
+        }
+        done
+    }
+}
+
+impl SurfaceFlow {
+    fn hold(&mut self, buffer: WlBuffer, rects: Vec<(Rect, Vec<u8>)>) {
+        match &mut self.held {
+            Some(tiles) => tiles.add(buffer, rects),
+            None => self.held = Some(Tiles::new(buffer, rects)),
+        }
+    }
+}
+
+impl Tiles {
+    pub fn new(buffer: WlBuffer, rects: Vec<(Rect, Vec<u8>)>) -> Tiles {
+        let mut tiles = Tiles {
+            buffer: buffer.clone(),
+            tiles: BTreeMap::new(),
+        };
+        tiles.add(buffer, rects);
+        tiles
+    }
+
+    // The rects of a commit replace the tiles they cover. Tiles of another
+    // layout are replaced by a full redraw.
+    pub fn add(&mut self, buffer: WlBuffer, rects: Vec<(Rect, Vec<u8>)>) {
+        let layout =
+            |buffer: &WlBuffer| (buffer.width, buffer.height, buffer.stride, buffer.format);
+        if layout(&self.buffer) != layout(&buffer) {
+            self.tiles.clear();
+        }
+        let bytes_per_pixel = pixels::bytes_per_pixel(buffer.format).unwrap_or(4);
+        // The rects are runs of whole tiles in a row of tiles.
+        for (rect, rows) in rects {
+            let row_len = (rect.width * bytes_per_pixel) as usize;
+            for x in (rect.x..rect.x + rect.width).step_by(TILE_SIZE as usize) {
+                let tile = tile_rect(&buffer, x, rect.y);
+                let start = ((x - rect.x) * bytes_per_pixel) as usize;
+                let tile_row_len = (tile.width * bytes_per_pixel) as usize;
+                let mut tile_rows = Vec::with_capacity(tile_row_len * tile.height as usize);
+                for row in 0..tile.height as usize {
+                    let row_start = row * row_len + start;
+                    tile_rows.extend_from_slice(&rows[row_start..row_start + tile_row_len]);
+                }
+                self.tiles.insert((rect.y, x), tile_rows);
+            }
+        }
+        self.buffer = buffer;
+    }
+
+    pub fn buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    // Every tile as a rect of its own.
+    pub fn rects(&self) -> Vec<(Rect, Vec<u8>)> {
+        self.tiles
+            .iter()
+            .map(|((y, x), rows)| (tile_rect(&self.buffer, *x, *y), rows.clone()))
+            .collect()
+    }
+
+    pub fn into_pixels(self) -> Pixels {
+        let buffer = self.buffer;
+        let rects = self
+            .tiles
+            .into_iter()
+            .map(|((y, x), rows)| (tile_rect(&buffer, x, y), rows))
+            .collect();
+        (buffer, rects)
+    }
+}
+
+// The tile of the buffer at x, y.
+fn tile_rect(buffer: &WlBuffer, x: i32, y: i32) -> Rect {
+    Rect {
+        x,
+        y,
+        width: std::cmp::min(TILE_SIZE, buffer.width - x),
+        height: std::cmp::min(TILE_SIZE, buffer.height - y),
+    }
+}
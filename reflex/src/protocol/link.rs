@@ -0,0 +1,136 @@
+use byteorder::{ByteOrder, LittleEndian, NativeEndian};
+use futures::future::Future;
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+// Exchanged before any Wayland message on the reflex-vision link.
+pub const HELLO_MAGIC: [u8; 4] = *b"UDHL";
+// Bumped whenever the framing or the meaning of messages on the link changes.
+pub const LINK_VERSION: u32 = 7;
+
+pub const HELLO_ACCEPTED: u32 = 0;
+pub const HELLO_VERSION_MISMATCH: u32 = 1;
+
+// Optional behaviour both ends have to agree on. Vision answers with the
+// subset it supports.
+// Rects of PIXELS frames may be run-length coded.
+pub const FEATURE_RLE: u32 = 1 << 0;
+pub const SUPPORTED_FEATURES: u32 = FEATURE_RLE;
+
+// What the display behind vision can do, as reported by vision.
+pub const CAPABILITY_WINDOWS: u32 = 1 << 0;
+
+// Prefix of every message vision sends after the hello: either a Wayland
+// event for the client or a control message about the host display.
+pub const RESPONSE_EVENT: u32 = 0;
+pub const RESPONSE_CONTROL: u32 = 1;
+
+// Prefix of every frame reflex sends after the hello, followed by the client
+// id and the length of the data.
+pub const REQUEST_MESSAGES: u32 = 0;
+pub const REQUEST_CLIENT_CONNECTED: u32 = 1;
+pub const REQUEST_CLIENT_DISCONNECTED: u32 = 2;
+pub const REQUEST_PIXELS: u32 = 3;
+// Pixels held back while the surface had no credit, for commits vision has
+// already seen: shown at once rather than at the next commit.
+pub const REQUEST_HELD_PIXELS: u32 = 4;
+// A serial that vision sends back in SYNC_DONE once it has handled every
+// earlier frame of the client.
+pub const REQUEST_SYNC: u32 = 5;
+pub const REQUEST_HEADER_SIZE: usize = 12;
+
+// All clients share one link, so object ids carry the client id in their top
+// bits. Client ids start at 1; a client may not have more than MAX_LOCAL_ID
+// objects at a time, which libwayland never gets near since it reuses the
+// lowest free id.
+pub const LOCAL_ID_BITS: u32 = 20;
+pub const MAX_LOCAL_ID: u32 = (1 << LOCAL_ID_BITS) - 1;
+pub const MAX_CLIENT_ID: u32 = (1 << (32 - LOCAL_ID_BITS)) - 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LinkInfo {
+    pub version: u32,
+    pub features: u32,
+    pub capabilities: u32,
+}
+
+// reflex -> vision: magic, version, features
+// vision -> reflex: magic, status, version, features, capabilities
+// All fields are little-endian so that both ends agree before anything else
+// is known about the peer.
+pub fn hello<S>(stream: S) -> impl Future<Item = (S, LinkInfo), Error = io::Error>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let mut request = [0; 12];
+    request[..4].copy_from_slice(&HELLO_MAGIC);
+    LittleEndian::write_u32(&mut request[4..], LINK_VERSION);
+    LittleEndian::write_u32(&mut request[8..], SUPPORTED_FEATURES);
+
+    tokio::io::write_all(stream, request)
+        .and_then(|(stream, _)| tokio::io::read_exact(stream, [0; 20]))
+        .and_then(|(stream, reply)| {
+            if reply[..4] != HELLO_MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "vision did not answer the hello; is it an older build?",
+                ));
+            }
+            let status = LittleEndian::read_u32(&reply[4..]);
+            let link_info = LinkInfo {
+                version: LittleEndian::read_u32(&reply[8..]),
+                features: LittleEndian::read_u32(&reply[12..]),
+                capabilities: LittleEndian::read_u32(&reply[16..]),
+            };
+            match status {
+                HELLO_ACCEPTED if link_info.version == LINK_VERSION => {}
+                HELLO_ACCEPTED | HELLO_VERSION_MISMATCH => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "link version mismatch: reflex speaks {}, vision speaks {}",
+                            LINK_VERSION, link_info.version
+                        ),
+                    ));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("vision refused the hello with status {}", status),
+                    ));
+                }
+            }
+            if link_info.features & !SUPPORTED_FEATURES != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "vision enabled unknown features {:#x}",
+                        link_info.features & !SUPPORTED_FEATURES
+                    ),
+                ));
+            }
+            Ok((stream, link_info))
+        })
+}
+
+// The null object stays null.
+pub fn to_link_id(client_id: u32, local_id: u32) -> u32 {
+    if local_id == 0 {
+        0
+    } else {
+        client_id << LOCAL_ID_BITS | local_id
+    }
+}
+
+pub fn to_local_id(link_id: u32) -> u32 {
+    link_id & MAX_LOCAL_ID
+}
+
+pub fn request_frame(request_type: u32, client_id: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; REQUEST_HEADER_SIZE];
+    NativeEndian::write_u32(&mut frame[0..], request_type);
+    NativeEndian::write_u32(&mut frame[4..], client_id);
+    NativeEndian::write_u32(&mut frame[8..], data.len() as u32);
+    frame.extend_from_slice(data);
+    frame
+}
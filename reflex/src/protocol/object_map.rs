@@ -0,0 +1,207 @@
+use crate::protocol::link::MAX_LOCAL_ID;
+use crate::protocol::resource::Resource;
+use crate::protocol::trace::Interface;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Creating-Objects
+pub const SERVER_ID_START: u32 = 0xff00_0000;
+
+#[derive(Debug, PartialEq)]
+pub enum ObjectMapError {
+    NullId,
+    OutOfRange,
+    AlreadyInUse,
+}
+
+impl std::fmt::Display for ObjectMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ObjectMapError::NullId => write!(f, "null object id"),
+            ObjectMapError::OutOfRange => write!(f, "object id out of client range"),
+            ObjectMapError::AlreadyInUse => write!(f, "object id already in use"),
+        }
+    }
+}
+
+// The version an object was bound with, shared with the EventSink so that it
+// can drop events the client did not ask for, and its interface for tracing.
+#[derive(Clone, Copy)]
+pub struct ObjectVersion {
+    pub version: u32,
+    pub event_since: fn(u16) -> u32,
+    pub interface: &'static Interface,
+}
+
+pub type ObjectVersions = Arc<RwLock<HashMap<u32, ObjectVersion>>>;
+
+pub struct ObjectMap {
+    client_objects: HashMap<u32, Resource>,
+    server_objects: HashMap<u32, Resource>,
+    free_server_ids: Vec<u32>,
+    next_server_id: u32,
+    dispatching_id: Option<u32>,
+    dispatching_version: u32,
+    versions: ObjectVersions,
+}
+
+impl ObjectMap {
+    pub fn new() -> ObjectMap {
+        ObjectMap {
+            client_objects: HashMap::new(),
+            server_objects: HashMap::new(),
+            free_server_ids: Vec::new(),
+            next_server_id: SERVER_ID_START,
+            dispatching_id: None,
+            dispatching_version: 1,
+            versions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn versions(&self) -> ObjectVersions {
+        self.versions.clone()
+    }
+
+    pub fn version(&self, id: u32) -> u32 {
+        match self.versions.read().unwrap().get(&id) {
+            Some(object_version) => object_version.version,
+            None => 1,
+        }
+    }
+
+    fn set_version(&self, id: u32, resource: &Resource, version: u32) {
+        self.versions.write().unwrap().insert(
+            id,
+            ObjectVersion {
+                version,
+                event_since: resource.event_since(),
+                interface: resource.interface(),
+            },
+        );
+    }
+
+    pub fn is_client_id(id: u32) -> bool {
+        id != 0 && id < SERVER_ID_START
+    }
+
+    // Register an object created by a client request (new_id argument).
+    // The new object has the same version as the object that created it.
+    pub fn insert(&mut self, id: u32, resource: Resource) -> Result<(), ObjectMapError> {
+        let version = self.dispatching_version;
+        self.insert_with_version(id, resource, version)
+    }
+
+    // Register an object created by wl_registry.bind with the negotiated version.
+    pub fn insert_with_version(
+        &mut self,
+        id: u32,
+        resource: Resource,
+        version: u32,
+    ) -> Result<(), ObjectMapError> {
+        self.validate_new_id(id)?;
+        self.set_version(id, &resource, version);
+        self.client_objects.insert(id, resource);
+        Ok(())
+    }
+
+    pub fn validate_new_id(&self, id: u32) -> Result<(), ObjectMapError> {
+        if id == 0 {
+            return Err(ObjectMapError::NullId);
+        }
+        if !ObjectMap::is_client_id(id) || id > MAX_LOCAL_ID {
+            return Err(ObjectMapError::OutOfRange);
+        }
+        if self.client_objects.contains_key(&id) || self.dispatching_id == Some(id) {
+            return Err(ObjectMapError::AlreadyInUse);
+        }
+        Ok(())
+    }
+
+    // Register an object created by the server (new_id argument of an event).
+    #[allow(dead_code)]
+    pub fn insert_server(&mut self, resource: Resource) -> u32 {
+        let id = if let Some(id) = self.free_server_ids.pop() {
+            id
+        } else {
+            let id = self.next_server_id;
+            self.next_server_id += 1;
+            id
+        };
+        self.set_version(id, &resource, self.dispatching_version);
+        self.server_objects.insert(id, resource);
+        id
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Resource> {
+        if ObjectMap::is_client_id(id) {
+            self.client_objects.get(&id)
+        } else {
+            self.server_objects.get(&id)
+        }
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Resource> {
+        if ObjectMap::is_client_id(id) {
+            self.client_objects.get_mut(&id)
+        } else {
+            self.server_objects.get_mut(&id)
+        }
+    }
+
+    // Temporarily move the object out while one of its requests is dispatched.
+    // The id stays reserved until `put_back` or `remove` is called.
+    pub fn take(&mut self, id: u32) -> Option<Resource> {
+        let resource = if ObjectMap::is_client_id(id) {
+            self.client_objects.remove(&id)
+        } else {
+            self.server_objects.remove(&id)
+        };
+        if resource.is_some() {
+            self.dispatching_id = Some(id);
+            self.dispatching_version = self.version(id);
+        }
+        resource
+    }
+
+    pub fn put_back(&mut self, id: u32, resource: Resource) {
+        if self.dispatching_id == Some(id) {
+            self.dispatching_id = None;
+        }
+        if ObjectMap::is_client_id(id) {
+            self.client_objects.insert(id, resource);
+        } else {
+            self.server_objects.insert(id, resource);
+        }
+    }
+
+    // Remove every object, e.g. when the client goes away.
+    pub fn drain(&mut self) -> Vec<(u32, Resource)> {
+        self.dispatching_id = None;
+        self.versions.write().unwrap().clear();
+        self.free_server_ids.clear();
+        self.next_server_id = SERVER_ID_START;
+        self.client_objects
+            .drain()
+            .chain(self.server_objects.drain())
+            .collect()
+    }
+
+    // Destroy the object. Returns true when the client must be told with
+    // wl_display.delete_id that the id can be reused.
+    pub fn remove(&mut self, id: u32) -> (Option<Resource>, bool) {
+        let dispatching = self.dispatching_id == Some(id);
+        if dispatching {
+            self.dispatching_id = None;
+        }
+        self.versions.write().unwrap().remove(&id);
+        if ObjectMap::is_client_id(id) {
+            (self.client_objects.remove(&id), true)
+        } else {
+            let resource = self.server_objects.remove(&id);
+            if resource.is_some() || dispatching {
+                self.free_server_ids.push(id);
+            }
+            (resource, false)
+        }
+    }
+}
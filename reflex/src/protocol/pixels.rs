@@ -0,0 +1,346 @@
+use super::link;
+use super::rle;
+use super::wayland::wl_buffer::WlBuffer;
+use super::wayland::wl_shm::enums::Format;
+use byteorder::{ByteOrder, NativeEndian};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io;
+
+// Vision cannot map the pools of Linux clients, so reflex reads the pixels
+// of a buffer when it is committed and sends them in a PIXELS frame:
+//
+//   surface (link id), format, width, height, stride, rect count
+//   per rect: x, y, width, height, encoding, length, then length bytes of
+//   height rows of width pixels, coded as the encoding says
+//
+// Rects are in buffer coordinates; width, height and stride are those of
+// the buffer.
+pub const PIXELS_HEADER_SIZE: usize = 24;
+pub const RECT_HEADER_SIZE: usize = 24;
+
+pub const ENCODING_RAW: u32 = 0;
+// Only when the link has FEATURE_RLE, see rle.rs.
+pub const ENCODING_RLE: u32 = 1;
+
+// Damage is sent in whole tiles, and a tile whose hash is the same as when
+// it was last sent is not sent again.
+pub const TILE_SIZE: i32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        // Clients commonly damage (0, 0, INT32_MAX, INT32_MAX).
+        let x = std::cmp::max(self.x, other.x);
+        let y = std::cmp::max(self.y, other.y);
+        let right = std::cmp::min(
+            self.x.saturating_add(self.width),
+            other.x.saturating_add(other.width),
+        );
+        let bottom = std::cmp::min(
+            self.y.saturating_add(self.height),
+            other.y.saturating_add(other.height),
+        );
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+// The formats vision advertises, both 4 bytes per pixel.
+pub fn bytes_per_pixel(format: u32) -> Option<i32> {
+    if format == Format::Argb8888 as u32 || format == Format::Xrgb8888 as u32 {
+        Some(4)
+    } else {
+        None
+    }
+}
+
+// Whether a buffer with this layout fits in a pool of pool_size bytes.
+pub fn fits(
+    pool_size: i32,
+    offset: i32,
+    width: i32,
+    height: i32,
+    stride: i32,
+    format: u32,
+) -> bool {
+    let bytes_per_pixel = match bytes_per_pixel(format) {
+        Some(bytes_per_pixel) => bytes_per_pixel,
+        None => return false,
+    };
+    if offset < 0 || width <= 0 || height <= 0 || stride / bytes_per_pixel < width {
+        return false;
+    }
+    i64::from(offset) + i64::from(stride) * i64::from(height) <= i64::from(pool_size)
+}
+
+// wl_surface.damage is in surface coordinates; turn it into buffer
+// coordinates for a buffer of the given size, scale and wl_output
+// transform.
+pub fn surface_to_buffer(
+    rect: &Rect,
+    scale: i32,
+    transform: u32,
+    buffer_width: i32,
+    buffer_height: i32,
+) -> Option<Rect> {
+    let (width, height) = if transform % 2 == 1 {
+        (buffer_height / scale, buffer_width / scale)
+    } else {
+        (buffer_width / scale, buffer_height / scale)
+    };
+    let rect = rect.intersect(&Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    })?;
+    let corner = |x: i32, y: i32| {
+        let (x, y) = match transform {
+            0 => (x, y),
+            1 => (height - y, x),
+            2 => (width - x, height - y),
+            3 => (y, width - x),
+            4 => (width - x, y),
+            5 => (height - y, width - x),
+            6 => (x, height - y),
+            _ => (y, x),
+        };
+        (x * scale, y * scale)
+    };
+    let (x1, y1) = corner(rect.x, rect.y);
+    let (x2, y2) = corner(rect.x + rect.width, rect.y + rect.height);
+    Some(Rect {
+        x: std::cmp::min(x1, x2),
+        y: std::cmp::min(y1, y2),
+        width: (x1 - x2).abs(),
+        height: (y1 - y2).abs(),
+    })
+}
+
+// The hashes of the tiles of a surface as vision last got them.
+#[derive(Default)]
+pub struct TileCache {
+    // width, height, stride and format of the buffer the hashes are for
+    layout: Option<(i32, i32, i32, u32)>,
+    hashes: Vec<Option<u64>>,
+}
+
+impl TileCache {
+    // The parts of the damage whose pixels changed, as rects in buffer
+    // coordinates with their rows. Changed tiles next to each other in a
+    // row of tiles go out as one rect.
+    pub fn changed(
+        &mut self,
+        buffer: &WlBuffer,
+        damage: &[Rect],
+    ) -> io::Result<Vec<(Rect, Vec<u8>)>> {
+        let bounds = Rect {
+            x: 0,
+            y: 0,
+            width: buffer.width,
+            height: buffer.height,
+        };
+        let columns = (buffer.width + TILE_SIZE - 1) / TILE_SIZE;
+        let rows = (buffer.height + TILE_SIZE - 1) / TILE_SIZE;
+        // Vision starts over with a buffer of another layout.
+        let layout = (buffer.width, buffer.height, buffer.stride, buffer.format);
+        let full = [bounds];
+        let damage = if self.layout == Some(layout) {
+            damage
+        } else {
+            self.layout = Some(layout);
+            self.hashes = vec![None; (columns * rows) as usize];
+            &full[..]
+        };
+
+        let mut damaged = vec![false; (columns * rows) as usize];
+        for rect in damage.iter().filter_map(|rect| rect.intersect(&bounds)) {
+            for row in rect.y / TILE_SIZE..=(rect.y + rect.height - 1) / TILE_SIZE {
+                for column in rect.x / TILE_SIZE..=(rect.x + rect.width - 1) / TILE_SIZE {
+                    damaged[(row * columns + column) as usize] = true;
+                }
+            }
+        }
+
+        let bytes_per_pixel = bytes_per_pixel(buffer.format).unwrap_or(4);
+        let mut changed = Vec::new();
+        for row in 0..rows {
+            let is_damaged = |column: &i32| damaged[(row * columns + column) as usize];
+            let first = match (0..columns).find(is_damaged) {
+                Some(first) => first,
+                None => continue,
+            };
+            let last = (0..columns).rev().find(is_damaged).unwrap();
+            // One read for the damaged part of the row of tiles.
+            let band = Rect {
+                x: first * TILE_SIZE,
+                y: row * TILE_SIZE,
+                width: std::cmp::min((last + 1) * TILE_SIZE, buffer.width) - first * TILE_SIZE,
+                height: std::cmp::min(TILE_SIZE, buffer.height - row * TILE_SIZE),
+            };
+            let band_pixels = read_rect(buffer, &band)?;
+
+            let mut run: Option<Rect> = None;
+            for column in first..=last {
+                let index = (row * columns + column) as usize;
+                let tile = Rect {
+                    x: column * TILE_SIZE,
+                    y: band.y,
+                    width: std::cmp::min(TILE_SIZE, buffer.width - column * TILE_SIZE),
+                    height: band.height,
+                };
+                let mut is_changed = false;
+                if damaged[index] {
+                    let mut hasher = DefaultHasher::new();
+                    for_each_row(&band_pixels, &band, &tile, bytes_per_pixel, |row| {
+                        hasher.write(row)
+                    });
+                    let hash = hasher.finish();
+                    is_changed = self.hashes[index] != Some(hash);
+                    self.hashes[index] = Some(hash);
+                }
+                if is_changed {
+                    run = Some(match run {
+                        Some(run) => Rect {
+                            width: run.width + tile.width,
+                            ..run
+                        },
+                        None => tile,
+                    });
+                } else if let Some(run) = run.take() {
+                    changed.push((run, crop(&band_pixels, &band, &run, bytes_per_pixel)));
+                }
+            }
+            if let Some(run) = run {
+                changed.push((run, crop(&band_pixels, &band, &run, bytes_per_pixel)));
+            }
+        }
+        Ok(changed)
+    }
+}
+
+// The rows of rect, which lies within band, out of the rows of band.
+fn for_each_row<F>(band_pixels: &[u8], band: &Rect, rect: &Rect, bytes_per_pixel: i32, mut f: F)
+where
+    F: FnMut(&[u8]),
+{
+    let band_row_len = (band.width * bytes_per_pixel) as usize;
+    let row_len = (rect.width * bytes_per_pixel) as usize;
+    let x = ((rect.x - band.x) * bytes_per_pixel) as usize;
+    for row in (rect.y - band.y)..(rect.y - band.y + rect.height) {
+        let start = row as usize * band_row_len + x;
+        f(&band_pixels[start..start + row_len]);
+    }
+}
+
+fn crop(band_pixels: &[u8], band: &Rect, rect: &Rect, bytes_per_pixel: i32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((rect.width * rect.height * bytes_per_pixel) as usize);
+    for_each_row(band_pixels, band, rect, bytes_per_pixel, |row| {
+        pixels.extend_from_slice(row)
+    });
+    pixels
+}
+
+// The rows of a rect of the buffer, without the rest of the stride.
+pub fn read_rect(buffer: &WlBuffer, rect: &Rect) -> io::Result<Vec<u8>> {
+    let bytes_per_pixel = bytes_per_pixel(buffer.format).unwrap_or(4);
+    // One read from the first pixel of the rect to the last, then keep the
+    // rows.
+    let row_len = (rect.width * bytes_per_pixel) as usize;
+    let stride = buffer.stride as usize;
+    let span = (rect.height as usize - 1) * stride + row_len;
+    let start =
+        buffer.offset as usize + rect.y as usize * stride + (rect.x * bytes_per_pixel) as usize;
+    let mut block = vec![0; span];
+    read_at(buffer.fd.as_raw_fd(), &mut block, start)?;
+    let mut pixels = Vec::with_capacity(row_len * rect.height as usize);
+    for row in 0..rect.height as usize {
+        pixels.extend_from_slice(&block[row * stride..row * stride + row_len]);
+    }
+    Ok(pixels)
+}
+
+// Each rect is run-length coded when the link allows it and that makes it
+// smaller.
+pub fn encode(
+    surface_id: u32,
+    buffer: &WlBuffer,
+    rects: &[(Rect, Vec<u8>)],
+    features: u32,
+) -> Vec<u8> {
+    let mut dst = vec![0; PIXELS_HEADER_SIZE];
+    NativeEndian::write_u32(&mut dst[0..], surface_id);
+    NativeEndian::write_u32(&mut dst[4..], buffer.format);
+    NativeEndian::write_u32(&mut dst[8..], buffer.width as u32);
+    NativeEndian::write_u32(&mut dst[12..], buffer.height as u32);
+    NativeEndian::write_u32(&mut dst[16..], buffer.stride as u32);
+    NativeEndian::write_u32(&mut dst[20..], rects.len() as u32);
+    for (rect, pixels) in rects {
+        let coded = if features & link::FEATURE_RLE != 0 {
+            Some(rle::encode(pixels)).filter(|coded| coded.len() < pixels.len())
+        } else {
+            None
+        };
+        let (encoding, data) = match &coded {
+            Some(coded) => (ENCODING_RLE, coded),
+            None => (ENCODING_RAW, pixels),
+        };
+        let mut header = [0; RECT_HEADER_SIZE];
+        NativeEndian::write_i32(&mut header[0..], rect.x);
+        NativeEndian::write_i32(&mut header[4..], rect.y);
+        NativeEndian::write_i32(&mut header[8..], rect.width);
+        NativeEndian::write_i32(&mut header[12..], rect.height);
+        NativeEndian::write_u32(&mut header[16..], encoding);
+        NativeEndian::write_u32(&mut header[20..], data.len() as u32);
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(data);
+    }
+    dst
+}
+
+// Read with pread rather than through a mapping of the pool: a client that
+// shrinks the file underneath would otherwise take all of reflex down with
+// SIGBUS.
+pub fn read_at(fd: i32, buf: &mut [u8], offset: usize) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let n = unsafe {
+            libc::pread(
+                fd,
+                buf[done..].as_mut_ptr() as *mut libc::c_void,
+                buf.len() - done,
+                (offset + done) as libc::off_t,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "buffer lies outside of the pool file",
+            ));
+        }
+        done += n as usize;
+    }
+    Ok(())
+}
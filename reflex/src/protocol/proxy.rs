@@ -0,0 +1,310 @@
+use crate::protocol::event_sink::MAX_FDS;
+use crate::protocol::fd_drop::FdDrop;
+use crate::protocol::trace::ObjectTracker;
+use byteorder::{ByteOrder, NativeEndian};
+use futures::future::Future;
+use nix::sys::socket::*;
+use nix::sys::uio::IoVec;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::prelude::Async;
+use tokio::reactor::Registration;
+
+const HEADER_SIZE: usize = 8;
+const READ_SIZE: usize = 64 * 1024;
+
+// Proxy mode: instead of being served by reflex, every client is connected
+// to another compositor, and what goes either way is passed through as it
+// is, fds included, and traced like the messages of reflex itself.
+
+// The socket of the compositor: a path, or a name in XDG_RUNTIME_DIR as in
+// WAYLAND_DISPLAY.
+pub fn socket_path(display: &str) -> Result<PathBuf, String> {
+    let path = Path::new(display);
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|runtime_dir| PathBuf::from(runtime_dir).join(path))
+        .ok_or_else(|| "XDG_RUNTIME_DIR is not set".to_owned())
+}
+
+pub fn handle_client(fd: RawFd, upstream: &Path, client_id: u32) {
+    let client = match Socket::new(fd) {
+        Ok(client) => client,
+        Err(err) => {
+            log::warn!("[Proxy] client {} not registered: {}", client_id, err);
+            return;
+        }
+    };
+    let compositor = match UnixStream::connect(upstream)
+        .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
+        .and_then(|stream| Socket::new(stream.into_raw_fd()))
+    {
+        Ok(compositor) => compositor,
+        Err(err) => {
+            log::warn!(
+                "[Proxy] client {} not connected to {}: {}",
+                client_id,
+                upstream.display(),
+                err
+            );
+            return;
+        }
+    };
+    log::info!("[Proxy] client {} connected", client_id);
+
+    let tracker = Arc::new(Mutex::new(ObjectTracker::new()));
+    let requests = Pump::new(client.clone(), compositor.clone(), tracker.clone(), true);
+    let events = Pump::new(compositor, client, tracker, false);
+    tokio::spawn(crate::logger::with_client(
+        client_id,
+        requests.join(events).then(move |_| {
+            log::info!("[Proxy] client {} disconnected", client_id);
+            Ok(())
+        }),
+    ));
+}
+
+// A socket that both pumps use, one for reading and one for writing.
+#[derive(Clone)]
+struct Socket {
+    fd: RawFd,
+    _fd_drop: Arc<FdDrop>,
+    tokio_registration: Arc<Registration>,
+}
+
+impl Socket {
+    fn new(fd: RawFd) -> std::io::Result<Socket> {
+        let fd_drop = Arc::new(FdDrop::new(fd));
+        let tokio_registration = Arc::new(Registration::new());
+        tokio_registration.register(&mio::unix::EventedFd(&fd))?;
+        Ok(Socket {
+            fd,
+            _fd_drop: fd_drop,
+            tokio_registration,
+        })
+    }
+}
+
+// Moves what one side sends to the other: each recvmsg is sent on with one
+// sendmsg, fds and all, before the next one is made, so that a side that
+// does not read holds up the one that writes to it. At the end of what it
+// reads, it shuts down the writing half of the other socket: the other side
+// sees the end in turn, while what it still sends goes through the other
+// pump. On errors both sockets are shut down, ending both pumps.
+struct Pump {
+    from: Socket,
+    to: Socket,
+    // Received and not yet sent on.
+    pending_bytes: Vec<u8>,
+    pending_fds: Vec<RawFd>,
+    // Received and not yet traced, up to the end of the last whole message.
+    trace_bytes: Vec<u8>,
+    trace_fds: Vec<RawFd>,
+    tracing: bool,
+    tracker: Arc<Mutex<ObjectTracker>>,
+    // From the client rather than from the compositor.
+    requests: bool,
+    msg_control: Vec<u8>,
+}
+
+impl Pump {
+    fn new(from: Socket, to: Socket, tracker: Arc<Mutex<ObjectTracker>>, requests: bool) -> Pump {
+        let cmsg_space =
+            unsafe { libc::CMSG_SPACE((std::mem::size_of::<RawFd>() * MAX_FDS) as u32) };
+        Pump {
+            from,
+            to,
+            pending_bytes: Vec::new(),
+            pending_fds: Vec::new(),
+            trace_bytes: Vec::new(),
+            trace_fds: Vec::new(),
+            tracing: true,
+            tracker,
+            requests,
+            msg_control: vec![0; cmsg_space as usize],
+        }
+    }
+
+    // One recvmsg. Returns the number of bytes received, 0 at the end of
+    // the stream.
+    fn receive(&mut self) -> nix::Result<usize> {
+        let mut buf = vec![0; READ_SIZE];
+        let (bytes, fds, truncated) = {
+            let iov = [IoVec::from_mut_slice(&mut buf)];
+            let msg = recvmsg(
+                self.from.fd,
+                &iov,
+                Some(&mut self.msg_control),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            )?;
+            let mut fds = Vec::new();
+            for cmsg in msg.cmsgs() {
+                if let ControlMessageOwned::ScmRights(received) = cmsg {
+                    fds.extend(received);
+                }
+            }
+            (msg.bytes, fds, msg.flags.contains(MsgFlags::MSG_CTRUNC))
+        };
+        if truncated {
+            // The fds that did not fit are lost, and the messages that carry
+            // them can no longer be matched with theirs.
+            for fd in fds {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+            return Err(nix::Error::Sys(nix::errno::Errno::EMSGSIZE));
+        }
+        buf.truncate(bytes);
+        self.trace(&buf, &fds);
+        self.pending_bytes = buf;
+        self.pending_fds = fds;
+        Ok(bytes)
+    }
+
+    // Trace every message made whole by what was received.
+    fn trace(&mut self, bytes: &[u8], fds: &[RawFd]) {
+        if !self.tracing {
+            return;
+        }
+        self.trace_bytes.extend_from_slice(bytes);
+        self.trace_fds.extend_from_slice(fds);
+        let mut tracker = self.tracker.lock().unwrap();
+        let mut start = 0;
+        while self.trace_bytes.len() - start >= HEADER_SIZE {
+            let message_size =
+                (NativeEndian::read_u32(&self.trace_bytes[start + 4..]) >> 16) as usize;
+            if message_size < HEADER_SIZE {
+                // Passed on all the same, but no longer traced.
+                log::warn!(
+                    "[Proxy] invalid message size {}, no longer traced",
+                    message_size
+                );
+                self.tracing = false;
+                return;
+            }
+            if self.trace_bytes.len() - start < message_size {
+                break;
+            }
+            let message = &self.trace_bytes[start..start + message_size];
+            let fds_taken = if self.requests {
+                tracker.request(message, &self.trace_fds)
+            } else {
+                tracker.event(message, &self.trace_fds)
+            };
+            self.trace_fds.drain(..fds_taken);
+            start += message_size;
+        }
+        self.trace_bytes.drain(..start);
+    }
+
+    // One sendmsg of what is pending, the fds going with the first byte.
+    fn send(&mut self) -> nix::Result<()> {
+        let iov = [IoVec::from_slice(&self.pending_bytes)];
+        let sent = if self.pending_fds.is_empty() {
+            sendmsg(self.to.fd, &iov, &[], MsgFlags::empty(), None)?
+        } else {
+            let cmsgs = [ControlMessage::ScmRights(&self.pending_fds)];
+            sendmsg(self.to.fd, &iov, &cmsgs, MsgFlags::empty(), None)?
+        };
+        self.close_fds();
+        self.pending_bytes.drain(..sent);
+        Ok(())
+    }
+
+    // Ours are copies of the fds passed on.
+    fn close_fds(&mut self) {
+        for fd in self.pending_fds.drain(..) {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.close_fds();
+        let _ = shutdown(self.from.fd, Shutdown::Both);
+        let _ = shutdown(self.to.fd, Shutdown::Both);
+    }
+
+    // Nothing more comes from this side, nor goes to the other.
+    fn finish(&mut self) {
+        let _ = shutdown(self.from.fd, Shutdown::Read);
+        let _ = shutdown(self.to.fd, Shutdown::Write);
+    }
+}
+
+impl Future for Pump {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Result<Async<()>, ()> {
+        loop {
+            if !self.pending_bytes.is_empty() {
+                let ready = match self.to.tokio_registration.poll_write_ready() {
+                    Ok(Async::Ready(ready)) => ready.is_writable(),
+                    Ok(Async::NotReady) => false,
+                    Err(err) => {
+                        log::warn!("[Proxy] write err {:?}", err);
+                        self.shutdown();
+                        return Err(());
+                    }
+                };
+                match self.send() {
+                    Ok(()) => continue,
+                    Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {
+                        // A readiness that found the socket full was spent,
+                        // so poll again to be woken by the next one.
+                        if ready {
+                            continue;
+                        }
+                        return Ok(Async::NotReady);
+                    }
+                    Err(err) => {
+                        log::warn!("[Proxy] write err {:?}", err);
+                        self.shutdown();
+                        return Err(());
+                    }
+                }
+            }
+
+            let ready = match self.from.tokio_registration.poll_read_ready() {
+                Ok(Async::Ready(ready)) => ready.is_readable(),
+                Ok(Async::NotReady) => false,
+                Err(err) => {
+                    log::warn!("[Proxy] read err {:?}", err);
+                    self.shutdown();
+                    return Err(());
+                }
+            };
+            match self.receive() {
+                Ok(0) => {
+                    log::debug!("[Proxy] EOF");
+                    self.finish();
+                    return Ok(Async::Ready(()));
+                }
+                Ok(read) => log::debug!("[Proxy] received {} bytes", read),
+                Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => {
+                    if !ready {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                Err(err) => {
+                    log::warn!("[Proxy] read err {:?}", err);
+                    self.shutdown();
+                    return Err(());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Pump {
+    fn drop(&mut self) {
+        self.close_fds();
+    }
+}
@@ -0,0 +1,348 @@
+use super::flow::{Pixels, Tiles};
+use super::link;
+use super::pixels;
+use super::resource::Resource;
+use byteorder::{ByteOrder, NativeEndian};
+use std::collections::{BTreeMap, HashMap};
+
+// What vision needs to rebuild the windows of every client after it has
+// restarted: the requests that created the objects still alive and the
+// latest of the requests that set their state, in the order they were sent.
+// Transient requests such as damage or pong are not kept.
+pub struct Journal {
+    next_seq: u64,
+    // Those of the link the frames go out on: set by each replay, so that
+    // pixels encoded later match the link they follow the replay on.
+    features: u32,
+    clients: BTreeMap<u32, ClientJournal>,
+}
+
+#[derive(Default)]
+struct ClientJournal {
+    // seq -> Wayland messages
+    entries: BTreeMap<u64, Vec<u8>>,
+    // Objects by the seq of the request that created them, so that a dead
+    // object survives the client reusing its id.
+    objects: HashMap<u64, JournalObject>,
+    live: HashMap<u32, u64>,
+    // (link id, opcode) -> seq of a state request, replaced by the next one
+    // with the same opcode.
+    state: HashMap<(u32, u16), u64>,
+    // wl_surface.attach waiting for the commit that applies it.
+    pending_attach: HashMap<u32, Vec<u8>>,
+    // The attach in effect on each surface and the buffer it holds.
+    attached: HashMap<u32, (Vec<u8>, Option<u64>)>,
+    // The pixels each surface shows, as they were committed: the client
+    // may draw into its buffers again once they are released.
+    pixels: HashMap<u32, Tiles>,
+}
+
+struct JournalObject {
+    parent: Option<u64>,
+    // Objects created by this one and surfaces showing it. While there are
+    // any, a destroyed object is replayed as created and then destroyed, so
+    // that e.g. a buffer can still be created from its pool.
+    references: usize,
+    alive: bool,
+    destroyed: Option<u64>,
+}
+
+const COMMIT_OPCODE: u16 = 6;
+
+enum Kind {
+    // The link id of the new object is at this byte offset.
+    Create(usize),
+    State,
+    Attach,
+    Commit,
+    Transient,
+}
+
+fn kind(resource: &Resource, opcode: u16, message: &[u8]) -> Kind {
+    match (resource, opcode) {
+        (Resource::WlDisplay(_), 1)
+        | (Resource::WlCompositor(_), 0)
+        | (Resource::WlCompositor(_), 1)
+        | (Resource::WlShm(_), 0)
+        | (Resource::WlShmPool(_), 0)
+        | (Resource::WlSubcompositor(_), 1)
+        | (Resource::XdgWmBase(_), 1)
+        | (Resource::XdgWmBase(_), 2)
+        | (Resource::XdgSurface(_), 1)
+        | (Resource::XdgSurface(_), 2) => Kind::Create(8),
+        // wl_registry.bind(name, interface, version, id)
+        (Resource::WlRegistry(_), 0) => Kind::Create(message.len() - 4),
+        (Resource::WlSurface(_), 1) => Kind::Attach,
+        (Resource::WlSurface(_), COMMIT_OPCODE) => Kind::Commit,
+        (Resource::WlSurface(_), 7)
+        | (Resource::WlSurface(_), 8)
+        | (Resource::WlShmPool(_), 2)
+        | (Resource::XdgPositioner(_), _)
+        | (Resource::XdgSurface(_), 3)
+        | (Resource::XdgToplevel(_), 2)
+        | (Resource::XdgToplevel(_), 3)
+        | (Resource::XdgToplevel(_), 7)
+        | (Resource::XdgToplevel(_), 8) => Kind::State,
+        _ => Kind::Transient,
+    }
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal {
+            next_seq: 1,
+            features: 0,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    // Returns the seq of the CONNECTED frame, which the replay covers.
+    pub fn add_client(&mut self, client_id: u32) -> u64 {
+        self.clients.insert(client_id, ClientJournal::default());
+        self.next_seq += 1;
+        self.next_seq - 1
+    }
+
+    pub fn remove_client(&mut self, client_id: u32) {
+        self.clients.remove(&client_id);
+    }
+
+    // Called with each relayed message after it has been dispatched, with
+    // the sender or None when the request destroyed it. Returns the seq of
+    // the journal entry that covers the message, if any.
+    pub fn record(
+        &mut self,
+        client_id: u32,
+        sender: Option<&Resource>,
+        message: &[u8],
+    ) -> Option<u64> {
+        let seq = self.next_seq;
+        let client = self.clients.get_mut(&client_id)?;
+        let sender_id = NativeEndian::read_u32(&message[0..]);
+        let opcode = (NativeEndian::read_u32(&message[4..]) & 0xffff) as u16;
+        let journaled = match sender {
+            Some(sender) => client.record(sender, sender_id, opcode, seq, message),
+            None => client.forget(sender_id, seq, message),
+        };
+        if journaled {
+            self.next_seq += 1;
+            Some(seq)
+        } else {
+            None
+        }
+    }
+
+    // Pixels sent ahead of a commit of the surface.
+    pub fn record_pixels(
+        &mut self,
+        client_id: u32,
+        surface_id: u32,
+        pixels: Pixels,
+    ) -> Option<u64> {
+        let client = self.clients.get_mut(&client_id)?;
+        let (buffer, rects) = pixels;
+        match client.pixels.get_mut(&surface_id) {
+            Some(tiles) => tiles.add(buffer, rects),
+            None => {
+                client.pixels.insert(surface_id, Tiles::new(buffer, rects));
+            }
+        }
+        self.next_seq += 1;
+        Some(self.next_seq - 1)
+    }
+
+    pub fn features(&self) -> u32 {
+        self.features
+    }
+
+    // A seq for a frame whose contents the journal already covers, so that
+    // it is dropped if a replay gets out first.
+    pub fn covered_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq - 1
+    }
+
+    // The frames that rebuild every client on a fresh link with the given
+    // features, and the last seq they cover.
+    pub fn replay(&mut self, features: u32) -> (Vec<Vec<u8>>, u64) {
+        self.features = features;
+        let mut frames = Vec::new();
+        for (client_id, client) in &self.clients {
+            frames.push(link::request_frame(
+                link::REQUEST_CLIENT_CONNECTED,
+                *client_id,
+                &[],
+            ));
+            let commits: HashMap<u64, u32> = client
+                .state
+                .iter()
+                .filter(|((_, opcode), _)| *opcode == COMMIT_OPCODE)
+                .map(|((surface_id, _), seq)| (*seq, *surface_id))
+                .collect();
+            let mut messages = Vec::new();
+            for (seq, entry) in &client.entries {
+                let tiles = commits
+                    .get(seq)
+                    .and_then(|surface_id| Some((*surface_id, client.pixels.get(surface_id)?)));
+                let (surface_id, tiles) = match tiles {
+                    Some(tiles) => tiles,
+                    None => {
+                        messages.extend_from_slice(entry);
+                        continue;
+                    }
+                };
+                // The commit is the last message of the entry.
+                let (attach, commit) = entry.split_at(entry.len() - 8);
+                messages.extend_from_slice(attach);
+                frames.push(link::request_frame(
+                    link::REQUEST_MESSAGES,
+                    *client_id,
+                    &messages,
+                ));
+                messages.clear();
+                let data = pixels::encode(surface_id, tiles.buffer(), &tiles.rects(), features);
+                frames.push(link::request_frame(link::REQUEST_PIXELS, *client_id, &data));
+                messages.extend_from_slice(commit);
+            }
+            if !messages.is_empty() {
+                frames.push(link::request_frame(
+                    link::REQUEST_MESSAGES,
+                    *client_id,
+                    &messages,
+                ));
+            }
+        }
+        (frames, self.next_seq - 1)
+    }
+}
+
+impl ClientJournal {
+    fn record(
+        &mut self,
+        sender: &Resource,
+        sender_id: u32,
+        opcode: u16,
+        seq: u64,
+        message: &[u8],
+    ) -> bool {
+        match kind(sender, opcode, message) {
+            Kind::Create(offset) => {
+                let id = NativeEndian::read_u32(&message[offset..]);
+                let parent = self.live.get(&sender_id).cloned();
+                if let Some(parent) = parent {
+                    self.objects.get_mut(&parent).unwrap().references += 1;
+                }
+                self.objects.insert(
+                    seq,
+                    JournalObject {
+                        parent,
+                        references: 0,
+                        alive: true,
+                        destroyed: None,
+                    },
+                );
+                self.live.insert(id, seq);
+                self.entries.insert(seq, message.to_vec());
+                true
+            }
+            Kind::State => {
+                self.replace_state(sender_id, opcode, seq, message.to_vec());
+                true
+            }
+            Kind::Attach => {
+                self.pending_attach.insert(sender_id, message.to_vec());
+                false
+            }
+            Kind::Commit => {
+                if let Some(attach) = self.pending_attach.remove(&sender_id) {
+                    let buffer = self
+                        .live
+                        .get(&NativeEndian::read_u32(&attach[8..]))
+                        .cloned();
+                    if let Some(buffer) = buffer {
+                        self.objects.get_mut(&buffer).unwrap().references += 1;
+                    }
+                    if let Some((_, Some(old))) = self.attached.insert(sender_id, (attach, buffer))
+                    {
+                        self.release(old);
+                    }
+                }
+                let mut messages = match self.attached.get(&sender_id) {
+                    Some((attach, Some(buffer))) if self.objects[buffer].alive => attach.clone(),
+                    _ => Vec::new(),
+                };
+                messages.extend_from_slice(message);
+                self.replace_state(sender_id, opcode, seq, messages);
+                true
+            }
+            Kind::Transient => false,
+        }
+    }
+
+    fn replace_state(&mut self, id: u32, opcode: u16, seq: u64, messages: Vec<u8>) {
+        if let Some(old) = self.state.insert((id, opcode), seq) {
+            self.entries.remove(&old);
+        }
+        self.entries.insert(seq, messages);
+    }
+
+    // The object has been destroyed by `message`.
+    fn forget(&mut self, id: u32, seq: u64, message: &[u8]) -> bool {
+        let state: Vec<(u32, u16)> = self
+            .state
+            .keys()
+            .filter(|(object_id, _)| *object_id == id)
+            .cloned()
+            .collect();
+        for key in state {
+            if let Some(old) = self.state.remove(&key) {
+                self.entries.remove(&old);
+            }
+        }
+        self.pending_attach.remove(&id);
+        self.pixels.remove(&id);
+        if let Some((_, Some(buffer))) = self.attached.remove(&id) {
+            self.release(buffer);
+        }
+
+        let key = match self.live.remove(&id) {
+            Some(key) => key,
+            None => return false,
+        };
+        let object = self.objects.get_mut(&key).unwrap();
+        object.alive = false;
+        let journaled = object.references > 0;
+        if journaled {
+            object.destroyed = Some(seq);
+            self.entries.insert(seq, message.to_vec());
+        }
+        self.collect(key);
+        journaled
+    }
+
+    fn release(&mut self, key: u64) {
+        if let Some(object) = self.objects.get_mut(&key) {
+            object.references -= 1;
+        }
+        self.collect(key);
+    }
+
+    // Drop a dead object once nothing refers to it any more.
+    fn collect(&mut self, key: u64) {
+        let collectable = match self.objects.get(&key) {
+            Some(object) => !object.alive && object.references == 0,
+            None => false,
+        };
+        if !collectable {
+            return;
+        }
+        let object = self.objects.remove(&key).unwrap();
+        self.entries.remove(&key);
+        if let Some(destroyed) = object.destroyed {
+            self.entries.remove(&destroyed);
+        }
+        if let Some(parent) = object.parent {
+            self.release(parent);
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use std::os::unix::io::RawFd;
+use bytes::Bytes;
 
 #[derive(Debug)]
 pub struct Request {
     pub sender_object_id: u32,
     pub opcode: u16,
-    pub args: Vec<u8>,
-    pub fds: Vec<RawFd>,
+    // A slice of the receive buffer of the stream.
+    pub args: Bytes,
 }
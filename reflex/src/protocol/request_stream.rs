@@ -1,21 +1,38 @@
 use crate::protocol::fd_drop::FdDrop;
+use crate::protocol::fd_queue::{FdQueue, MAX_CLIENT_FDS};
 use crate::protocol::request::Request;
-use byteorder::{NativeEndian, ReadBytesExt};
+use byteorder::{ByteOrder, NativeEndian};
+use bytes::{BufMut, BytesMut};
 use futures::stream::Stream;
-use std::io::{Cursor, Read};
+use std::collections::VecDeque;
+use std::os::raw::{c_int, c_void};
 use std::os::unix::io::RawFd;
 use std::sync::Arc;
 use tokio::prelude::Async;
 use tokio::reactor::Registration;
 
+const HEADER_SIZE: usize = 8;
+// Room for a full batch of messages, and for the largest message the wire
+// format allows.
+const BUFFER_SIZE: usize = 64 * 1024;
+// The free space made before every receive.
+const READ_SIZE: usize = 16 * 1024;
+// The fds one sendmsg of libwayland carries at most.
+const MAX_FDS: usize = 28;
+
+// Reads requests in batches: a readiness event is answered by one recvmsg
+// that takes as much as the buffer holds, and every complete message in it
+// is split off the buffer without copying the arguments. The buffer is
+// reused once the requests handed out of it have been dropped.
 pub struct RequestStream {
     fd: RawFd,
     _fd_drop: Arc<FdDrop>,
     tokio_registration: Arc<tokio::reactor::Registration>,
     //_tokio_stream: Arc<UnixStream>,
-    pending_bytes: Vec<u8>,
-    pending_fds: Vec<RawFd>,
-    pending_requests: Vec<Request>,
+    pending_bytes: BytesMut,
+    fds: FdQueue,
+    pending_requests: VecDeque<Request>,
+    msg_control: Vec<u8>,
 }
 
 impl RequestStream {
@@ -23,248 +40,156 @@ impl RequestStream {
         fd: RawFd,
         fd_drop: Arc<FdDrop>,
         tokio_registration: Arc<Registration>,
+        fds: FdQueue,
     ) -> RequestStream {
+        let cmsg_space =
+            unsafe { libc::CMSG_SPACE((std::mem::size_of::<c_int>() * MAX_FDS) as u32) };
         RequestStream {
             fd,
             _fd_drop: fd_drop,
             tokio_registration,
             //_tokio_stream: tokio_stream,
-            pending_bytes: Vec::new(),
-            pending_fds: Vec::new(),
-            pending_requests: Vec::new(),
+            pending_bytes: BytesMut::with_capacity(BUFFER_SIZE),
+            fds,
+            pending_requests: VecDeque::new(),
+            msg_control: vec![0; cmsg_space as usize],
         }
     }
-}
-
-impl Stream for RequestStream {
-    type Item = Request;
-    type Error = ();
-
-    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        println!(
-            "[Stream] poll: pending_requests={}",
-            self.pending_requests.len()
-        );
-
-        if !self.pending_requests.is_empty() {
-            let rest = self.pending_requests.split_off(1);
-            let first = self.pending_requests.pop().expect("pending requests error");
-            self.pending_requests = rest;
-            println!("[Stream] return pending request {:?}", first);
-            return Ok(Async::Ready(Some(first)));
-        }
 
-        match self.tokio_registration.poll_read_ready() {
-            Ok(Async::Ready(ready)) if ready.is_readable() => {
-                println!("[Stream] read ready");
-            }
-            Ok(Async::Ready(_)) => {
-                println!("[Stream] read ready ERROR");
-                //return Ok(Async::NotReady);
-            }
-            Ok(Async::NotReady) => {
-                println!("[Stream] read not ready");
-                //return Ok(Async::NotReady);
-            }
-            Err(e) => {
-                println!("[Stream] err {:?}", e);
-                return Err(());
+    // One recvmsg into the free space of the buffer. Returns the number of
+    // bytes received, 0 at the end of the stream.
+    fn receive(&mut self) -> std::io::Result<usize> {
+        self.pending_bytes.reserve(READ_SIZE);
+        unsafe {
+            let free = self.pending_bytes.bytes_mut();
+            let mut io_vec = libc::iovec {
+                iov_len: free.len(),
+                iov_base: free.as_mut_ptr() as *mut c_void,
+            };
+            let mut msg_hdr = libc::msghdr {
+                msg_name: std::ptr::null_mut(),
+                msg_namelen: 0,
+                msg_iov: &mut io_vec,
+                msg_iovlen: 1,
+                msg_control: self.msg_control.as_mut_ptr() as *mut c_void,
+                msg_controllen: self.msg_control.len(),
+                msg_flags: 0,
+            };
+            let read = libc::recvmsg(self.fd, &mut msg_hdr, libc::MSG_CMSG_CLOEXEC);
+            if read < 0 {
+                return Err(std::io::Error::last_os_error());
             }
-        }
-
-        let mut received_fds: Vec<RawFd> = Vec::new();
-        let mut buf: Vec<u8> = Vec::new();
-        buf.resize(16, 0);
-        let mut fd_len = 8;
-        loop {
-            let buf_len = buf.len();
-
-            unsafe {
-                let cmsg_space = std::cmp::max(
-                    libc::CMSG_SPACE(std::mem::size_of::<std::os::raw::c_int>() as u32 * fd_len),
-                    std::mem::size_of::<libc::cmsghdr>() as u32,
-                );
-                {
-                    let mut msg_control: Vec<u8> = Vec::new();
-                    msg_control.resize(cmsg_space as usize, 0);
-
-                    let mut io_vec = libc::iovec {
-                        iov_len: buf.len(),
-                        iov_base: buf.as_mut_ptr() as *mut std::os::raw::c_void,
-                    };
-                    let mut msg_hdr = libc::msghdr {
-                        msg_name: std::ptr::null_mut(),
-                        msg_namelen: 0,
-                        msg_iov: &mut io_vec,
-                        msg_iovlen: 1,
-                        msg_control: msg_control.as_mut_ptr() as *mut std::os::raw::c_void,
-                        msg_controllen: msg_control.len(),
-                        msg_flags: 0,
-                    };
-                    let read = libc::recvmsg(self.fd, &mut msg_hdr, libc::MSG_PEEK);
-                    if read < 0 {
-                        let errno = *libc::__errno_location();
-                        if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK {
-                            println!("[Stream] EAGAIN not ready");
-                            return Ok(Async::NotReady);
-                        }
-                        println!("[Stream] err {}", errno);
-                        return Err(());
-                    }
-                    if (msg_hdr.msg_flags & libc::MSG_TRUNC) != 0 {
-                        buf.resize(buf.len() * 2, 0);
-                        continue;
-                    }
-                    if (msg_hdr.msg_flags & libc::MSG_CTRUNC) != 0 {
-                        fd_len *= 2;
-                        continue;
-                    }
-                    if read as usize == buf_len {
-                        buf.resize(buf.len() * 2, 0);
-                        continue;
-                    }
-                }
-                {
-                    let mut msg_control: Vec<u8> = Vec::new();
-                    msg_control.resize(cmsg_space as usize, 0);
-
-                    let mut io_vec = libc::iovec {
-                        iov_len: buf.len(),
-                        iov_base: buf.as_mut_ptr() as *mut std::os::raw::c_void,
-                    };
-                    let mut msg_hdr = libc::msghdr {
-                        msg_name: std::ptr::null_mut(),
-                        msg_namelen: 0,
-                        msg_iov: &mut io_vec,
-                        msg_iovlen: 1,
-                        msg_control: msg_control.as_mut_ptr() as *mut std::os::raw::c_void,
-                        msg_controllen: msg_control.len(),
-                        msg_flags: 0,
-                    };
-                    let read = libc::recvmsg(self.fd, &mut msg_hdr, 0);
-                    if read < 0 {
-                        let errno = *libc::__errno_location();
-                        if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK {
-                            println!("[Stream] EAGAIN not ready");
-                            return Ok(Async::NotReady);
-                        }
-                        println!("[Stream] err {}", errno);
-                        return Err(());
-                    }
-                    if (msg_hdr.msg_flags & libc::MSG_TRUNC) != 0 {
-                        println!("[Stream] Oops MSG_TRUNC");
-                    }
-                    if (msg_hdr.msg_flags & libc::MSG_CTRUNC) != 0 {
-                        println!("[Stream] Oops MSG_CTRUNC");
-                    }
-
-                    buf.resize(read as usize, 0);
-
-                    let mut cmsg_hdr = libc::CMSG_FIRSTHDR(&msg_hdr);
-                    while !cmsg_hdr.is_null() {
-                        println!("[Stream] cmsg");
-                        let cmsg_level = (*cmsg_hdr).cmsg_level;
-                        let cmsg_type = (*cmsg_hdr).cmsg_type;
-                        if cmsg_level == libc::SOL_SOCKET && cmsg_type == libc::SCM_RIGHTS {
-                            println!("[Stream] SCM_RIGHTS cmsg_len={}", (*cmsg_hdr).cmsg_len);
-                            let received_fds_ptr =
-                                libc::CMSG_DATA(cmsg_hdr) as *mut std::os::raw::c_int;
-                            let mut received_fds_len = 1;
-                            while libc::CMSG_LEN(
-                                std::mem::size_of::<std::os::raw::c_int>() as u32
-                                    * received_fds_len,
-                            ) <= (*cmsg_hdr).cmsg_len as u32
-                            {
-                                println!(
-                                    "[Stream] SCM_RIGHTS  vs {}",
-                                    libc::CMSG_LEN(
-                                        std::mem::size_of::<std::os::raw::c_int>() as u32
-                                            * received_fds_len
-                                    )
-                                );
-                                received_fds_len += 1;
-                            }
-                            received_fds_len -= 1;
-                            println!("[Stream] len={}", received_fds_len);
-                            for offset in 0..received_fds_len {
-                                received_fds.push(*received_fds_ptr.offset(offset as isize));
-                            }
-                        } else {
-                            println!(
-                                "[Stream] UNHANDLED CMSG: level={} type={}",
-                                cmsg_level, cmsg_type
-                            );
+            self.pending_bytes.advance_mut(read as usize);
+
+            let mut over_cap = false;
+            let mut cmsg_hdr = libc::CMSG_FIRSTHDR(&msg_hdr);
+            while !cmsg_hdr.is_null() {
+                let cmsg_level = (*cmsg_hdr).cmsg_level;
+                let cmsg_type = (*cmsg_hdr).cmsg_type;
+                if cmsg_level == libc::SOL_SOCKET && cmsg_type == libc::SCM_RIGHTS {
+                    let received_fds_ptr = libc::CMSG_DATA(cmsg_hdr) as *const c_int;
+                    let received_fds_len = ((*cmsg_hdr).cmsg_len as usize
+                        - libc::CMSG_LEN(0) as usize)
+                        / std::mem::size_of::<c_int>();
+                    log::debug!("[Stream] received {} fds", received_fds_len);
+                    for offset in 0..received_fds_len {
+                        if self.fds.push(*received_fds_ptr.add(offset)).is_err() {
+                            over_cap = true;
                         }
-                        cmsg_hdr = libc::CMSG_NXTHDR(&msg_hdr, cmsg_hdr);
                     }
+                } else {
+                    log::warn!(
+                        "[Stream] UNHANDLED CMSG: level={} type={}",
+                        cmsg_level,
+                        cmsg_type
+                    );
                 }
+                cmsg_hdr = libc::CMSG_NXTHDR(&msg_hdr, cmsg_hdr);
             }
-
-            break;
-        }
-
-        println!("[Stream] data={:?} fds={:?}", buf, received_fds);
-        self.pending_bytes.extend(buf);
-        self.pending_fds.extend(received_fds);
-
-        let header_size = 8;
-        loop {
-            if self.pending_bytes.len() < header_size {
-                println!(
-                    "[Stream] break pending_bytes={} < header_size={}",
-                    self.pending_bytes.len(),
-                    header_size
-                );
-                break;
+            if (msg_hdr.msg_flags & libc::MSG_CTRUNC) != 0 {
+                // The fds that did not fit are lost, and the requests that
+                // carry them can no longer be matched with theirs.
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "too many fds in one message",
+                ));
+            }
+            if over_cap {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("more than {} fds held", MAX_CLIENT_FDS),
+                ));
             }
+            Ok(read as usize)
+        }
+    }
 
-            // https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Wire-Format
-            let mut cursor = Cursor::new(&self.pending_bytes);
-            let sender_object_id = cursor.read_u32::<NativeEndian>().unwrap();
-            let message_size_and_opcode = cursor.read_u32::<NativeEndian>().unwrap();
+    // Splits every complete message off the buffer.
+    fn decode(&mut self) -> Result<(), ()> {
+        // https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Wire-Format
+        while self.pending_bytes.len() >= HEADER_SIZE {
+            let sender_object_id = NativeEndian::read_u32(&self.pending_bytes[0..]);
+            let message_size_and_opcode = NativeEndian::read_u32(&self.pending_bytes[4..]);
             let message_size = (message_size_and_opcode >> 16) as usize;
+            if message_size < HEADER_SIZE {
+                log::warn!("[Stream] invalid message size {}", message_size);
+                return Err(());
+            }
             if self.pending_bytes.len() < message_size {
-                println!(
-                    "[Stream] break pending_bytes={} < message_size={}",
-                    self.pending_bytes.len(),
-                    message_size
-                );
                 break;
             }
-
             let opcode = (0x0000_ffff & message_size_and_opcode) as u16;
-            if message_size < header_size {
-                return Err(());
-            }
-            let mut args = Vec::new();
-            args.resize(message_size - header_size, 0);
-            cursor.read_exact(&mut args).unwrap();
-            println!(
-                "[Stream] decode: id={} opcode={} args={:?} fds={:?}",
-                sender_object_id, opcode, &args, &self.pending_fds
-            );
-            let fds = self.pending_fds.clone();
-            self.pending_fds.clear();
-            self.pending_bytes = self.pending_bytes.split_off(message_size);
-            self.pending_requests.push(Request {
+            let message = self.pending_bytes.split_to(message_size).freeze();
+            self.pending_requests.push_back(Request {
                 sender_object_id,
                 opcode,
-                args,
-                fds,
+                args: message.slice_from(HEADER_SIZE),
             });
         }
+        Ok(())
+    }
+}
 
-        if self.pending_requests.is_empty() {
-            println!("[Stream] pending request is empty");
-            return Ok(Async::NotReady);
-        }
+impl Stream for RequestStream {
+    type Item = Request;
+    type Error = ();
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        loop {
+            if let Some(request) = self.pending_requests.pop_front() {
+                return Ok(Async::Ready(Some(request)));
+            }
 
-        {
-            let rest = self.pending_requests.split_off(1);
-            let first = self.pending_requests.pop().expect("pending requests error");
-            self.pending_requests = rest;
-            println!("[Stream] ok {:?}", first);
-            return Ok(Async::Ready(Some(first)));
+            let ready = match self.tokio_registration.poll_read_ready() {
+                Ok(Async::Ready(ready)) => ready.is_readable(),
+                Ok(Async::NotReady) => false,
+                Err(e) => {
+                    log::warn!("[Stream] err {:?}", e);
+                    return Err(());
+                }
+            };
+
+            match self.receive() {
+                Ok(0) => {
+                    log::debug!("[Stream] EOF");
+                    return Ok(Async::Ready(None));
+                }
+                Ok(read) => {
+                    log::debug!("[Stream] received {} bytes", read);
+                    self.decode()?;
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    // A readiness that found nothing to read was spent, so
+                    // poll again to be woken by the next one.
+                    if !ready {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("[Stream] err {:?}", e);
+                    return Err(());
+                }
+            }
         }
     }
 }
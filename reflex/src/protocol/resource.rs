@@ -30,12 +30,103 @@ pub enum Resource {
     XdgWmBase(Arc<RwLock<crate::protocol::xdg_shell::xdg_wm_base::XdgWmBase>>),
 }
 
+impl Resource {
+    pub fn event_since(&self) -> fn(u16) -> u32 {
+        match self {
+            Resource::WlBuffer(_) => super::wayland::wl_buffer::event_since,
+            Resource::WlCallback(_) => super::wayland::wl_callback::event_since,
+            Resource::WlCompositor(_) => super::wayland::wl_compositor::event_since,
+            Resource::WlDataDevice(_) => super::wayland::wl_data_device::event_since,
+            Resource::WlDataDeviceManager(_) => super::wayland::wl_data_device_manager::event_since,
+            Resource::WlDataOffer(_) => super::wayland::wl_data_offer::event_since,
+            Resource::WlDataSource(_) => super::wayland::wl_data_source::event_since,
+            Resource::WlDisplay(_) => super::wayland::wl_display::event_since,
+            Resource::WlKeyboard(_) => super::wayland::wl_keyboard::event_since,
+            Resource::WlOutput(_) => super::wayland::wl_output::event_since,
+            Resource::WlPointer(_) => super::wayland::wl_pointer::event_since,
+            Resource::WlRegion(_) => super::wayland::wl_region::event_since,
+            Resource::WlRegistry(_) => super::wayland::wl_registry::event_since,
+            Resource::WlSeat(_) => super::wayland::wl_seat::event_since,
+            Resource::WlShm(_) => super::wayland::wl_shm::event_since,
+            Resource::WlShmPool(_) => super::wayland::wl_shm_pool::event_since,
+            Resource::WlSubcompositor(_) => super::wayland::wl_subcompositor::event_since,
+            Resource::WlSubsurface(_) => super::wayland::wl_subsurface::event_since,
+            Resource::WlSurface(_) => super::wayland::wl_surface::event_since,
+            Resource::WlTouch(_) => super::wayland::wl_touch::event_since,
+            Resource::XdgPopup(_) => super::xdg_shell::xdg_popup::event_since,
+            Resource::XdgPositioner(_) => super::xdg_shell::xdg_positioner::event_since,
+            Resource::XdgSurface(_) => super::xdg_shell::xdg_surface::event_since,
+            Resource::XdgToplevel(_) => super::xdg_shell::xdg_toplevel::event_since,
+            Resource::XdgWmBase(_) => super::xdg_shell::xdg_wm_base::event_since,
+        }
+    }
+
+    pub fn interface(&self) -> &'static crate::protocol::trace::Interface {
+        match self {
+            Resource::WlBuffer(_) => &super::wayland::wl_buffer::INTERFACE,
+            Resource::WlCallback(_) => &super::wayland::wl_callback::INTERFACE,
+            Resource::WlCompositor(_) => &super::wayland::wl_compositor::INTERFACE,
+            Resource::WlDataDevice(_) => &super::wayland::wl_data_device::INTERFACE,
+            Resource::WlDataDeviceManager(_) => &super::wayland::wl_data_device_manager::INTERFACE,
+            Resource::WlDataOffer(_) => &super::wayland::wl_data_offer::INTERFACE,
+            Resource::WlDataSource(_) => &super::wayland::wl_data_source::INTERFACE,
+            Resource::WlDisplay(_) => &super::wayland::wl_display::INTERFACE,
+            Resource::WlKeyboard(_) => &super::wayland::wl_keyboard::INTERFACE,
+            Resource::WlOutput(_) => &super::wayland::wl_output::INTERFACE,
+            Resource::WlPointer(_) => &super::wayland::wl_pointer::INTERFACE,
+            Resource::WlRegion(_) => &super::wayland::wl_region::INTERFACE,
+            Resource::WlRegistry(_) => &super::wayland::wl_registry::INTERFACE,
+            Resource::WlSeat(_) => &super::wayland::wl_seat::INTERFACE,
+            Resource::WlShm(_) => &super::wayland::wl_shm::INTERFACE,
+            Resource::WlShmPool(_) => &super::wayland::wl_shm_pool::INTERFACE,
+            Resource::WlSubcompositor(_) => &super::wayland::wl_subcompositor::INTERFACE,
+            Resource::WlSubsurface(_) => &super::wayland::wl_subsurface::INTERFACE,
+            Resource::WlSurface(_) => &super::wayland::wl_surface::INTERFACE,
+            Resource::WlTouch(_) => &super::wayland::wl_touch::INTERFACE,
+            Resource::XdgPopup(_) => &super::xdg_shell::xdg_popup::INTERFACE,
+            Resource::XdgPositioner(_) => &super::xdg_shell::xdg_positioner::INTERFACE,
+            Resource::XdgSurface(_) => &super::xdg_shell::xdg_surface::INTERFACE,
+            Resource::XdgToplevel(_) => &super::xdg_shell::xdg_toplevel::INTERFACE,
+            Resource::XdgWmBase(_) => &super::xdg_shell::xdg_wm_base::INTERFACE,
+        }
+    }
+}
+
+// Every interface, for looking them up by name.
+pub static INTERFACES: [&crate::protocol::trace::Interface; 25] = [
+    &super::wayland::wl_buffer::INTERFACE,
+    &super::wayland::wl_callback::INTERFACE,
+    &super::wayland::wl_compositor::INTERFACE,
+    &super::wayland::wl_data_device::INTERFACE,
+    &super::wayland::wl_data_device_manager::INTERFACE,
+    &super::wayland::wl_data_offer::INTERFACE,
+    &super::wayland::wl_data_source::INTERFACE,
+    &super::wayland::wl_display::INTERFACE,
+    &super::wayland::wl_keyboard::INTERFACE,
+    &super::wayland::wl_output::INTERFACE,
+    &super::wayland::wl_pointer::INTERFACE,
+    &super::wayland::wl_region::INTERFACE,
+    &super::wayland::wl_registry::INTERFACE,
+    &super::wayland::wl_seat::INTERFACE,
+    &super::wayland::wl_shm::INTERFACE,
+    &super::wayland::wl_shm_pool::INTERFACE,
+    &super::wayland::wl_subcompositor::INTERFACE,
+    &super::wayland::wl_subsurface::INTERFACE,
+    &super::wayland::wl_surface::INTERFACE,
+    &super::wayland::wl_touch::INTERFACE,
+    &super::xdg_shell::xdg_popup::INTERFACE,
+    &super::xdg_shell::xdg_positioner::INTERFACE,
+    &super::xdg_shell::xdg_surface::INTERFACE,
+    &super::xdg_shell::xdg_toplevel::INTERFACE,
+    &super::xdg_shell::xdg_wm_base::INTERFACE,
+];
+
 pub fn dispatch_request(
     resource: Resource,
     session: crate::protocol::session::Session,
     sender_object_id: u32,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     match resource {
         Resource::WlBuffer(object) => super::wayland::wl_buffer::dispatch_request(
@@ -1,18 +1,19 @@
 use super::event::Event;
+use super::fd_queue::FdQueue;
+use super::link;
+use super::object_map::ObjectMap;
 use super::resource::Resource;
+use super::vision_link::VisionLink;
 use super::wayland::wl_compositor::WlCompositor;
 use super::wayland::wl_display::WlDisplay;
 use super::wayland::wl_registry::WlRegistry;
 use super::wayland::wl_shm::WlShm;
 use super::xdg_shell::xdg_wm_base::XdgWmBase;
 use crate::protocol::wayland::wl_data_device_manager::WlDataDeviceManager;
+use byteorder::{ByteOrder, NativeEndian};
 use futures::future::Future;
 use futures::sink::Sink;
-use std::collections::HashMap;
-use std::os::unix::io::RawFd;
 use std::sync::{Arc, RwLock};
-use tokio::io::WriteHalf;
-use tokio::net::UnixStream;
 use tokio::sync::mpsc::Sender;
 
 pub enum NextAction {
@@ -22,7 +23,8 @@ pub enum NextAction {
 }
 
 pub struct Session {
-    pub resources: HashMap<u32, Resource>,
+    pub client_id: u32,
+    pub resources: ObjectMap,
     pub wl_display: Arc<RwLock<WlDisplay>>,
     pub wl_compositor: Arc<RwLock<WlCompositor>>,
     pub wl_shm: Arc<RwLock<WlShm>>,
@@ -30,8 +32,10 @@ pub struct Session {
     pub wl_data_device_manager: Arc<RwLock<WlDataDeviceManager>>,
     pub xdg_wm_base: Arc<RwLock<XdgWmBase>>,
     pub tx: Sender<Box<dyn Event + Send>>,
-    pub fds: Vec<RawFd>,
-    pub unix_stream: WriteHalf<UnixStream>,
+    pub fds: FdQueue,
+    pub callback_data: u32,
+    pub fatal_error: bool,
+    pub vision: VisionLink,
 }
 
 pub struct Context<T>
@@ -41,7 +45,8 @@ where
     pub sender_object_id: u32,
     pub sender_object: T,
 
-    pub resources: HashMap<u32, Resource>,
+    pub client_id: u32,
+    pub resources: ObjectMap,
     pub wl_display: Arc<RwLock<WlDisplay>>,
     pub wl_compositor: Arc<RwLock<WlCompositor>>,
     pub wl_shm: Arc<RwLock<WlShm>>,
@@ -49,8 +54,10 @@ where
     pub wl_data_device_manager: Arc<RwLock<WlDataDeviceManager>>,
     pub xdg_wm_base: Arc<RwLock<XdgWmBase>>,
     pub tx: Sender<Box<dyn Event + Send>>,
-    pub fds: Vec<RawFd>,
-    pub unix_stream: WriteHalf<UnixStream>,
+    pub fds: FdQueue,
+    pub callback_data: u32,
+    pub fatal_error: bool,
+    pub vision: VisionLink,
 }
 
 impl<T> Context<T>
@@ -59,6 +66,7 @@ where
 {
     pub fn new(session: Session, sender_object: T, sender_object_id: u32) -> Self {
         Self {
+            client_id: session.client_id,
             resources: session.resources,
             wl_display: session.wl_display,
             wl_registry: session.wl_registry,
@@ -68,9 +76,11 @@ where
             xdg_wm_base: session.xdg_wm_base,
             tx: session.tx,
             fds: session.fds,
+            callback_data: session.callback_data,
+            fatal_error: session.fatal_error,
             sender_object_id,
             sender_object,
-            unix_stream: session.unix_stream,
+            vision: session.vision,
         }
     }
 
@@ -80,6 +90,86 @@ where
         Box::new(futures::future::ok((self.into(), NextAction::Relay)))
     }
 
+    pub fn destroy(
+        self,
+    ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
+        let sender_object_id = self.sender_object_id;
+        let mut session = Session {
+            client_id: self.client_id,
+            resources: self.resources,
+            wl_display: self.wl_display,
+            wl_registry: self.wl_registry,
+            wl_compositor: self.wl_compositor,
+            wl_shm: self.wl_shm,
+            wl_data_device_manager: self.wl_data_device_manager,
+            xdg_wm_base: self.xdg_wm_base,
+            tx: self.tx,
+            fds: self.fds,
+            callback_data: self.callback_data,
+            fatal_error: self.fatal_error,
+            vision: self.vision,
+        };
+        let (_, delete_id) = session.resources.remove(sender_object_id);
+        if !delete_id {
+            return Box::new(futures::future::ok((session, NextAction::Relay)));
+        }
+        let tx = session.tx.clone();
+        Box::new(
+            tx.send(Box::new(
+                crate::protocol::wayland::wl_display::events::DeleteId {
+                    sender_object_id: 1,
+                    id: sender_object_id,
+                },
+            ))
+            .map_err(|_| ())
+            .map(|_| (session, NextAction::Relay)),
+        )
+    }
+
+    pub fn invalid_object(
+        self,
+        message: String,
+    ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
+        let tx = self.tx.clone();
+        let error = crate::protocol::wayland::wl_display::events::Error {
+            sender_object_id: 1,
+            object_id: self.sender_object_id,
+            code: crate::protocol::wayland::wl_display::enums::Error::InvalidObject as u32,
+            message,
+        };
+        let mut session: Session = self.into();
+        session.fatal_error = true;
+
+        Box::new(
+            tx.send(Box::new(error))
+                .map_err(|_| ())
+                .map(|_| (session, NextAction::Nop)),
+        )
+    }
+
+    // An error defined by the interface of the sender.
+    pub fn protocol_error(
+        self,
+        code: u32,
+        message: String,
+    ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
+        let tx = self.tx.clone();
+        let error = crate::protocol::wayland::wl_display::events::Error {
+            sender_object_id: 1,
+            object_id: self.sender_object_id,
+            code,
+            message,
+        };
+        let mut session: Session = self.into();
+        session.fatal_error = true;
+
+        Box::new(
+            tx.send(Box::new(error))
+                .map_err(|_| ())
+                .map(|_| (session, NextAction::Nop)),
+        )
+    }
+
     fn create_invalid_method_error(
         &self,
         message: String,
@@ -98,7 +188,8 @@ where
     ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
         let tx = self.tx.clone();
         let error = self.create_invalid_method_error(message);
-        let session: Session = self.into();
+        let mut session: Session = self.into();
+        session.fatal_error = true;
 
         Box::new(
             tx.send(Box::new(error))
@@ -113,7 +204,8 @@ where
     ) -> Box<dyn futures::future::Future<Item = Session, Error = ()> + Send> {
         let tx = self.tx.clone();
         let error = self.create_invalid_method_error(message);
-        let session: Session = self.into();
+        let mut session: Session = self.into();
+        session.fatal_error = true;
 
         Box::new(tx.send(Box::new(error)).map_err(|_| ()).map(|_| session))
     }
@@ -125,8 +217,9 @@ where
 {
     fn into(mut self) -> Session {
         self.resources
-            .insert(self.sender_object_id, self.sender_object.into());
+            .put_back(self.sender_object_id, self.sender_object.into());
         Session {
+            client_id: self.client_id,
             resources: self.resources,
             wl_display: self.wl_display,
             wl_registry: self.wl_registry,
@@ -136,7 +229,9 @@ where
             xdg_wm_base: self.xdg_wm_base,
             tx: self.tx,
             fds: self.fds,
-            unix_stream: self.unix_stream,
+            callback_data: self.callback_data,
+            fatal_error: self.fatal_error,
+            vision: self.vision,
         }
     }
 }
@@ -146,75 +241,91 @@ impl Session {
         self,
         buf: Vec<u8>,
     ) -> Box<dyn futures::future::Future<Item = Session, Error = ()> + Send> {
-        let (relay_session, unix_stream) = self.into_relay_session();
-        Box::new(
-            tokio::io::write_all(unix_stream, buf)
-                .map_err(|err| panic!("relay err: {:?}", err))
-                .and_then(|(u, _): (WriteHalf<UnixStream>, Vec<u8>)| {
-                    futures::future::ok(Session::from_relay_session(relay_session, u))
-                }),
-        )
-        //Box::new(futures::future::ok(self))
+        let sender_object_id = link::to_local_id(NativeEndian::read_u32(&buf[0..]));
+        let sent = self
+            .vision
+            .relay(self.client_id, self.resources.get(sender_object_id), &buf);
+        Box::new(sent.map(|_| self))
     }
 
+    // Like relay, but the next request of the client waits until vision has
+    // handled this one.
     pub fn relay_wait(
         self,
         buf: Vec<u8>,
     ) -> Box<dyn futures::future::Future<Item = Session, Error = ()> + Send> {
-        let (relay_session, unix_stream) = self.into_relay_session();
-        Box::new(
-            tokio::io::write_all(unix_stream, buf)
-                .map_err(|err| panic!("relay_wait err: {:?}", err))
-                .and_then(|(u, _): (WriteHalf<UnixStream>, Vec<u8>)| {
-                    futures::future::ok(Session::from_relay_session(relay_session, u))
-                }),
-        )
-        //Box::new(futures::future::ok(self))
+        let sender_object_id = link::to_local_id(NativeEndian::read_u32(&buf[0..]));
+        let sent =
+            self.vision
+                .relay_wait(self.client_id, self.resources.get(sender_object_id), &buf);
+        Box::new(sent.map(|_| self))
     }
 
-    fn from_relay_session(
-        relay_session: RelaySession,
-        unix_stream: WriteHalf<UnixStream>,
-    ) -> Session {
-        Session {
-            resources: relay_session.resources,
-            wl_display: relay_session.wl_display,
-            wl_registry: relay_session.wl_registry,
-            wl_compositor: relay_session.wl_compositor,
-            wl_shm: relay_session.wl_shm,
-            wl_data_device_manager: relay_session.wl_data_device_manager,
-            xdg_wm_base: relay_session.xdg_wm_base,
-            tx: relay_session.tx,
-            fds: relay_session.fds,
-            unix_stream,
+    // Release everything the client owned once it has gone away, either by
+    // itself or because it was disconnected after a fatal protocol error.
+    // Vision is told to destroy the objects backing windows and buffers.
+    pub fn teardown(mut self) -> Box<dyn futures::future::Future<Item = (), Error = ()> + Send> {
+        self.wl_registry
+            .write()
+            .unwrap()
+            .unbind_client(self.client_id);
+
+        let mut resources = self.resources.drain();
+        resources.sort_by_key(|(id, resource)| (teardown_order(resource), *id));
+
+        let mut relay_buf: Vec<u8> = Vec::new();
+        for (id, resource) in resources {
+            if let Some(opcode) = destructor_opcode(&resource) {
+                let total_len = 8;
+                let mut dst = [0; 8];
+                NativeEndian::write_u32(&mut dst[0..], link::to_link_id(self.client_id, id));
+                NativeEndian::write_u32(
+                    &mut dst[4..],
+                    (total_len << 16) as u32 | u32::from(opcode),
+                );
+                relay_buf.extend_from_slice(&dst);
+            }
+        }
+        self.fds.clear();
+
+        log::debug!("[Session] teardown: {:?}", &relay_buf);
+        self.vision.remove_client(self.client_id);
+        let disconnected = self
+            .vision
+            .send(link::REQUEST_CLIENT_DISCONNECTED, self.client_id, &[]);
+        if relay_buf.is_empty() {
+            return Box::new(disconnected);
         }
+        Box::new(
+            self.vision
+                .send(link::REQUEST_MESSAGES, self.client_id, &relay_buf)
+                .and_then(|_| disconnected),
+        )
     }
+}
 
-    fn into_relay_session(self) -> (RelaySession, WriteHalf<UnixStream>) {
-        let unix_stream = self.unix_stream;
-        let relay_session = RelaySession {
-            resources: self.resources,
-            wl_display: self.wl_display,
-            wl_registry: self.wl_registry,
-            wl_compositor: self.wl_compositor,
-            wl_shm: self.wl_shm,
-            wl_data_device_manager: self.wl_data_device_manager,
-            xdg_wm_base: self.xdg_wm_base,
-            tx: self.tx,
-            fds: self.fds,
-        };
-        (relay_session, unix_stream)
+// Role objects go first so that vision never sees a wl_surface or wl_buffer
+// vanish underneath a window that still refers to it.
+fn teardown_order(resource: &Resource) -> u32 {
+    match resource {
+        Resource::XdgToplevel(_) | Resource::XdgPopup(_) => 0,
+        Resource::XdgSurface(_) | Resource::WlSubsurface(_) => 1,
+        Resource::WlSurface(_) => 2,
+        Resource::WlBuffer(_) => 3,
+        Resource::WlShmPool(_) => 4,
+        _ => 5,
     }
 }
 
-struct RelaySession {
-    pub resources: HashMap<u32, Resource>,
-    pub wl_display: Arc<RwLock<WlDisplay>>,
-    pub wl_compositor: Arc<RwLock<WlCompositor>>,
-    pub wl_shm: Arc<RwLock<WlShm>>,
-    pub wl_registry: Arc<RwLock<WlRegistry>>,
-    pub wl_data_device_manager: Arc<RwLock<WlDataDeviceManager>>,
-    pub xdg_wm_base: Arc<RwLock<XdgWmBase>>,
-    pub tx: Sender<Box<dyn Event + Send>>,
-    pub fds: Vec<RawFd>,
+fn destructor_opcode(resource: &Resource) -> Option<u16> {
+    match resource {
+        Resource::XdgToplevel(_)
+        | Resource::XdgPopup(_)
+        | Resource::XdgSurface(_)
+        | Resource::WlSubsurface(_)
+        | Resource::WlSurface(_)
+        | Resource::WlBuffer(_) => Some(0),
+        Resource::WlShmPool(_) => Some(1),
+        _ => None,
+    }
 }
@@ -0,0 +1,331 @@
+use crate::protocol::fd_queue::FdQueue;
+use crate::protocol::object_map::{ObjectMap, ObjectVersion, ObjectVersions};
+use crate::protocol::resource::INTERFACES;
+use byteorder::{ByteOrder, NativeEndian};
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Traces the requests and events of clients in the format of libwayland's
+// WAYLAND_DEBUG, as seen from the client:
+//
+//   [1234.567] -> wl_surface@5.attach(wl_buffer@7, 0, 0)
+//   [1234.568] wl_callback@8.done(1234568)
+//
+// The lines are logged at trace level by this module, so they are switched on
+// for one client with "protocol::trace@<client id>=trace" in the log filter,
+// or for every client with "protocol::trace=trace".
+
+// A generated formatter, which turns the opcode and arguments of a message
+// into e.g. "attach(wl_buffer@7, 0, 0)", or None for an unknown opcode.
+pub type Formatter = fn(u16, &mut Args) -> Option<String>;
+
+pub struct Interface {
+    pub name: &'static str,
+    pub format_request: Formatter,
+    pub format_event: Formatter,
+}
+
+// The interface of an object of the client, by id.
+pub trait Objects {
+    fn interface(&self, id: u32) -> Option<&'static Interface>;
+}
+
+impl Objects for HashMap<u32, ObjectVersion> {
+    fn interface(&self, id: u32) -> Option<&'static Interface> {
+        self.get(&id).map(|object| object.interface)
+    }
+}
+
+impl Objects for HashMap<u32, &'static Interface> {
+    fn interface(&self, id: u32) -> Option<&'static Interface> {
+        self.get(&id).cloned()
+    }
+}
+
+// The arguments of one message, read in order by the formatters. Whatever is
+// missing from a short message is shown as "?".
+pub struct Args<'a> {
+    bytes: &'a [u8],
+    fds: std::slice::Iter<'a, RawFd>,
+    objects: &'a dyn Objects,
+    // The last string, which names the interface of a new_id the protocol
+    // does not fix, as in wl_registry.bind.
+    last_string: Option<String>,
+    // The objects the message creates, by id and interface name.
+    created: Vec<(u32, Option<String>)>,
+}
+
+impl<'a> Args<'a> {
+    fn new(bytes: &'a [u8], fds: &'a [RawFd], objects: &'a dyn Objects) -> Args<'a> {
+        Args {
+            bytes,
+            fds: fds.iter(),
+            objects,
+            last_string: None,
+            created: Vec::new(),
+        }
+    }
+
+    fn word(&mut self) -> Option<u32> {
+        if self.bytes.len() < 4 {
+            self.bytes = &[];
+            return None;
+        }
+        let word = NativeEndian::read_u32(self.bytes);
+        self.bytes = &self.bytes[4..];
+        Some(word)
+    }
+
+    // A length and that many bytes, padded to 32 bits.
+    fn blob(&mut self) -> Option<&'a [u8]> {
+        let len = self.word()? as usize;
+        let padded_len = (len + 3) & !3;
+        if self.bytes.len() < padded_len {
+            self.bytes = &[];
+            return None;
+        }
+        let blob = &self.bytes[..len];
+        self.bytes = &self.bytes[padded_len..];
+        Some(blob)
+    }
+
+    // The interface given by the protocol, or else the one of the object.
+    fn interface(&self, id: u32, interface: Option<&'static str>) -> &'static str {
+        interface
+            .or_else(|| self.objects.interface(id).map(|interface| interface.name))
+            .unwrap_or("[unknown]")
+    }
+
+    pub fn uint(&mut self) -> String {
+        match self.word() {
+            Some(word) => word.to_string(),
+            None => "?".to_owned(),
+        }
+    }
+
+    pub fn int(&mut self) -> String {
+        match self.word() {
+            Some(word) => (word as i32).to_string(),
+            None => "?".to_owned(),
+        }
+    }
+
+    pub fn fixed(&mut self) -> String {
+        match self.word() {
+            Some(word) => format!("{:.6}", f64::from(word as i32) / 256.0),
+            None => "?".to_owned(),
+        }
+    }
+
+    pub fn string(&mut self) -> String {
+        match self.blob() {
+            Some([]) => {
+                self.last_string = None;
+                "nil".to_owned()
+            }
+            Some(blob) => {
+                let text = blob.split(|&byte| byte == 0).next().unwrap_or(&[]);
+                let text = String::from_utf8_lossy(text).into_owned();
+                let string = format!("\"{}\"", text);
+                self.last_string = Some(text);
+                string
+            }
+            None => "?".to_owned(),
+        }
+    }
+
+    pub fn array(&mut self) -> String {
+        match self.blob() {
+            Some(blob) => format!("array[{}]", blob.len()),
+            None => "?".to_owned(),
+        }
+    }
+
+    pub fn fd(&mut self) -> String {
+        match self.fds.next() {
+            Some(fd) => format!("fd {}", fd),
+            None => "fd ?".to_owned(),
+        }
+    }
+
+    pub fn object(&mut self, interface: Option<&'static str>) -> String {
+        match self.word() {
+            Some(0) => "nil".to_owned(),
+            Some(id) => format!("{}@{}", self.interface(id, interface), id),
+            None => "?".to_owned(),
+        }
+    }
+
+    pub fn new_id(&mut self, interface: Option<&'static str>) -> String {
+        match self.word() {
+            Some(id) => {
+                let created = interface
+                    .map(str::to_owned)
+                    .or_else(|| self.last_string.clone());
+                self.created.push((id, created));
+                format!("new id {}@{}", interface.unwrap_or("[unknown]"), id)
+            }
+            None => "?".to_owned(),
+        }
+    }
+}
+
+// A request about to be dispatched. Its fds are still at the front of fds.
+pub fn request(
+    resources: &ObjectMap,
+    sender_object_id: u32,
+    opcode: u16,
+    args: &[u8],
+    fds: &FdQueue,
+) {
+    if !log::log_enabled!(log::Level::Trace) {
+        return;
+    }
+    let versions = resources.versions();
+    let objects = versions.read().unwrap();
+    let fds = fds.raw_fds();
+    let (line, _) = format_message(
+        objects.interface(sender_object_id),
+        sender_object_id,
+        opcode,
+        Args::new(args, &fds, &*objects),
+        |interface| interface.format_request,
+    );
+    log::trace!("{} -> {}", timestamp(), line);
+}
+
+// An encoded event about to be sent, from an object of interface when the
+// event knows it. Its fds are not traced.
+pub fn event(objects: &ObjectVersions, interface: Option<&'static Interface>, message: &[u8]) {
+    if !log::log_enabled!(log::Level::Trace) || message.len() < 8 {
+        return;
+    }
+    let objects = objects.read().unwrap();
+    let sender_object_id = NativeEndian::read_u32(&message[0..]);
+    let opcode = (NativeEndian::read_u32(&message[4..]) & 0xffff) as u16;
+    let (line, _) = format_message(
+        interface.or_else(|| objects.interface(sender_object_id)),
+        sender_object_id,
+        opcode,
+        Args::new(&message[8..], &[], &*objects),
+        |interface| interface.format_event,
+    );
+    log::trace!("{} {}", timestamp(), line);
+}
+
+// The objects of a client whose messages are only looked at, as by the
+// proxy. They are learnt from the new_id arguments of the messages, and
+// forgotten with wl_display.delete_id.
+pub struct ObjectTracker {
+    objects: HashMap<u32, &'static Interface>,
+}
+
+impl ObjectTracker {
+    pub fn new() -> ObjectTracker {
+        let mut objects = HashMap::new();
+        objects.insert(1, wl_display_interface());
+        ObjectTracker { objects }
+    }
+
+    // Trace a whole message the client sent, and note the objects it
+    // creates. fds are those not yet taken by earlier messages; the number
+    // the message takes is returned.
+    pub fn request(&mut self, message: &[u8], fds: &[RawFd]) -> usize {
+        let (line, fds_taken) = self.message(message, fds, |interface| interface.format_request);
+        log::trace!("{} -> {}", timestamp(), line);
+        fds_taken
+    }
+
+    // The same for a message sent to the client.
+    pub fn event(&mut self, message: &[u8], fds: &[RawFd]) -> usize {
+        let (line, fds_taken) = self.message(message, fds, |interface| interface.format_event);
+        log::trace!("{} {}", timestamp(), line);
+        if NativeEndian::read_u32(&message[0..]) == 1
+            && NativeEndian::read_u32(&message[4..]) & 0xffff == DELETE_ID_OPCODE
+            && message.len() >= 12
+        {
+            self.objects.remove(&NativeEndian::read_u32(&message[8..]));
+        }
+        fds_taken
+    }
+
+    // Formatted whether traced or not, as the objects a message creates are
+    // only known from its arguments.
+    fn message(
+        &mut self,
+        message: &[u8],
+        fds: &[RawFd],
+        formatter: fn(&Interface) -> Formatter,
+    ) -> (String, usize) {
+        let sender_object_id = NativeEndian::read_u32(&message[0..]);
+        let opcode = (NativeEndian::read_u32(&message[4..]) & 0xffff) as u16;
+        let (line, args) = format_message(
+            self.objects.interface(sender_object_id),
+            sender_object_id,
+            opcode,
+            Args::new(&message[8..], fds, &self.objects),
+            formatter,
+        );
+        let fds_taken = fds.len() - args.fds.len();
+        for (id, interface) in args.created {
+            match interface.as_ref().and_then(|name| find_interface(name)) {
+                Some(interface) => {
+                    self.objects.insert(id, interface);
+                }
+                None => {
+                    self.objects.remove(&id);
+                }
+            }
+        }
+        (line, fds_taken)
+    }
+}
+
+// wl_display.delete_id
+const DELETE_ID_OPCODE: u32 = 1;
+
+fn wl_display_interface() -> &'static Interface {
+    find_interface("wl_display").expect("wl_display is generated")
+}
+
+fn find_interface(name: &str) -> Option<&'static Interface> {
+    INTERFACES
+        .iter()
+        .find(|interface| interface.name == name)
+        .cloned()
+}
+
+// The line for a message, and its arguments as the formatter left them.
+fn format_message<'a>(
+    interface: Option<&'static Interface>,
+    sender_object_id: u32,
+    opcode: u16,
+    mut args: Args<'a>,
+    formatter: fn(&Interface) -> Formatter,
+) -> (String, Args<'a>) {
+    let interface = match interface {
+        Some(interface) => interface,
+        None => {
+            let line = format!("[unknown]@{}.[opcode {}]", sender_object_id, opcode);
+            return (line, args);
+        }
+    };
+    let line = match formatter(interface)(opcode, &mut args) {
+        Some(message) => format!("{}@{}.{}", interface.name, sender_object_id, message),
+        None => format!(
+            "{}@{}.[opcode {}]",
+            interface.name, sender_object_id, opcode
+        ),
+    };
+    (line, args)
+}
+
+// Milliseconds with microseconds, wrapped to 32 bits as libwayland does.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let micros = now.as_secs() * 1_000_000 + u64::from(now.subsec_micros());
+    format!("[{:7}.{:03}]", (micros / 1000) as u32, micros % 1000)
+}
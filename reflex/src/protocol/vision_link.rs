@@ -0,0 +1,427 @@
+use super::capture::{Capture, Clock};
+use super::control::{ControlMessage, ControlState};
+use super::event::Event;
+use super::event_sink::Hangup;
+use super::flow::{FlowControl, Pixels};
+use super::link;
+use super::pixels;
+use super::replay::Journal;
+use super::resource::Resource;
+use super::wayland::wl_callback;
+use super::wayland::wl_display;
+use super::wayland::wl_registry::WlRegistry;
+use byteorder::{ByteOrder, NativeEndian};
+use futures::future::{Either, Future};
+use futures::sink::Sink;
+use futures::sync::oneshot;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::Sender;
+use tokio::timer::Timeout;
+
+type ClientSenders = HashMap<u32, LinkClient>;
+type Waiters = HashMap<(u32, u32), oneshot::Sender<()>>;
+
+// How long a client is held for an answer from vision, see relay_wait.
+const RELAY_WAIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// What the writer task gets: a frame, with the seq of the journal entry
+// that covers it if any, or word that the reader of the given connection
+// has stopped.
+pub enum LinkFrame {
+    Data(Option<u64>, Vec<u8>),
+    Closed(u64),
+}
+
+// Where the events of a client are queued, and what disconnects it when they
+// cannot be. The clients of a replay have no socket to shut down.
+#[derive(Clone)]
+struct LinkClient {
+    tx: Sender<Box<dyn Event + Send>>,
+    hangup: Option<Hangup>,
+}
+
+// The one connection to vision that every client session is multiplexed
+// over. Frames are queued for a single writer task, which outlives the
+// connection itself; frames coming back are routed to the client they are
+// tagged with.
+#[derive(Clone)]
+pub struct VisionLink {
+    frames: Sender<LinkFrame>,
+    clients: Arc<RwLock<ClientSenders>>,
+    journal: Arc<Mutex<Journal>>,
+    // Locked after the journal when both are needed.
+    flow: Arc<Mutex<FlowControl>>,
+    // Requests waiting for SYNC_DONE by (client id, serial), while vision is
+    // connected.
+    waiters: Arc<Mutex<Waiters>>,
+    control_state: Arc<Mutex<ControlState>>,
+    next_serial: Arc<AtomicU32>,
+    connected: Arc<AtomicBool>,
+    capture: Option<Capture>,
+    clock: Clock,
+}
+
+impl VisionLink {
+    pub fn new(
+        frames: Sender<LinkFrame>,
+        capture: Option<Capture>,
+        clock: Clock,
+        wl_registry: Arc<RwLock<WlRegistry>>,
+    ) -> VisionLink {
+        VisionLink {
+            frames,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            journal: Arc::new(Mutex::new(Journal::new())),
+            flow: Arc::new(Mutex::new(FlowControl::default())),
+            waiters: Arc::new(Mutex::new(HashMap::new())),
+            control_state: Arc::new(Mutex::new(ControlState::new(wl_registry))),
+            next_serial: Arc::new(AtomicU32::new(1)),
+            connected: Arc::new(AtomicBool::new(false)),
+            capture,
+            clock,
+        }
+    }
+
+    // Where the session is recorded, if it is.
+    pub fn capture(&self) -> Option<&Capture> {
+        self.capture.as_ref()
+    }
+
+    // Hand out the lowest free client id so that ids stay below
+    // MAX_CLIENT_ID however many clients come and go.
+    pub fn add_client(&self, tx: Sender<Box<dyn Event + Send>>, hangup: Hangup) -> Option<u32> {
+        let mut clients = self.clients.write().unwrap();
+        let client_id = (1..=link::MAX_CLIENT_ID).find(|id| !clients.contains_key(id))?;
+        clients.insert(
+            client_id,
+            LinkClient {
+                tx,
+                hangup: Some(hangup),
+            },
+        );
+        Some(client_id)
+    }
+
+    // Add a client under the id it had in a capture that is replayed.
+    pub fn insert_client(&self, client_id: u32, tx: Sender<Box<dyn Event + Send>>) {
+        self.clients
+            .write()
+            .unwrap()
+            .insert(client_id, LinkClient { tx, hangup: None });
+    }
+
+    pub fn remove_client(&self, client_id: u32) {
+        self.clients.write().unwrap().remove(&client_id);
+        self.journal.lock().unwrap().remove_client(client_id);
+        self.flow.lock().unwrap().remove_client(client_id);
+        self.control_state.lock().unwrap().remove_client(client_id);
+    }
+
+    // Tell vision about a client added with add_client.
+    pub fn connect_client(&self, client_id: u32) -> impl Future<Item = (), Error = ()> {
+        let seq = self.journal.lock().unwrap().add_client(client_id);
+        let frame = link::request_frame(link::REQUEST_CLIENT_CONNECTED, client_id, &[]);
+        self.queue(client_id, LinkFrame::Data(Some(seq), frame))
+    }
+
+    pub fn client(&self, client_id: u32) -> Option<Sender<Box<dyn Event + Send>>> {
+        self.clients
+            .read()
+            .unwrap()
+            .get(&client_id)
+            .map(|client| client.tx.clone())
+    }
+
+    pub fn hangup(&self, client_id: u32) -> Option<Hangup> {
+        self.clients
+            .read()
+            .unwrap()
+            .get(&client_id)
+            .and_then(|client| client.hangup.clone())
+    }
+
+    // The events a control message from vision makes for its client.
+    pub fn control(&self, message: ControlMessage) -> Vec<Box<dyn Event + Send>> {
+        self.control_state.lock().unwrap().handle(message)
+    }
+
+    // Relay a message the client sent once it has been dispatched. The
+    // sender is None when the message destroyed it.
+    pub fn relay(
+        &self,
+        client_id: u32,
+        sender: Option<&Resource>,
+        message: &[u8],
+    ) -> impl Future<Item = (), Error = ()> {
+        let seq = self
+            .journal
+            .lock()
+            .unwrap()
+            .record(client_id, sender, message);
+        let frame = link::request_frame(link::REQUEST_MESSAGES, client_id, message);
+        self.queue(client_id, LinkFrame::Data(seq, frame))
+    }
+
+    // Relay a message and then hold the client until vision has handled it,
+    // so that what vision does about it, e.g. the configure of a new window,
+    // reaches the client before the answers to its next requests. Vision
+    // being away or slow only costs the client RELAY_WAIT_TIMEOUT.
+    pub fn relay_wait(
+        &self,
+        client_id: u32,
+        sender: Option<&Resource>,
+        message: &[u8],
+    ) -> impl Future<Item = (), Error = ()> {
+        let relayed = self.relay(client_id, sender, message);
+        if !self.connected.load(Ordering::SeqCst) {
+            return Either::A(relayed);
+        }
+        let serial = self.next_serial.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap().insert((client_id, serial), tx);
+        let mut data = [0; 4];
+        NativeEndian::write_u32(&mut data, serial);
+        let sync = link::request_frame(link::REQUEST_SYNC, client_id, &data);
+        let sent = self.queue(client_id, LinkFrame::Data(None, sync));
+        let waiters = self.waiters.clone();
+        Either::B(relayed.and_then(|_| sent).and_then(move |_| {
+            Timeout::new(rx, RELAY_WAIT_TIMEOUT).then(move |result| {
+                if let Err(err) = result {
+                    waiters.lock().unwrap().remove(&(client_id, serial));
+                    if err.is_elapsed() {
+                        log::warn!(
+                            "[Vision] client {} sync {} timed out, going on",
+                            client_id,
+                            serial
+                        );
+                    } else {
+                        log::warn!(
+                            "[Vision] client {} sync {} lost with the link",
+                            client_id,
+                            serial
+                        );
+                    }
+                }
+                Ok(())
+            })
+        }))
+    }
+
+    pub fn sync_done(&self, client_id: u32, serial: u32) {
+        match self.waiters.lock().unwrap().remove(&(client_id, serial)) {
+            Some(tx) => {
+                let _ = tx.send(());
+            }
+            None => log::warn!(
+                "[Vision] client {} sync {} is not waited for",
+                client_id,
+                serial
+            ),
+        }
+    }
+
+    // A commit of a surface with the pixels it changed, if it attached a
+    // buffer, and its frame callbacks. The journal keeps the pixels for the
+    // replay.
+    pub fn commit_frame(
+        &self,
+        client_id: u32,
+        surface_id: u32,
+        changed: Option<Pixels>,
+        callbacks: Vec<u32>,
+    ) -> impl Future<Item = (), Error = ()> {
+        // Encoded under the lock so that a replay cannot come in between
+        // and change the features of the link.
+        let (frame, done) = {
+            let mut journal = self.journal.lock().unwrap();
+            let seq = match &changed {
+                Some((buffer, rects)) => {
+                    journal.record_pixels(client_id, surface_id, (buffer.clone(), rects.clone()))
+                }
+                None => None,
+            };
+            let action = self
+                .flow
+                .lock()
+                .unwrap()
+                .commit(client_id, surface_id, changed, callbacks);
+            let frame = action.send.map(|(buffer, rects)| {
+                let data = pixels::encode(surface_id, &buffer, &rects, journal.features());
+                let frame = link::request_frame(link::REQUEST_PIXELS, client_id, &data);
+                LinkFrame::Data(seq, frame)
+            });
+            (frame, action.done)
+        };
+        self.act(client_id, frame, done)
+    }
+
+    // Vision has shown the last frame sent for the surface: answer the
+    // callbacks it carried and send what was held back meanwhile.
+    pub fn frame_done(
+        &self,
+        client_id: u32,
+        surface_id: u32,
+    ) -> impl Future<Item = (), Error = ()> {
+        let (frame, done) = {
+            let mut journal = self.journal.lock().unwrap();
+            let action = self.flow.lock().unwrap().frame_done(client_id, surface_id);
+            let frame = action.send.map(|(buffer, rects)| {
+                let data = pixels::encode(surface_id, &buffer, &rects, journal.features());
+                let frame = link::request_frame(link::REQUEST_HELD_PIXELS, client_id, &data);
+                LinkFrame::Data(Some(journal.covered_seq()), frame)
+            });
+            (frame, action.done)
+        };
+        self.act(client_id, frame, done)
+    }
+
+    pub fn forget_surface(&self, client_id: u32, surface_id: u32) {
+        self.flow
+            .lock()
+            .unwrap()
+            .remove_surface(client_id, surface_id);
+    }
+
+    fn act(
+        &self,
+        client_id: u32,
+        frame: Option<LinkFrame>,
+        done: Vec<u32>,
+    ) -> impl Future<Item = (), Error = ()> {
+        let sent = match frame {
+            Some(frame) => Either::A(self.queue(client_id, frame)),
+            None => Either::B(futures::future::ok(())),
+        };
+        sent.join(self.answer_callbacks(client_id, done))
+            .map(|_| ())
+    }
+
+    // wl_callback.done, then the id goes back to the client.
+    fn answer_callbacks(
+        &self,
+        client_id: u32,
+        callbacks: Vec<u32>,
+    ) -> impl Future<Item = (), Error = ()> {
+        let tx = match self.client(client_id) {
+            Some(tx) if !callbacks.is_empty() => tx,
+            _ => return Either::A(futures::future::ok(())),
+        };
+        let time = self.time_ms(client_id);
+        let mut events: Vec<Box<dyn Event + Send>> = Vec::new();
+        for callback in callbacks {
+            events.push(Box::new(wl_callback::events::Done {
+                sender_object_id: callback,
+                callback_data: time,
+            }));
+            events.push(Box::new(wl_display::events::DeleteId {
+                sender_object_id: 1,
+                id: callback,
+            }));
+        }
+        Either::B(
+            tx.send_all(futures::stream::iter_ok::<_, SendError>(events))
+                .map(|_| ())
+                .map_err(move |_| {
+                    log::warn!(
+                        "[Vision] client {} is gone, frame callbacks dropped",
+                        client_id
+                    )
+                }),
+        )
+    }
+
+    // Send a frame that is not replayed after a reconnect.
+    pub fn send(
+        &self,
+        request_type: u32,
+        client_id: u32,
+        data: &[u8],
+    ) -> impl Future<Item = (), Error = ()> {
+        let frame = link::request_frame(request_type, client_id, data);
+        self.queue(client_id, LinkFrame::Data(None, frame))
+    }
+
+    // The frames that rebuild every client on a new connection with the
+    // given features, and the last seq they cover.
+    pub fn replay(&self, features: u32) -> (Vec<Vec<u8>>, u64) {
+        if let Some(capture) = &self.capture {
+            capture.link_up(features);
+        }
+        let mut journal = self.journal.lock().unwrap();
+        // Frames in flight on the old connection will never be done, and
+        // the replay sends all of the pixels again.
+        let done = self.flow.lock().unwrap().reset();
+        for (client_id, callbacks) in done {
+            tokio::spawn(crate::logger::with_client(
+                client_id,
+                self.answer_callbacks(client_id, callbacks),
+            ));
+        }
+        self.control_state.lock().unwrap().reset();
+        self.connected.store(true, Ordering::SeqCst);
+        journal.replay(features)
+    }
+
+    pub fn closed(&self, generation: u64) -> impl Future<Item = (), Error = ()> {
+        if let Some(capture) = &self.capture {
+            capture.link_down();
+        }
+        // Whoever waits for the old connection goes on.
+        self.connected.store(false, Ordering::SeqCst);
+        self.waiters.lock().unwrap().clear();
+        self.frames
+            .clone()
+            .send(LinkFrame::Closed(generation))
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    // The time for the frame callbacks of a client.
+    fn time_ms(&self, client_id: u32) -> u32 {
+        match &self.clock {
+            Clock::System => {
+                let time = now_ms();
+                if let Some(capture) = &self.capture {
+                    capture.time(client_id, time);
+                }
+                time
+            }
+            Clock::Replay(times) => {
+                let time = times
+                    .lock()
+                    .unwrap()
+                    .get_mut(&client_id)
+                    .and_then(VecDeque::pop_front);
+                time.unwrap_or_else(|| {
+                    log::warn!(
+                        "[Vision] client {} has no time left in the capture",
+                        client_id
+                    );
+                    0
+                })
+            }
+        }
+    }
+
+    fn queue(&self, client_id: u32, frame: LinkFrame) -> impl Future<Item = (), Error = ()> {
+        self.frames
+            .clone()
+            .send(frame)
+            .map(|_| ())
+            .map_err(move |_| {
+                log::warn!("[Vision] link closed, frame for client {} lost", client_id)
+            })
+    }
+}
+
+// The time frame callbacks carry, in milliseconds with an undefined base.
+fn now_ms() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u32)
+        .unwrap_or(0)
+}
@@ -0,0 +1,204 @@
+use crate::token::{TOKEN_ACCEPTED, TOKEN_MAGIC};
+use byteorder::{ByteOrder, LittleEndian};
+use futures::future::Future;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::prelude::Poll;
+
+#[derive(Clone, Debug)]
+pub enum VisionEndpoint {
+    Unix(PathBuf),
+    // The address as given and what it resolved to.
+    Tcp(String, SocketAddr),
+}
+
+impl VisionEndpoint {
+    // "unix:/path/to/socket" or "tcp:host:port". The host is looked up here,
+    // once, rather than on the reactor whenever reflex reconnects.
+    pub fn parse(value: &str) -> Result<VisionEndpoint, String> {
+        let mut parts = value.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("unix"), Some(path)) if !path.is_empty() => {
+                Ok(VisionEndpoint::Unix(PathBuf::from(path)))
+            }
+            (Some("tcp"), Some(address)) => match address.rfind(':') {
+                Some(i) if i > 0 && address[i + 1..].parse::<u16>().is_ok() => {
+                    match address.to_socket_addrs().map(|mut addrs| addrs.next()) {
+                        Ok(Some(socket_addr)) => {
+                            Ok(VisionEndpoint::Tcp(address.to_owned(), socket_addr))
+                        }
+                        Ok(None) => Err(format!("no address for {}", address)),
+                        Err(err) => Err(format!("failed to look up {}: {}", address, err)),
+                    }
+                }
+                _ => Err(format!("expected tcp:<host>:<port>: {}", value)),
+            },
+            _ => Err(format!(
+                "expected unix:<path> or tcp:<host>:<port>: {}",
+                value
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for VisionEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VisionEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            VisionEndpoint::Tcp(address, _) => write!(f, "tcp:{}", address),
+        }
+    }
+}
+
+pub enum VisionStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl VisionStream {
+    // TCP links may cross machines, so they are only usable after vision has
+    // accepted the pre-shared token.
+    pub fn connect(
+        endpoint: &VisionEndpoint,
+        token: Option<Arc<Vec<u8>>>,
+    ) -> Box<dyn Future<Item = VisionStream, Error = io::Error> + Send> {
+        match endpoint {
+            VisionEndpoint::Unix(path) => {
+                Box::new(UnixStream::connect(path).map(VisionStream::Unix))
+            }
+            VisionEndpoint::Tcp(_, socket_addr) => {
+                let token = match token {
+                    Some(token) => token,
+                    None => {
+                        return Box::new(futures::future::err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "a token is required for tcp endpoints",
+                        )))
+                    }
+                };
+                Box::new(
+                    TcpStream::connect(socket_addr)
+                        .and_then(|stream| {
+                            stream.set_nodelay(true)?;
+                            Ok(stream)
+                        })
+                        .and_then(move |stream| send_token(stream, &token))
+                        .map(VisionStream::Tcp),
+                )
+            }
+        }
+    }
+}
+
+fn send_token(stream: TcpStream, token: &[u8]) -> impl Future<Item = TcpStream, Error = io::Error> {
+    let mut hello = Vec::with_capacity(8 + token.len());
+    hello.extend_from_slice(&TOKEN_MAGIC);
+    let mut len = [0; 4];
+    LittleEndian::write_u32(&mut len, token.len() as u32);
+    hello.extend_from_slice(&len);
+    hello.extend_from_slice(token);
+
+    tokio::io::write_all(stream, hello)
+        .and_then(|(stream, _)| tokio::io::read_exact(stream, [0; 4]))
+        .and_then(|(stream, status)| {
+            if LittleEndian::read_u32(&status) == TOKEN_ACCEPTED {
+                Ok(stream)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "vision rejected the token",
+                ))
+            }
+        })
+}
+
+impl io::Read for VisionStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            VisionStream::Unix(stream) => stream.read(buf),
+            VisionStream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl io::Write for VisionStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            VisionStream::Unix(stream) => stream.write(buf),
+            VisionStream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            VisionStream::Unix(stream) => stream.flush(),
+            VisionStream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsyncRead for VisionStream {}
+
+impl AsyncWrite for VisionStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self {
+            VisionStream::Unix(stream) => AsyncWrite::shutdown(stream),
+            VisionStream::Tcp(stream) => AsyncWrite::shutdown(stream),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{verify_token, MAX_TOKEN_LEN};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    // Connects over loopback to a listener that checks the token as vision
+    // does, and returns what reflex and the listener made of it.
+    fn connect_with(token: Vec<u8>) -> (io::Result<()>, io::Result<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint =
+            VisionEndpoint::parse(&format!("tcp:{}", listener.local_addr().unwrap())).unwrap();
+        let vision = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            verify_token(&mut stream, b"secret")
+        });
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let reflex = runtime
+            .block_on(VisionStream::connect(&endpoint, Some(Arc::new(token))))
+            .map(|_| ());
+        (reflex, vision.join().unwrap())
+    }
+
+    #[test]
+    fn good_token() {
+        let (reflex, vision) = connect_with(b"secret".to_vec());
+        assert!(reflex.is_ok());
+        assert!(vision.is_ok());
+    }
+
+    #[test]
+    fn bad_token() {
+        let (reflex, vision) = connect_with(b"secreT".to_vec());
+        assert_eq!(reflex.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(vision.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn token_too_long() {
+        // Rejected from its size alone, so reflex may see the connection
+        // reset rather than the status.
+        let (reflex, vision) = connect_with(vec![b's'; MAX_TOKEN_LEN + 1]);
+        assert!(reflex.is_err());
+        assert_eq!(vision.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+}
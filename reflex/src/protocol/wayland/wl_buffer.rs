@@ -23,6 +23,7 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::fd_queue::OwnedFd;
 #[allow(unused_imports)]
 use crate::protocol::session::{Context, NextAction, Session};
 #[allow(unused_imports)]
@@ -43,10 +44,12 @@ pub use lib::*;
 // similar. It has a width and a height and can be attached to a
 // wl_surface, but the mechanism by which a client provides and
 // updates the contents is defined by the buffer factory interface.
+#[derive(Clone)]
 pub struct WlBuffer {
-    pub offset: i32, // int: buffer byte offset within the pool
-    pub width: i32,  // int: buffer width, in pixels
-    pub height: i32, // int: buffer height, in pixels
+    pub fd: Arc<OwnedFd>, // fd: file descriptor of the pool
+    pub offset: i32,      // int: buffer byte offset within the pool
+    pub width: i32,       // int: buffer width, in pixels
+    pub height: i32,      // int: buffer height, in pixels
     pub stride: i32, // int: number of bytes from the beginning of one row to the beginning of the next row
     pub format: u32, // uint: buffer pixel format
 }
@@ -61,6 +64,6 @@ impl WlBuffer {
     pub fn destroy(
         context: Context<WlBuffer>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_buffer::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 }
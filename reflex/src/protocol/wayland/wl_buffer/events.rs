@@ -65,4 +65,8 @@ impl super::super::super::event::Event for Release {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -57,4 +57,8 @@ impl super::super::super::event::Event for Done {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
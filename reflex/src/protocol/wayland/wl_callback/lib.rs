@@ -41,12 +41,39 @@ use std::sync::{Arc, RwLock};
 #[allow(dead_code)]
 pub const VERSION: u32 = 1;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(_opcode: u16) -> u32 {
+    1
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(_opcode: u16, _args: &mut crate::protocol::trace::Args) -> Option<String> {
+    None
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!("done({})", args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_callback",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
     context: crate::protocol::session::Context<crate::protocol::wayland::wl_callback::WlCallback>,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     return context.invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, args));
 }
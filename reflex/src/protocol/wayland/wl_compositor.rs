@@ -61,9 +61,12 @@ impl WlCompositor {
         mut context: Context<Arc<RwLock<WlCompositor>>>,
         id: u32, // new_id: the new surface
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context
+        if let Err(err) = context
             .resources
-            .insert(id, wayland::wl_surface::WlSurface {}.into());
+            .insert(id, wayland::wl_surface::WlSurface::new().into())
+        {
+            return context.invalid_object(format!("wl_surface@{}: {}", id, err));
+        }
         return context.ok();
     }
 }
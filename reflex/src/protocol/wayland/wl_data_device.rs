@@ -53,7 +53,7 @@ impl WlDataDevice {
     pub fn release(
         context: Context<WlDataDevice>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_data_device::release is not implemented yet".to_string())
+        context.destroy()
     }
 
     // copy data to the selection
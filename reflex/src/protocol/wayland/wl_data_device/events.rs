@@ -63,6 +63,10 @@ impl super::super::super::event::Event for DataOffer {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // end drag-and-drop session successfully
@@ -106,6 +110,10 @@ impl super::super::super::event::Event for Drop {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // initiate drag-and-drop session
@@ -155,6 +163,10 @@ impl super::super::super::event::Event for Enter {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // end drag-and-drop session
@@ -188,6 +200,10 @@ impl super::super::super::event::Event for Leave {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // drag-and-drop session motion
@@ -231,6 +247,10 @@ impl super::super::super::event::Event for Motion {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // advertise new selection
@@ -275,4 +295,8 @@ impl super::super::super::event::Event for Selection {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -41,6 +41,68 @@ use std::sync::{Arc, RwLock};
 #[allow(dead_code)]
 pub const VERSION: u32 = 3;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(_opcode: u16) -> u32 {
+    1
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "start_drag({}, {}, {}, {})",
+            args.object(Some("wl_data_source")),
+            args.object(Some("wl_surface")),
+            args.object(Some("wl_surface")),
+            args.uint()
+        )),
+        1 => Some(format!(
+            "set_selection({}, {})",
+            args.object(Some("wl_data_source")),
+            args.uint()
+        )),
+        2 => Some("release()".to_owned()),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "data_offer({})",
+            args.new_id(Some("wl_data_offer"))
+        )),
+        1 => Some(format!(
+            "enter({}, {}, {}, {}, {})",
+            args.uint(),
+            args.object(Some("wl_surface")),
+            args.fixed(),
+            args.fixed(),
+            args.object(Some("wl_data_offer"))
+        )),
+        2 => Some("leave()".to_owned()),
+        3 => Some(format!(
+            "motion({}, {}, {})",
+            args.uint(),
+            args.fixed(),
+            args.fixed()
+        )),
+        4 => Some("drop()".to_owned()),
+        5 => Some(format!("selection({})", args.object(Some("wl_data_offer")))),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_data_device",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
@@ -48,7 +110,7 @@ pub fn dispatch_request(
         crate::protocol::wayland::wl_data_device::WlDataDevice,
     >,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -97,14 +159,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + 4 + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -113,11 +178,20 @@ pub fn dispatch_request(
                 #[allow(unused_mut)]
                 let mut encode_offset = 8;
 
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_source);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_source),
+                );
                 encode_offset += 4;
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_origin);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_origin),
+                );
                 encode_offset += 4;
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_icon);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_icon),
+                );
                 encode_offset += 4;
                 NativeEndian::write_u32(&mut dst[encode_offset..], arg_serial);
                 encode_offset += 4;
@@ -171,14 +245,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -187,7 +264,10 @@ pub fn dispatch_request(
                 #[allow(unused_mut)]
                 let mut encode_offset = 8;
 
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_source);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_source),
+                );
                 encode_offset += 4;
                 NativeEndian::write_u32(&mut dst[encode_offset..], arg_serial);
                 encode_offset += 4;
@@ -221,14 +301,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
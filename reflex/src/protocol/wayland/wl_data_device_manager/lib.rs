@@ -43,6 +43,41 @@ pub const GLOBAL_SINGLETON_NAME: u32 = 5;
 #[allow(dead_code)]
 pub const VERSION: u32 = 3;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(_opcode: u16) -> u32 {
+    1
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "create_data_source({})",
+            args.new_id(Some("wl_data_source"))
+        )),
+        1 => Some(format!(
+            "get_data_device({}, {})",
+            args.new_id(Some("wl_data_device")),
+            args.object(Some("wl_seat"))
+        )),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(_opcode: u16, _args: &mut crate::protocol::trace::Args) -> Option<String> {
+    None
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_data_device_manager",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
@@ -50,7 +85,7 @@ pub fn dispatch_request(
         Arc<RwLock<crate::protocol::wayland::wl_data_device_manager::WlDataDeviceManager>>,
     >,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -75,14 +110,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -91,7 +129,10 @@ pub fn dispatch_request(
                 #[allow(unused_mut)]
                 let mut encode_offset = 8;
 
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_id);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_id),
+                );
                 encode_offset += 4;
                 let _ = encode_offset;
                 dst
@@ -140,14 +181,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -156,9 +200,15 @@ pub fn dispatch_request(
                 #[allow(unused_mut)]
                 let mut encode_offset = 8;
 
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_id);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_id),
+                );
                 encode_offset += 4;
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_seat);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_seat),
+                );
                 encode_offset += 4;
                 let _ = encode_offset;
                 dst
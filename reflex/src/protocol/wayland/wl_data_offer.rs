@@ -23,6 +23,8 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::fd_queue::OwnedFd;
+use crate::protocol::object_map::ObjectMap;
 #[allow(unused_imports)]
 use crate::protocol::session::{Context, NextAction, Session};
 #[allow(unused_imports)]
@@ -48,6 +50,13 @@ pub use lib::*;
 pub struct WlDataOffer {}
 
 impl WlDataOffer {
+    // Data offers are introduced by the compositor (wl_data_device.data_offer),
+    // so they live in the server side of the object id space.
+    #[allow(dead_code)]
+    pub fn create(resources: &mut ObjectMap) -> u32 {
+        resources.insert_server(WlDataOffer {}.into())
+    }
+
     // accept one of the offered mime types
     //
     // Indicate that the client can accept the given mime type, or
@@ -78,7 +87,7 @@ impl WlDataOffer {
     pub fn destroy(
         context: Context<WlDataOffer>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_data_offer::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // the offer will no longer be used
@@ -120,7 +129,7 @@ impl WlDataOffer {
     pub fn receive(
         context: Context<WlDataOffer>,
         _mime_type: String, // string: mime type desired by receiver
-        _fd: i32,           // fd: file descriptor for data transfer
+        _fd: OwnedFd,       // fd: file descriptor for data transfer
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
         context.invalid_method("wl_data_offer::receive is not implemented yet".to_string())
     }
@@ -91,6 +91,10 @@ impl super::super::super::event::Event for Action {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // advertise offered mime type
@@ -136,6 +140,10 @@ impl super::super::super::event::Event for Offer {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // notify the source-side available actions
@@ -172,4 +180,8 @@ impl super::super::super::event::Event for SourceActions {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
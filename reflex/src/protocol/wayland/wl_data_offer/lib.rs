@@ -41,14 +41,54 @@ use std::sync::{Arc, RwLock};
 #[allow(dead_code)]
 pub const VERSION: u32 = 3;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(opcode: u16) -> u32 {
+    match opcode {
+        1 => 3,
+        2 => 3,
+        _ => 1,
+    }
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!("accept({}, {})", args.uint(), args.string())),
+        1 => Some(format!("receive({}, {})", args.string(), args.fd())),
+        2 => Some("destroy()".to_owned()),
+        3 => Some("finish()".to_owned()),
+        4 => Some(format!("set_actions({}, {})", args.uint(), args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!("offer({})", args.string())),
+        1 => Some(format!("source_actions({})", args.uint())),
+        2 => Some(format!("action({})", args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_data_offer",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
-    mut context: crate::protocol::session::Context<
+    context: crate::protocol::session::Context<
         crate::protocol::wayland::wl_data_offer::WlDataOffer,
     >,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -102,14 +142,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + { 4 + (arg_mime_type.len() + 1 + 3) / 4 * 4 };
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -185,17 +228,13 @@ pub fn dispatch_request(
                 cursor.set_position(cursor.position() + u64::from(padded_buf_len - buf_len));
                 s
             };
-            if context.fds.is_empty() {
+            let arg_fd = if let Some(x) = context.fds.pop() {
+                x
+            } else {
                 return context.invalid_method_dispatch(format!(
                     "opcode={} args={:?} not found",
                     opcode, args
                 ));
-            }
-            let arg_fd = {
-                let rest = context.fds.split_off(1);
-                let first = context.fds.pop().expect("fds");
-                context.fds = rest;
-                first
             };
 
             if Ok(cursor.position()) != args.len().try_into() {
@@ -207,14 +246,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + { 4 + (arg_mime_type.len() + 1 + 3) / 4 * 4 } + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -238,7 +280,7 @@ pub fn dispatch_request(
                 }
 
                 encode_offset += { 4 + (arg_mime_type.len() + 1 + 3) / 4 * 4 };
-                NativeEndian::write_i32(&mut dst[encode_offset..], arg_fd);
+                NativeEndian::write_i32(&mut dst[encode_offset..], arg_fd.as_raw_fd());
                 encode_offset += 4;
                 let _ = encode_offset;
                 dst
@@ -270,14 +312,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -314,14 +359,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -375,14 +423,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
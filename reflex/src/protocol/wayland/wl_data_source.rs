@@ -52,7 +52,7 @@ impl WlDataSource {
     pub fn destroy(
         context: Context<WlDataSource>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_data_source::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // add an offered mime type
@@ -81,6 +81,10 @@ impl super::super::super::event::Event for Action {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // selection was cancelled
@@ -131,6 +135,10 @@ impl super::super::super::event::Event for Cancelled {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // the drag-and-drop operation physically finished
@@ -170,6 +178,10 @@ impl super::super::super::event::Event for DndDropPerformed {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // the drag-and-drop operation concluded
@@ -206,6 +218,10 @@ impl super::super::super::event::Event for DndFinished {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // send the data
@@ -217,7 +233,7 @@ impl super::super::super::event::Event for DndFinished {
 pub struct Send {
     pub sender_object_id: u32,
     pub mime_type: String, // string: mime type for the data
-    pub fd: i32,           // fd: file descriptor for the data
+    pub fd: std::fs::File, // fd: file descriptor for the data
 }
 
 impl super::super::super::event::Event for Send {
@@ -250,11 +266,20 @@ impl super::super::super::event::Event for Send {
         }
 
         encode_offset += { 4 + (self.mime_type.len() + 1 + 3) / 4 * 4 };
-        println!("UNIMPLEMENTED!!!!!");
+        // The fd goes as ancillary data, not in the bytes.
         encode_offset += 0;
         let _ = encode_offset;
         Ok(())
     }
+
+    fn fds(&self) -> Vec<std::os::unix::io::RawFd> {
+        use std::os::unix::io::AsRawFd;
+        vec![self.fd.as_raw_fd()]
+    }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // a target accepts an offered mime type
@@ -302,4 +327,8 @@ impl super::super::super::event::Event for Target {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
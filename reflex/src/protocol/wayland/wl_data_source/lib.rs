@@ -41,6 +41,48 @@ use std::sync::{Arc, RwLock};
 #[allow(dead_code)]
 pub const VERSION: u32 = 3;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(opcode: u16) -> u32 {
+    match opcode {
+        3 => 3,
+        4 => 3,
+        5 => 3,
+        _ => 1,
+    }
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!("offer({})", args.string())),
+        1 => Some("destroy()".to_owned()),
+        2 => Some(format!("set_actions({})", args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!("target({})", args.string())),
+        1 => Some(format!("send({}, {})", args.string(), args.fd())),
+        2 => Some("cancelled()".to_owned()),
+        3 => Some("dnd_drop_performed()".to_owned()),
+        4 => Some("dnd_finished()".to_owned()),
+        5 => Some(format!("action({})", args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_data_source",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
@@ -48,7 +90,7 @@ pub fn dispatch_request(
         crate::protocol::wayland::wl_data_source::WlDataSource,
     >,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -94,14 +136,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + { 4 + (arg_mime_type.len() + 1 + 3) / 4 * 4 };
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -153,14 +198,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -206,14 +254,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
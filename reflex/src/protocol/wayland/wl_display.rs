@@ -59,11 +59,27 @@ impl WlDisplay {
         mut context: Context<Arc<RwLock<WlDisplay>>>,
         registry: u32, // new_id: global registry object
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlDisplay::get_registry({})", registry);
-        context
+        log::debug!("WlDisplay::get_registry({})", registry);
+        if let Err(err) = context
             .resources
-            .insert(registry, context.wl_registry.clone().into());
-        context.ok()
+            .insert(registry, context.wl_registry.clone().into())
+        {
+            return context.invalid_object(format!("wl_registry@{}: {}", registry, err));
+        }
+
+        // Globals are owned by reflex. Vision only learns about the registry
+        // object itself through the relayed request.
+        let events = context.wl_registry.write().unwrap().bind_registry(
+            context.client_id,
+            registry,
+            context.tx.clone(),
+            context.vision.hangup(context.client_id),
+        );
+        let tx = context.tx.clone();
+        Box::new(
+            crate::protocol::wayland::wl_registry::send_globals(tx, events)
+                .and_then(|_| context.ok()),
+        )
     }
 
     // asynchronous roundtrip
@@ -80,10 +96,36 @@ impl WlDisplay {
     //
     // The callback_data passed in the callback is the event serial.
     pub fn sync(
-        context: Context<Arc<RwLock<WlDisplay>>>,
+        mut context: Context<Arc<RwLock<WlDisplay>>>,
         callback: u32, // new_id: callback object for the sync request
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlDisplay::sync({})", callback);
-        context.ok()
+        log::debug!("WlDisplay::sync({})", callback);
+        if let Err(err) = context.resources.validate_new_id(callback) {
+            return context.invalid_object(format!("wl_callback@{}: {}", callback, err));
+        }
+
+        // Requests are dispatched one at a time and each relay (including
+        // RelayWait) completes before the next request is read, so every
+        // earlier request of this client has been handled by now. Answer
+        // locally instead of waiting for a round trip to vision.
+        context.callback_data += 1;
+        let tx = context.tx.clone();
+        let callback_data = context.callback_data;
+        Box::new(
+            tx.send(Box::new(
+                crate::protocol::wayland::wl_callback::events::Done {
+                    sender_object_id: callback,
+                    callback_data,
+                },
+            ))
+            .and_then(move |tx| {
+                tx.send(Box::new(events::DeleteId {
+                    sender_object_id: 1,
+                    id: callback,
+                }))
+            })
+            .map_err(|_| ())
+            .map(|_| (context.into(), NextAction::Nop)),
+        )
     }
 }
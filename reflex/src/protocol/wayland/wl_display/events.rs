@@ -61,6 +61,10 @@ impl super::super::super::event::Event for DeleteId {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // fatal error event
@@ -117,4 +121,8 @@ impl super::super::super::event::Event for Error {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -39,7 +39,7 @@ pub struct Enter {
 
 impl super::super::super::event::Event for Enter {
     fn encode(&self, dst: &mut bytes::BytesMut) -> Result<(), std::io::Error> {
-        let total_len = 8 + 4 + 4 + { 4 + (self.keys.len() + 1 + 3) / 4 * 4 };
+        let total_len = 8 + 4 + 4 + { 4 + (self.keys.len() + 3) / 4 * 4 };
         if total_len > 0xffff {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Oops!"));
         }
@@ -70,10 +70,14 @@ impl super::super::super::event::Event for Enter {
                 .copy_from_slice(&aligned_keys[..]);
         }
 
-        encode_offset += { 4 + (self.keys.len() + 1 + 3) / 4 * 4 };
+        encode_offset += { 4 + (self.keys.len() + 3) / 4 * 4 };
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // key event
@@ -119,6 +123,10 @@ impl super::super::super::event::Event for Key {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // keyboard mapping
@@ -128,9 +136,9 @@ impl super::super::super::event::Event for Key {
 #[allow(dead_code)]
 pub struct Keymap {
     pub sender_object_id: u32,
-    pub format: u32, // uint: keymap format
-    pub fd: i32,     // fd: keymap file descriptor
-    pub size: u32,   // uint: keymap size, in bytes
+    pub format: u32,       // uint: keymap format
+    pub fd: std::fs::File, // fd: keymap file descriptor
+    pub size: u32,         // uint: keymap size, in bytes
 }
 
 impl super::super::super::event::Event for Keymap {
@@ -153,13 +161,22 @@ impl super::super::super::event::Event for Keymap {
         encode_offset += 8;
         NativeEndian::write_u32(&mut dst[encode_offset..], self.format);
         encode_offset += 4;
-        println!("UNIMPLEMENTED!!!!!");
+        // The fd goes as ancillary data, not in the bytes.
         encode_offset += 0;
         NativeEndian::write_u32(&mut dst[encode_offset..], self.size);
         encode_offset += 4;
         let _ = encode_offset;
         Ok(())
     }
+
+    fn fds(&self) -> Vec<std::os::unix::io::RawFd> {
+        use std::os::unix::io::AsRawFd;
+        vec![self.fd.as_raw_fd()]
+    }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // leave event
@@ -201,6 +218,10 @@ impl super::super::super::event::Event for Leave {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // modifier and group state
@@ -248,6 +269,10 @@ impl super::super::super::event::Event for Modifiers {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // repeat rate and delay
@@ -296,4 +321,8 @@ impl super::super::super::event::Event for RepeatInfo {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
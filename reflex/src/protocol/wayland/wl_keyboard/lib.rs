@@ -41,12 +41,77 @@ use std::sync::{Arc, RwLock};
 #[allow(dead_code)]
 pub const VERSION: u32 = 6;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(opcode: u16) -> u32 {
+    match opcode {
+        5 => 4,
+        _ => 1,
+    }
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, _args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some("release()".to_owned()),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "keymap({}, {}, {})",
+            args.uint(),
+            args.fd(),
+            args.uint()
+        )),
+        1 => Some(format!(
+            "enter({}, {}, {})",
+            args.uint(),
+            args.object(Some("wl_surface")),
+            args.array()
+        )),
+        2 => Some(format!(
+            "leave({}, {})",
+            args.uint(),
+            args.object(Some("wl_surface"))
+        )),
+        3 => Some(format!(
+            "key({}, {}, {}, {})",
+            args.uint(),
+            args.uint(),
+            args.uint(),
+            args.uint()
+        )),
+        4 => Some(format!(
+            "modifiers({}, {}, {}, {}, {})",
+            args.uint(),
+            args.uint(),
+            args.uint(),
+            args.uint(),
+            args.uint()
+        )),
+        5 => Some(format!("repeat_info({}, {})", args.int(), args.int())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_keyboard",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
     context: crate::protocol::session::Context<crate::protocol::wayland::wl_keyboard::WlKeyboard>,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -62,14 +127,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -55,6 +55,6 @@ impl WlOutput {
     pub fn release(
         context: Context<WlOutput>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_output::release is not implemented yet".to_string())
+        context.destroy()
     }
 }
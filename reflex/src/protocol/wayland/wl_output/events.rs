@@ -58,6 +58,10 @@ impl super::super::super::event::Event for Done {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // properties of the output
@@ -146,6 +150,10 @@ impl super::super::super::event::Event for Geometry {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // advertise available modes for the output
@@ -201,6 +209,10 @@ impl super::super::super::event::Event for Mode {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // output scaling properties
@@ -252,4 +264,8 @@ impl super::super::super::event::Event for Scale {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -78,6 +78,10 @@ impl super::super::super::event::Event for Axis {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // axis click event
@@ -140,6 +144,10 @@ impl super::super::super::event::Event for AxisDiscrete {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // axis source event
@@ -198,6 +206,10 @@ impl super::super::super::event::Event for AxisSource {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // axis stop event
@@ -248,6 +260,10 @@ impl super::super::super::event::Event for AxisStop {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // pointer button event
@@ -304,6 +320,10 @@ impl super::super::super::event::Event for Button {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // enter event
@@ -352,6 +372,10 @@ impl super::super::super::event::Event for Enter {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // end of a pointer event sequence
@@ -416,6 +440,10 @@ impl super::super::super::event::Event for Frame {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // leave event
@@ -457,6 +485,10 @@ impl super::super::super::event::Event for Leave {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // pointer motion event
@@ -499,4 +531,8 @@ impl super::super::super::event::Event for Motion {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -41,12 +41,89 @@ use std::sync::{Arc, RwLock};
 #[allow(dead_code)]
 pub const VERSION: u32 = 6;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(opcode: u16) -> u32 {
+    match opcode {
+        5 => 5,
+        6 => 5,
+        7 => 5,
+        8 => 5,
+        _ => 1,
+    }
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "set_cursor({}, {}, {}, {})",
+            args.uint(),
+            args.object(Some("wl_surface")),
+            args.int(),
+            args.int()
+        )),
+        1 => Some("release()".to_owned()),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "enter({}, {}, {}, {})",
+            args.uint(),
+            args.object(Some("wl_surface")),
+            args.fixed(),
+            args.fixed()
+        )),
+        1 => Some(format!(
+            "leave({}, {})",
+            args.uint(),
+            args.object(Some("wl_surface"))
+        )),
+        2 => Some(format!(
+            "motion({}, {}, {})",
+            args.uint(),
+            args.fixed(),
+            args.fixed()
+        )),
+        3 => Some(format!(
+            "button({}, {}, {}, {})",
+            args.uint(),
+            args.uint(),
+            args.uint(),
+            args.uint()
+        )),
+        4 => Some(format!(
+            "axis({}, {}, {})",
+            args.uint(),
+            args.uint(),
+            args.fixed()
+        )),
+        5 => Some("frame()".to_owned()),
+        6 => Some(format!("axis_source({})", args.uint())),
+        7 => Some(format!("axis_stop({}, {})", args.uint(), args.uint())),
+        8 => Some(format!("axis_discrete({}, {})", args.uint(), args.int())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_pointer",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
     context: crate::protocol::session::Context<crate::protocol::wayland::wl_pointer::WlPointer>,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -95,14 +172,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + 4 + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -113,7 +193,10 @@ pub fn dispatch_request(
 
                 NativeEndian::write_u32(&mut dst[encode_offset..], arg_serial);
                 encode_offset += 4;
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_surface);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_surface),
+                );
                 encode_offset += 4;
                 NativeEndian::write_i32(&mut dst[encode_offset..], arg_hotspot_x);
                 encode_offset += 4;
@@ -156,14 +239,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -23,106 +23,20 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::event::Event;
+use crate::protocol::event_sink::Hangup;
+use crate::protocol::resource::Resource;
 use crate::protocol::session::{Context, NextAction, Session};
-use byteorder::{ByteOrder, NativeEndian, ReadBytesExt};
-use futures::future::{ok, Future};
+use futures::future::Future;
 use futures::sink::Sink;
-use std::convert::TryInto;
-use std::io::{Cursor, Read};
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::Sender;
 
 pub mod events;
 mod lib;
-pub use lib::{GLOBAL_SINGLETON_NAME, VERSION};
-
-pub fn dispatch_request(
-    context: crate::protocol::session::Context<
-        Arc<RwLock<crate::protocol::wayland::wl_registry::WlRegistry>>,
-    >,
-    opcode: u16,
-    args: Vec<u8>,
-) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
-    if opcode != 0 || args.len() <= 8 {
-        return lib::dispatch_request(context, opcode, args);
-    }
-
-    let mut cursor = Cursor::new(&args);
-    let name = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-    let name_buf_len = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x as usize
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-    let name_buf_len_with_pad = (name_buf_len + 3) / 4 * 4;
-    let mut name_buf = Vec::new();
-    name_buf.resize(name_buf_len, 0);
-    cursor.read_exact(&mut name_buf).unwrap();
-    cursor.set_position(cursor.position() + (name_buf_len_with_pad - name_buf_len) as u64);
-    let _version = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-
-    let id = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-
-    if Ok(cursor.position()) != args.len().try_into() {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    }
-
-    let relay_buf = {
-        let total_len = 8 + 4 + 4;
-        if total_len > 0xffff {
-            println!("Oops! total_len={}", total_len);
-            return Box::new(futures::future::err(()));
-        }
-
-        let mut dst: Vec<u8> = Vec::new();
-        dst.resize(total_len, 0);
-
-        NativeEndian::write_u32(&mut dst[0..], context.sender_object_id);
-        NativeEndian::write_u32(&mut dst[4..], (total_len << 16) as u32 | u32::from(opcode));
-
-        #[allow(unused_mut)]
-        let mut encode_offset = 8;
-
-        NativeEndian::write_u32(&mut dst[encode_offset..], name);
-        encode_offset += 4;
-        NativeEndian::write_u32(&mut dst[encode_offset..], id);
-        encode_offset += 4;
-        let _ = encode_offset;
-        dst
-    };
-
-    Box::new(WlRegistry::bind(context, name, id).and_then(
-        |(session, next_action)| -> Box<
-            dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()>
-                + Send,
-        > {
-            match next_action {
-                NextAction::Nop => Box::new(futures::future::ok(session)),
-                NextAction::Relay => {
-                    println!("[WlRegistry Relay]: {:?}", &relay_buf);
-                    session.relay(relay_buf)
-                }
-                NextAction::RelayWait => session.relay_wait(relay_buf),
-            }
-        },
-    ))
-}
+pub use lib::*;
 
 // global registry object
 //
@@ -146,7 +60,210 @@ pub fn dispatch_request(
 // request.  This creates a client-side handle that lets the object
 // emit events to the client and lets the client invoke requests on
 // the object.
-pub struct WlRegistry {}
+pub struct WlRegistry {
+    globals: Vec<RegistryGlobal>,
+    next_name: u32,
+    bindings: Vec<RegistryBinding>,
+    // Host monitor id reported by vision -> name of its wl_output global.
+    outputs: HashMap<u32, u32>,
+}
+
+#[derive(Clone)]
+pub struct RegistryGlobal {
+    pub name: u32,
+    pub interface: String,
+    pub version: u32,
+}
+
+// A wl_registry object of some client, which is told about globals coming
+// and going.
+struct RegistryBinding {
+    client_id: u32,
+    registry_id: u32,
+    tx: Sender<Box<dyn Event + Send>>,
+    hangup: Option<Hangup>,
+}
+
+impl RegistryBinding {
+    // A client whose queue is full would be left with a wrong view of the
+    // globals for good, so it is disconnected instead.
+    fn send(&mut self, event: Box<dyn Event + Send>) {
+        if let Err(err) = self.tx.try_send(event) {
+            if err.is_full() {
+                log::warn!(
+                    "[Registry] client {} is not reading, disconnecting",
+                    self.client_id
+                );
+                if let Some(hangup) = &self.hangup {
+                    hangup.hang_up();
+                }
+            }
+        }
+    }
+}
+
+// The interfaces reflex can advertise as globals and the highest version
+// it implements for each of them.
+pub const SUPPORTED_GLOBALS: [(&str, u32); 6] = [
+    (
+        "wl_compositor",
+        crate::protocol::wayland::wl_compositor::VERSION,
+    ),
+    ("wl_shm", crate::protocol::wayland::wl_shm::VERSION),
+    (
+        "wl_data_device_manager",
+        crate::protocol::wayland::wl_data_device_manager::VERSION,
+    ),
+    ("wl_seat", crate::protocol::wayland::wl_seat::VERSION),
+    ("wl_output", crate::protocol::wayland::wl_output::VERSION),
+    (
+        "xdg_wm_base",
+        crate::protocol::xdg_shell::xdg_wm_base::VERSION,
+    ),
+];
+
+pub fn supported_version(interface: &str) -> Option<u32> {
+    SUPPORTED_GLOBALS
+        .iter()
+        .find(|(name, _)| *name == interface)
+        .map(|(_, version)| *version)
+}
+
+impl WlRegistry {
+    pub fn new(globals: &[(String, u32)]) -> WlRegistry {
+        let mut wl_registry = WlRegistry {
+            globals: Vec::new(),
+            next_name: 1,
+            bindings: Vec::new(),
+            outputs: HashMap::new(),
+        };
+        for (interface, version) in globals {
+            wl_registry.add_global(interface, *version);
+        }
+        wl_registry
+    }
+
+    pub fn find_global(&self, name: u32) -> Option<&RegistryGlobal> {
+        self.globals.iter().find(|global| global.name == name)
+    }
+
+    // Names are never reused, so a name below next_name that is not found
+    // belonged to a global that has been removed.
+    pub fn is_removed_global(&self, name: u32) -> bool {
+        name != 0 && name < self.next_name && self.find_global(name).is_none()
+    }
+
+    // Add a global and announce it to every bound registry.
+    pub fn add_global(&mut self, interface: &str, version: u32) -> u32 {
+        let name = self.next_name;
+        self.next_name += 1;
+        let global = RegistryGlobal {
+            name,
+            interface: interface.to_owned(),
+            version,
+        };
+        for binding in &mut self.bindings {
+            let event = global_event(binding.registry_id, &global);
+            binding.send(Box::new(event));
+        }
+        log::info!("[Registry] add global {} {} v{}", name, interface, version);
+        self.globals.push(global);
+        name
+    }
+
+    // Remove a global, e.g. an unplugged output, and announce it to every
+    // bound registry.
+    pub fn remove_global(&mut self, name: u32) -> bool {
+        let len = self.globals.len();
+        self.globals.retain(|global| global.name != name);
+        if self.globals.len() == len {
+            return false;
+        }
+        for binding in &mut self.bindings {
+            let event = events::GlobalRemove {
+                sender_object_id: binding.registry_id,
+                name,
+            };
+            binding.send(Box::new(event));
+        }
+        log::info!("[Registry] remove global {}", name);
+        true
+    }
+
+    // Register a new wl_registry object and return the global events for the
+    // initial burst.
+    pub fn bind_registry(
+        &mut self,
+        client_id: u32,
+        registry_id: u32,
+        tx: Sender<Box<dyn Event + Send>>,
+        hangup: Option<Hangup>,
+    ) -> Vec<Box<dyn Event + Send>> {
+        self.bindings.push(RegistryBinding {
+            client_id,
+            registry_id,
+            tx,
+            hangup,
+        });
+        self.globals
+            .iter()
+            .map(|global| -> Box<dyn Event + Send> { Box::new(global_event(registry_id, global)) })
+            .collect()
+    }
+
+    // Every link reports the same host monitors, so this is idempotent.
+    pub fn output_changed(&mut self, output_id: u32, connected: bool) {
+        if connected && !self.outputs.contains_key(&output_id) {
+            let version = supported_version("wl_output").unwrap_or(1);
+            let name = self.add_global("wl_output", version);
+            self.outputs.insert(output_id, name);
+        } else if !connected {
+            if let Some(name) = self.outputs.remove(&output_id) {
+                self.remove_global(name);
+            }
+        }
+    }
+
+    pub fn unbind_client(&mut self, client_id: u32) {
+        self.bindings
+            .retain(|binding| binding.client_id != client_id);
+    }
+}
+
+fn global_event(registry_id: u32, global: &RegistryGlobal) -> events::Global {
+    events::Global {
+        sender_object_id: registry_id,
+        name: global.name,
+        interface: global.interface.clone(),
+        version: global.version,
+    }
+}
+
+// Create the resource backing a bound global.
+fn global_resource<T>(context: &Context<T>, interface: &str) -> Option<Resource>
+where
+    T: Into<Resource>,
+{
+    match interface {
+        "wl_compositor" => Some(context.wl_compositor.clone().into()),
+        "wl_shm" => Some(context.wl_shm.clone().into()),
+        "wl_data_device_manager" => Some(context.wl_data_device_manager.clone().into()),
+        "wl_seat" => Some(crate::protocol::wayland::wl_seat::WlSeat {}.into()),
+        "wl_output" => Some(crate::protocol::wayland::wl_output::WlOutput {}.into()),
+        "xdg_wm_base" => Some(context.xdg_wm_base.clone().into()),
+        _ => None,
+    }
+}
+
+// Send the initial burst of global events to a freshly created registry.
+pub fn send_globals(
+    tx: Sender<Box<dyn Event + Send>>,
+    events: Vec<Box<dyn Event + Send>>,
+) -> impl Future<Item = Sender<Box<dyn Event + Send>>, Error = ()> {
+    tx.send_all(futures::stream::iter_ok::<_, SendError>(events))
+        .map(|(tx, _)| tx)
+        .map_err(|_| ())
+}
 
 impl WlRegistry {
     // bind an object to the display
@@ -155,47 +272,69 @@ impl WlRegistry {
     // specified name as the identifier.
     pub fn bind(
         mut context: Context<Arc<RwLock<WlRegistry>>>,
-        name: u32, // uint: unique numeric name of the object
-        id: u32,   // new_id: bounded object
+        name: u32,         // uint: unique numeric name of the object
+        interface: String, // string: interface of the bound object
+        version: u32,      // uint: version of the bound object
+        id: u32,           // new_id: bounded object
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlRegistry::bind(name: {}, id: {})", name, id);
-
-        match name {
-            crate::protocol::wayland::wl_registry::GLOBAL_SINGLETON_NAME => {
-                context
-                    .resources
-                    .insert(id, context.wl_registry.clone().into());
-                return context.ok();
-            }
-            crate::protocol::wayland::wl_display::GLOBAL_SINGLETON_NAME => {
-                context
-                    .resources
-                    .insert(id, context.wl_display.clone().into());
-                return context.ok();
-            }
-            crate::protocol::wayland::wl_compositor::GLOBAL_SINGLETON_NAME => {
-                context
-                    .resources
-                    .insert(id, context.wl_compositor.clone().into());
-                return context.ok();
-            }
-            crate::protocol::wayland::wl_data_device_manager::GLOBAL_SINGLETON_NAME => {
-                context
-                    .resources
-                    .insert(id, context.wl_data_device_manager.clone().into());
-                return context.ok();
+        let interface = interface.trim_end_matches('\0');
+        log::debug!(
+            "WlRegistry::bind(name: {}, interface: {}, version: {}, id: {})",
+            name,
+            interface,
+            version,
+            id
+        );
+
+        let global = context
+            .wl_registry
+            .read()
+            .unwrap()
+            .find_global(name)
+            .cloned();
+        let global = match global {
+            Some(global) => global,
+            None if context.wl_registry.read().unwrap().is_removed_global(name) => {
+                // The client has not seen global_remove yet, which is not an
+                // error. The object is bound like any other and relayed, so
+                // that vision, which creates objects by interface, has it too.
+                log::warn!("[Registry] bind to removed global {}", name);
+                match supported_version(interface) {
+                    Some(version) => RegistryGlobal {
+                        name,
+                        interface: interface.to_owned(),
+                        version,
+                    },
+                    None => {
+                        return context
+                            .invalid_object(format!("invalid global {} ({})", interface, name));
+                    }
+                }
             }
-            crate::protocol::xdg_shell::xdg_wm_base::GLOBAL_SINGLETON_NAME => {
-                context
-                    .resources
-                    .insert(id, context.xdg_wm_base.clone().into());
-                return context.ok();
+            None => {
+                return context.invalid_object(format!("invalid global {} ({})", interface, name));
             }
-            crate::protocol::wayland::wl_shm::GLOBAL_SINGLETON_NAME => {
-                context.resources.insert(id, context.wl_shm.clone().into());
-                return context.ok();
+        };
+        if interface != global.interface {
+            return context.invalid_object(format!(
+                "invalid interface for global {}: have {}, wanted {}",
+                name, interface, global.interface
+            ));
+        }
+        if version == 0 || version > global.version {
+            return context.invalid_object(format!(
+                "invalid version for global {} ({}): have {}, wanted {}",
+                interface, name, global.version, version
+            ));
+        }
+        let resource = match global_resource(&context, interface) {
+            Some(resource) => resource,
+            None => {
+                return context.invalid_object(format!("invalid global {} ({})", interface, name));
             }
-            _ => {}
+        };
+        if let Err(err) = context.resources.insert_with_version(id, resource, version) {
+            return context.invalid_object(format!("{}@{}: {}", interface, id, err));
         }
 
         context.ok()
@@ -77,6 +77,10 @@ impl super::super::super::event::Event for Global {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // announce removal of global object
@@ -120,4 +124,8 @@ impl super::super::super::event::Event for GlobalRemove {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
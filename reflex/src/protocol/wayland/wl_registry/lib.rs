@@ -43,6 +43,48 @@ pub const GLOBAL_SINGLETON_NAME: u32 = 4;
 #[allow(dead_code)]
 pub const VERSION: u32 = 1;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(_opcode: u16) -> u32 {
+    1
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "bind({}, {}, {}, {})",
+            args.uint(),
+            args.string(),
+            args.uint(),
+            args.new_id(None)
+        )),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "global({}, {}, {})",
+            args.uint(),
+            args.string(),
+            args.uint()
+        )),
+        1 => Some(format!("global_remove({})", args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_registry",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
@@ -50,7 +92,7 @@ pub fn dispatch_request(
         Arc<RwLock<crate::protocol::wayland::wl_registry::WlRegistry>>,
     >,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -65,6 +107,43 @@ pub fn dispatch_request(
                     opcode, args
                 ));
             };
+            let arg_interface = {
+                let buf_len = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
+                    x
+                } else {
+                    return context.invalid_method_dispatch(format!(
+                        "opcode={} args={:?} not found",
+                        opcode, args
+                    ));
+                };
+                let padded_buf_len = (buf_len + 3) / 4 * 4;
+                let mut buf = Vec::new();
+                buf.resize(buf_len as usize, 0);
+                if cursor.read_exact(&mut buf).is_err() {
+                    return context.invalid_method_dispatch(format!(
+                        "opcode={} args={:?} not found",
+                        opcode, args
+                    ));
+                }
+                let s = if let Ok(x) = String::from_utf8(buf) {
+                    x
+                } else {
+                    return context.invalid_method_dispatch(format!(
+                        "opcode={} args={:?} not found",
+                        opcode, args
+                    ));
+                };
+                cursor.set_position(cursor.position() + u64::from(padded_buf_len - buf_len));
+                s
+            };
+            let arg_version = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
+                x
+            } else {
+                return context.invalid_method_dispatch(format!(
+                    "opcode={} args={:?} not found",
+                    opcode, args
+                ));
+            };
             let arg_id = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
                 x
             } else {
@@ -81,16 +160,19 @@ pub fn dispatch_request(
                 ));
             }
             let relay_buf = {
-                let total_len = 8 + 4 + 4;
+                let total_len = 8 + 4 + { 4 + (arg_interface.len() + 1 + 3) / 4 * 4 } + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -101,25 +183,48 @@ pub fn dispatch_request(
 
                 NativeEndian::write_u32(&mut dst[encode_offset..], arg_name);
                 encode_offset += 4;
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_id);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    (arg_interface.len() + 1) as u32,
+                );
+                {
+                    let mut aligned = arg_interface.clone();
+                    aligned.push(0u8.into());
+                    while aligned.len() % 4 != 0 {
+                        aligned.push(0u8.into());
+                    }
+                    dst[(encode_offset + 4)..(encode_offset + 4 + aligned.len())]
+                        .copy_from_slice(aligned.as_bytes());
+                }
+
+                encode_offset += { 4 + (arg_interface.len() + 1 + 3) / 4 * 4 };
+                NativeEndian::write_u32(&mut dst[encode_offset..], arg_version);
+                encode_offset += 4;
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_id),
+                );
                 encode_offset += 4;
                 let _ = encode_offset;
                 dst
             };
-            return Box::new(super::WlRegistry::bind(context, arg_name, arg_id).and_then(
-                |(session, next_action)| -> Box<
-                    dyn futures::future::Future<
-                            Item = crate::protocol::session::Session,
-                            Error = (),
-                        > + Send,
-                > {
-                    match next_action {
-                        NextAction::Nop => Box::new(futures::future::ok(session)),
-                        NextAction::Relay => session.relay(relay_buf),
-                        NextAction::RelayWait => session.relay_wait(relay_buf),
-                    }
-                },
-            ));
+            return Box::new(
+                super::WlRegistry::bind(context, arg_name, arg_interface, arg_version, arg_id)
+                    .and_then(
+                        |(session, next_action)| -> Box<
+                            dyn futures::future::Future<
+                                    Item = crate::protocol::session::Session,
+                                    Error = (),
+                                > + Send,
+                        > {
+                            match next_action {
+                                NextAction::Nop => Box::new(futures::future::ok(session)),
+                                NextAction::Relay => session.relay(relay_buf),
+                                NextAction::RelayWait => session.relay_wait(relay_buf),
+                            }
+                        },
+                    ),
+            );
         }
         _ => {}
     };
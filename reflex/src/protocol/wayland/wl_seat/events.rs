@@ -80,6 +80,10 @@ impl super::super::super::event::Event for Capabilities {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // unique identifier for this seat
@@ -126,4 +130,8 @@ impl super::super::super::event::Event for Name {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
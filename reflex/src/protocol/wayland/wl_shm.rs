@@ -23,6 +23,7 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::fd_queue::OwnedFd;
 #[allow(unused_imports)]
 use crate::protocol::session::{Context, NextAction, Session};
 #[allow(unused_imports)]
@@ -60,14 +61,20 @@ impl WlShm {
     // descriptor, to use as backing memory for the pool.
     pub fn create_pool(
         mut context: Context<Arc<RwLock<WlShm>>>,
-        id: u32,   // new_id: pool to create
-        fd: i32,   // fd: file descriptor for the pool
-        size: i32, // int: pool size, in bytes
+        id: u32,     // new_id: pool to create
+        fd: OwnedFd, // fd: file descriptor for the pool
+        size: i32,   // int: pool size, in bytes
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.resources.insert(
+        if let Err(err) = context.resources.insert(
             id,
-            crate::protocol::wayland::wl_shm_pool::WlShmPool { fd, size }.into(),
-        );
+            crate::protocol::wayland::wl_shm_pool::WlShmPool {
+                fd: Arc::new(fd),
+                size,
+            }
+            .into(),
+        ) {
+            return context.invalid_object(format!("wl_shm_pool@{}: {}", id, err));
+        }
         context.ok()
     }
 }
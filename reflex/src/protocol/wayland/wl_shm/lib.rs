@@ -43,14 +43,49 @@ pub const GLOBAL_SINGLETON_NAME: u32 = 3;
 #[allow(dead_code)]
 pub const VERSION: u32 = 1;
 
+// The interface version in which each event was introduced.
+#[allow(dead_code)]
+pub fn event_since(_opcode: u16) -> u32 {
+    1
+}
+
+// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.
+#[allow(dead_code)]
+pub fn format_request(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!(
+            "create_pool({}, {}, {})",
+            args.new_id(Some("wl_shm_pool")),
+            args.fd(),
+            args.int()
+        )),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn format_event(opcode: u16, args: &mut crate::protocol::trace::Args) -> Option<String> {
+    match opcode {
+        0 => Some(format!("format({})", args.uint())),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+    name: "wl_shm",
+    format_request,
+    format_event,
+};
+
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub fn dispatch_request(
-    mut context: crate::protocol::session::Context<
+    context: crate::protocol::session::Context<
         Arc<RwLock<crate::protocol::wayland::wl_shm::WlShm>>,
     >,
     opcode: u16,
-    args: Vec<u8>,
+    args: bytes::Bytes,
 ) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
     let sender_object_id = context.sender_object_id;
     #[allow(unused_mut)]
@@ -65,17 +100,13 @@ pub fn dispatch_request(
                     opcode, args
                 ));
             };
-            if context.fds.is_empty() {
+            let arg_fd = if let Some(x) = context.fds.pop() {
+                x
+            } else {
                 return context.invalid_method_dispatch(format!(
                     "opcode={} args={:?} not found",
                     opcode, args
                 ));
-            }
-            let arg_fd = {
-                let rest = context.fds.split_off(1);
-                let first = context.fds.pop().expect("fds");
-                context.fds = rest;
-                first
             };
             let arg_size = if let Ok(x) = cursor.read_i32::<NativeEndian>() {
                 x
@@ -95,14 +126,17 @@ pub fn dispatch_request(
             let relay_buf = {
                 let total_len = 8 + 4 + 4 + 4;
                 if total_len > 0xffff {
-                    println!("Oops! total_len={}", total_len);
+                    log::error!("Oops! total_len={}", total_len);
                     return Box::new(futures::future::err(()));
                 }
 
                 let mut dst: Vec<u8> = Vec::new();
                 dst.resize(total_len, 0);
 
-                NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+                NativeEndian::write_u32(
+                    &mut dst[0..],
+                    crate::protocol::link::to_link_id(context.client_id, sender_object_id),
+                );
                 NativeEndian::write_u32(
                     &mut dst[4..],
                     (total_len << 16) as u32 | u32::from(opcode),
@@ -111,9 +145,12 @@ pub fn dispatch_request(
                 #[allow(unused_mut)]
                 let mut encode_offset = 8;
 
-                NativeEndian::write_u32(&mut dst[encode_offset..], arg_id);
+                NativeEndian::write_u32(
+                    &mut dst[encode_offset..],
+                    crate::protocol::link::to_link_id(context.client_id, arg_id),
+                );
                 encode_offset += 4;
-                NativeEndian::write_i32(&mut dst[encode_offset..], arg_fd);
+                NativeEndian::write_i32(&mut dst[encode_offset..], arg_fd.as_raw_fd());
                 encode_offset += 4;
                 NativeEndian::write_i32(&mut dst[encode_offset..], arg_size);
                 encode_offset += 4;
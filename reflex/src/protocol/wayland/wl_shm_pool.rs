@@ -23,8 +23,11 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::fd_queue::OwnedFd;
+use crate::protocol::pixels;
 #[allow(unused_imports)]
 use crate::protocol::session::{Context, NextAction, Session};
+use crate::protocol::wayland::wl_shm::enums::Error;
 #[allow(unused_imports)]
 use futures::future::{err, ok, Future};
 #[allow(unused_imports)]
@@ -45,8 +48,8 @@ pub use lib::*;
 // setup/teardown overhead and is useful when interactively resizing
 // a surface or for many small buffers.
 pub struct WlShmPool {
-    pub fd: i32,   // fd: file descriptor for the pool
-    pub size: i32, // int: pool size, in bytes
+    pub fd: Arc<OwnedFd>, // fd: file descriptor for the pool
+    pub size: i32,        // int: pool size, in bytes
 }
 
 impl WlShmPool {
@@ -72,9 +75,27 @@ impl WlShmPool {
         stride: i32, // int: number of bytes from the beginning of one row to the beginning of the next row
         format: u32, // uint: buffer pixel format
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.resources.insert(
+        if pixels::bytes_per_pixel(format).is_none() {
+            return context.protocol_error(
+                Error::InvalidFormat as u32,
+                format!("wl_buffer@{}: unsupported format {:#x}", id, format),
+            );
+        }
+        let pool_size = context.sender_object.size;
+        if !pixels::fits(pool_size, offset, width, height, stride, format) {
+            return context.protocol_error(
+                Error::InvalidStride as u32,
+                format!(
+                    "wl_buffer@{}: offset={} width={} height={} stride={} does not fit in {} bytes",
+                    id, offset, width, height, stride, pool_size
+                ),
+            );
+        }
+        let fd = context.sender_object.fd.clone();
+        if let Err(err) = context.resources.insert(
             id,
             crate::protocol::wayland::wl_buffer::WlBuffer {
+                fd,
                 offset,
                 width,
                 height,
@@ -82,7 +103,9 @@ impl WlShmPool {
                 format,
             }
             .into(),
-        );
+        ) {
+            return context.invalid_object(format!("wl_buffer@{}: {}", id, err));
+        }
         context.ok()
     }
 
@@ -96,7 +119,7 @@ impl WlShmPool {
     pub fn destroy(
         context: Context<WlShmPool>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_shm_pool::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // change the size of the pool mapping
@@ -106,9 +129,18 @@ impl WlShmPool {
     // created, but using the new size.  This request can only be
     // used to make the pool bigger.
     pub fn resize(
-        context: Context<WlShmPool>,
-        _size: i32, // int: new size of the pool, in bytes
+        mut context: Context<WlShmPool>,
+        size: i32, // int: new size of the pool, in bytes
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_shm_pool::resize is not implemented yet".to_string())
+        let pool_size = context.sender_object.size;
+        if size < pool_size {
+            let message = format!(
+                "wl_shm_pool@{}: cannot shrink from {} to {} bytes",
+                context.sender_object_id, pool_size, size
+            );
+            return context.protocol_error(Error::InvalidStride as u32, message);
+        }
+        context.sender_object.size = size;
+        context.ok()
     }
 }
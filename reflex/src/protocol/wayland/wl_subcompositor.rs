@@ -68,7 +68,7 @@ impl WlSubcompositor {
     pub fn destroy(
         context: Context<WlSubcompositor>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_subcompositor::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // give a surface the role sub-surface
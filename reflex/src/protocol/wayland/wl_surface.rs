@@ -23,10 +23,15 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::flow::Pixels;
+use crate::protocol::link;
+use crate::protocol::pixels::{self, Rect, TileCache};
+use crate::protocol::resource::Resource;
 #[allow(unused_imports)]
 use crate::protocol::session::{Context, NextAction, Session};
+use crate::protocol::wayland::wl_buffer;
 #[allow(unused_imports)]
-use futures::future::{err, ok, Future};
+use futures::future::{err, ok, Either, Future};
 #[allow(unused_imports)]
 use futures::sink::Sink;
 #[allow(unused_imports)]
@@ -37,6 +42,8 @@ pub mod events;
 mod lib;
 pub use lib::*;
 
+use enums::Error;
+
 // an onscreen surface
 //
 // A surface is a rectangular area that is displayed on the screen.
@@ -78,9 +85,37 @@ pub use lib::*;
 // wl_surface again, but it is not allowed to use the wl_surface as
 // a cursor (cursor is a different role than sub-surface, and role
 // switching is not allowed).
-pub struct WlSurface {}
+pub struct WlSurface {
+    // Attached and not committed yet; Some(0) for a null buffer.
+    pub pending_buffer: Option<u32>,
+    // Damage since the last commit, by wl_surface.damage in surface
+    // coordinates and by wl_surface.damage_buffer in buffer coordinates.
+    pub pending_damage: Vec<Rect>,
+    pub pending_buffer_damage: Vec<Rect>,
+    pub pending_scale: Option<i32>,
+    pub pending_transform: Option<u32>,
+    // wl_callback ids of the frame requests since the last commit.
+    pub pending_frame_callbacks: Vec<u32>,
+    pub scale: i32,
+    pub transform: u32,
+    pub tiles: TileCache,
+}
 
 impl WlSurface {
+    pub fn new() -> WlSurface {
+        WlSurface {
+            pending_buffer: None,
+            pending_damage: Vec::new(),
+            pending_buffer_damage: Vec::new(),
+            pending_scale: None,
+            pending_transform: None,
+            pending_frame_callbacks: Vec::new(),
+            scale: 1,
+            transform: 0,
+            tiles: TileCache::default(),
+        }
+    }
+
     // set the surface contents
     //
     // Set a buffer as the content of this surface.
@@ -123,12 +158,14 @@ impl WlSurface {
     // If wl_surface.attach is sent with a NULL wl_buffer, the
     // following wl_surface.commit will remove the surface content.
     pub fn attach(
-        context: Context<WlSurface>,
-        _buffer: u32, // object: buffer of surface contents
-        _x: i32,      // int: surface-local x coordinate
-        _y: i32,      // int: surface-local y coordinate
+        mut context: Context<WlSurface>,
+        buffer: u32, // object: buffer of surface contents
+        x: i32,      // int: surface-local x coordinate
+        y: i32,      // int: surface-local y coordinate
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::attach is not implemented yet".to_string())
+        log::debug!("WlSurface::attach(buffer={}, x={}, y={})", buffer, x, y);
+        context.sender_object.pending_buffer = Some(buffer);
+        context.ok()
     }
 
     // commit pending surface state
@@ -151,10 +188,87 @@ impl WlSurface {
     //
     // Other interfaces may add further double-buffered surface state.
     pub fn commit(
-        context: Context<WlSurface>,
+        mut context: Context<WlSurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlSurface::commit");
-        context.ok()
+        log::debug!("WlSurface::commit");
+        let surface = &mut context.sender_object;
+        if let Some(scale) = surface.pending_scale.take() {
+            surface.scale = scale;
+        }
+        if let Some(transform) = surface.pending_transform.take() {
+            surface.transform = transform;
+        }
+        let surface_damage = std::mem::take(&mut surface.pending_damage);
+        let damage = std::mem::take(&mut surface.pending_buffer_damage);
+        let callbacks = std::mem::take(&mut surface.pending_frame_callbacks);
+        let changed = match surface.pending_buffer.take() {
+            Some(buffer_id) if buffer_id != 0 => {
+                Self::changed_pixels(&mut context, buffer_id, &surface_damage, damage)
+            }
+            _ => None,
+        };
+
+        // The pixels go ahead of the commit, which makes vision show them,
+        // unless they have to wait for the credit of the surface.
+        let surface_id = link::to_link_id(context.client_id, context.sender_object_id);
+        // Vision has a copy now, so the client may reuse the buffer.
+        let release = changed
+            .as_ref()
+            .map(|(buffer_id, _)| wl_buffer::events::Release {
+                sender_object_id: *buffer_id,
+            });
+        let tx = context.tx.clone();
+        Box::new(
+            context
+                .vision
+                .commit_frame(
+                    context.client_id,
+                    surface_id,
+                    changed.map(|(_, pixels)| pixels),
+                    callbacks,
+                )
+                .and_then(move |_| match release {
+                    Some(release) => {
+                        Either::A(tx.send(Box::new(release)).map(|_| ()).map_err(|_| ()))
+                    }
+                    None => Either::B(ok(())),
+                })
+                .and_then(|_| context.ok()),
+        )
+    }
+
+    // The tiles of the attached buffer the damage changed, in buffer
+    // coordinates.
+    fn changed_pixels(
+        context: &mut Context<WlSurface>,
+        buffer_id: u32,
+        surface_damage: &[Rect],
+        mut damage: Vec<Rect>,
+    ) -> Option<(u32, Pixels)> {
+        let buffer = match context.resources.get(buffer_id) {
+            Some(Resource::WlBuffer(buffer)) => buffer.clone(),
+            _ => {
+                log::warn!("[Pixels] wl_buffer@{} is gone", buffer_id);
+                return None;
+            }
+        };
+        let surface = &mut context.sender_object;
+        damage.extend(surface_damage.iter().filter_map(|rect| {
+            pixels::surface_to_buffer(
+                rect,
+                surface.scale,
+                surface.transform,
+                buffer.width,
+                buffer.height,
+            )
+        }));
+        match surface.tiles.changed(&buffer, &damage) {
+            Ok(changed) => Some((buffer_id, (buffer, changed))),
+            Err(err) => {
+                log::warn!("[Pixels] wl_buffer@{}: {}", buffer_id, err);
+                None
+            }
+        }
     }
 
     // mark part of the surface damaged
@@ -181,13 +295,20 @@ impl WlSurface {
     // which uses buffer coordinates instead of surface coordinates,
     // and is probably the preferred and intuitive way of doing this.
     pub fn damage(
-        context: Context<WlSurface>,
-        _x: i32,      // int: surface-local x coordinate
-        _y: i32,      // int: surface-local y coordinate
-        _width: i32,  // int: width of damage rectangle
-        _height: i32, // int: height of damage rectangle
+        mut context: Context<WlSurface>,
+        x: i32,      // int: surface-local x coordinate
+        y: i32,      // int: surface-local y coordinate
+        width: i32,  // int: width of damage rectangle
+        height: i32, // int: height of damage rectangle
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::damage is not implemented yet".to_string())
+        context.sender_object.pending_damage.push(Rect {
+            x,
+            y,
+            width,
+            height,
+        });
+        // Vision learns about damage from the pixels.
+        Box::new(futures::future::ok((context.into(), NextAction::Nop)))
     }
 
     // mark part of the surface damaged using buffer coordinates
@@ -225,13 +346,19 @@ impl WlSurface {
     // two requests separately and only transform from one to the other
     // after receiving the wl_surface.commit.
     pub fn damage_buffer(
-        context: Context<WlSurface>,
-        _x: i32,      // int: buffer-local x coordinate
-        _y: i32,      // int: buffer-local y coordinate
-        _width: i32,  // int: width of damage rectangle
-        _height: i32, // int: height of damage rectangle
+        mut context: Context<WlSurface>,
+        x: i32,      // int: buffer-local x coordinate
+        y: i32,      // int: buffer-local y coordinate
+        width: i32,  // int: width of damage rectangle
+        height: i32, // int: height of damage rectangle
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::damage_buffer is not implemented yet".to_string())
+        context.sender_object.pending_buffer_damage.push(Rect {
+            x,
+            y,
+            width,
+            height,
+        });
+        Box::new(futures::future::ok((context.into(), NextAction::Nop)))
     }
 
     // delete surface
@@ -240,7 +367,9 @@ impl WlSurface {
     pub fn destroy(
         context: Context<WlSurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::destroy is not implemented yet".to_string())
+        let surface_id = link::to_link_id(context.client_id, context.sender_object_id);
+        context.vision.forget_surface(context.client_id, surface_id);
+        context.destroy()
     }
 
     // request a frame throttling hint
@@ -278,10 +407,16 @@ impl WlSurface {
     // The callback_data passed in the callback is the current time, in
     // milliseconds, with an undefined base.
     pub fn frame(
-        context: Context<WlSurface>,
-        _callback: u32, // new_id: callback object for the frame request
+        mut context: Context<WlSurface>,
+        callback: u32, // new_id: callback object for the frame request
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::frame is not implemented yet".to_string())
+        if let Err(err) = context.resources.validate_new_id(callback) {
+            return context.invalid_object(format!("wl_callback@{}: {}", callback, err));
+        }
+        // Answered by reflex once vision has shown the frame, see flow.rs;
+        // vision never sees the callback.
+        context.sender_object.pending_frame_callbacks.push(callback);
+        Box::new(futures::future::ok((context.into(), NextAction::Nop)))
     }
 
     // sets the buffer scaling factor
@@ -310,10 +445,15 @@ impl WlSurface {
     // If scale is not positive the invalid_scale protocol error is
     // raised.
     pub fn set_buffer_scale(
-        context: Context<WlSurface>,
-        _scale: i32, // int: positive scale for interpreting buffer contents
+        mut context: Context<WlSurface>,
+        scale: i32, // int: positive scale for interpreting buffer contents
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::set_buffer_scale is not implemented yet".to_string())
+        if scale <= 0 {
+            let message = format!("wl_surface@{}: scale {}", context.sender_object_id, scale);
+            return context.protocol_error(Error::InvalidScale as u32, message);
+        }
+        context.sender_object.pending_scale = Some(scale);
+        context.ok()
     }
 
     // sets the buffer transformation
@@ -348,11 +488,19 @@ impl WlSurface {
     // wl_output.transform enum the invalid_transform protocol error
     // is raised.
     pub fn set_buffer_transform(
-        context: Context<WlSurface>,
-        _transform: i32, // int: transform for interpreting buffer contents
+        mut context: Context<WlSurface>,
+        transform: i32, // int: transform for interpreting buffer contents
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context
-            .invalid_method("wl_surface::set_buffer_transform is not implemented yet".to_string())
+        // wl_output.transform, normal to flipped_270
+        if !(0..=7).contains(&transform) {
+            let message = format!(
+                "wl_surface@{}: transform {}",
+                context.sender_object_id, transform
+            );
+            return context.protocol_error(Error::InvalidTransform as u32, message);
+        }
+        context.sender_object.pending_transform = Some(transform as u32);
+        context.ok()
     }
 
     // set input region
@@ -61,6 +61,10 @@ impl super::super::super::event::Event for Enter {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // surface leaves an output
@@ -97,4 +101,8 @@ impl super::super::super::event::Event for Leave {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -53,6 +53,6 @@ impl WlTouch {
     pub fn release(
         context: Context<WlTouch>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_touch::release is not implemented yet".to_string())
+        context.destroy()
     }
 }
@@ -59,6 +59,10 @@ impl super::super::super::event::Event for Cancel {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // touch down event and beginning of a touch sequence
@@ -111,6 +115,10 @@ impl super::super::super::event::Event for Down {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // end of touch frame event
@@ -149,6 +157,10 @@ impl super::super::super::event::Event for Frame {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // update of touch point coordinates
@@ -192,6 +204,10 @@ impl super::super::super::event::Event for Motion {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // update orientation of touch point
@@ -251,6 +267,10 @@ impl super::super::super::event::Event for Orientation {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // update shape of touch point
@@ -315,6 +335,10 @@ impl super::super::super::event::Event for Shape {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // end of a touch event sequence
@@ -357,4 +381,8 @@ impl super::super::super::event::Event for Up {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -83,7 +83,7 @@ impl XdgPopup {
     pub fn destroy(
         context: Context<XdgPopup>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_popup::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // make the popup take an explicit grab
@@ -73,6 +73,10 @@ impl super::super::super::event::Event for Configure {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // popup interaction is done
@@ -106,4 +110,8 @@ impl super::super::super::event::Event for PopupDone {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
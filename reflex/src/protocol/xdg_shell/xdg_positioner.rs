@@ -67,7 +67,7 @@ impl XdgPositioner {
     pub fn destroy(
         context: Context<XdgPositioner>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_positioner::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // set anchor rectangle anchor
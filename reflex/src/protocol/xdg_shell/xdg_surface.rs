@@ -119,7 +119,7 @@ impl XdgSurface {
     pub fn destroy(
         context: Context<XdgSurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_surface::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // assign the xdg_popup surface role
@@ -152,15 +152,18 @@ impl XdgSurface {
         mut context: Context<XdgSurface>,
         id: u32, // new_id:
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("XdgSurface::get_toplevel(id={})", id);
-        context.resources.insert(
+        log::debug!("XdgSurface::get_toplevel(id={})", id);
+        if let Err(err) = context.resources.insert(
             id,
             crate::protocol::xdg_shell::xdg_toplevel::XdgToplevel {
                 xdg_surface_id: context.sender_object_id,
             }
             .into(),
-        );
-        context.ok()
+        ) {
+            return context.invalid_object(format!("xdg_toplevel@{}: {}", id, err));
+        }
+        // Vision creates the host window before the client goes on.
+        Box::new(futures::future::ok((context.into(), NextAction::RelayWait)))
     }
 
     // set the new window geometry
@@ -65,7 +65,7 @@ impl XdgToplevel {
     pub fn destroy(
         context: Context<XdgToplevel>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_toplevel::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // start an interactive move
@@ -159,9 +159,10 @@ impl XdgToplevel {
     // [0] http://standards.freedesktop.org/desktop-entry-spec/
     pub fn set_app_id(
         context: Context<XdgToplevel>,
-        _app_id: String, // string:
+        app_id: String, // string:
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_toplevel::set_app_id is not implemented yet".to_string())
+        log::debug!("XdgToplevel::set_app_id({:?})", app_id);
+        context.ok()
     }
 
     // set the window as fullscreen on an output
@@ -363,9 +364,10 @@ impl XdgToplevel {
     // The string must be encoded in UTF-8.
     pub fn set_title(
         context: Context<XdgToplevel>,
-        _title: String, // string:
+        title: String, // string:
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_toplevel::set_title is not implemented yet".to_string())
+        log::debug!("XdgToplevel::set_title({:?})", title);
+        context.ok()
     }
 
     // show the window menu
@@ -62,6 +62,10 @@ impl super::super::super::event::Event for Close {
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
 
 // suggest a surface change
@@ -95,7 +99,7 @@ pub struct Configure {
 
 impl super::super::super::event::Event for Configure {
     fn encode(&self, dst: &mut bytes::BytesMut) -> Result<(), std::io::Error> {
-        let total_len = 8 + 4 + 4 + { 4 + (self.states.len() + 1 + 3) / 4 * 4 };
+        let total_len = 8 + 4 + 4 + { 4 + (self.states.len() + 3) / 4 * 4 };
         if total_len > 0xffff {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Oops!"));
         }
@@ -126,8 +130,12 @@ impl super::super::super::event::Event for Configure {
                 .copy_from_slice(&aligned_states[..]);
         }
 
-        encode_offset += { 4 + (self.states.len() + 1 + 3) / 4 * 4 };
+        encode_offset += { 4 + (self.states.len() + 3) / 4 * 4 };
         let _ = encode_offset;
         Ok(())
     }
+
+    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {
+        Some(&super::INTERFACE)
+    }
 }
@@ -70,7 +70,7 @@ impl XdgWmBase {
     pub fn destroy(
         context: Context<Arc<RwLock<XdgWmBase>>>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_wm_base::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // create a shell surface from a surface
@@ -91,14 +91,16 @@ impl XdgWmBase {
         id: u32,      // new_id:
         surface: u32, // object:
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("XdgWmBase::get_xdg_surface(id={}, surface={})", id, surface);
-        context.resources.insert(
+        log::debug!("XdgWmBase::get_xdg_surface(id={}, surface={})", id, surface);
+        if let Err(err) = context.resources.insert(
             id,
             crate::protocol::xdg_shell::xdg_surface::XdgSurface {
                 wl_surface_id: surface,
             }
             .into(),
-        );
+        ) {
+            return context.invalid_object(format!("xdg_surface@{}: {}", id, err));
+        }
         context.ok()
     }
 
@@ -7,14 +7,30 @@ def generate_reflex(base_dir, protocols)
       f.puts("pub mod #{protocol.name};")
     end
     f.puts <<~MOD
+      pub mod capture;
       pub mod connection_stream;
+      pub mod control;
       pub mod event;
       pub mod event_sink;
       pub mod fd_drop;
+      pub mod fd_queue;
+      pub mod flow;
+      pub mod link;
+      pub mod object_map;
+      pub mod pixels;
+      pub mod proxy;
+      pub mod raw_event;
+      pub mod replay;
       pub mod request;
       pub mod request_stream;
       pub mod resource;
+      #[allow(dead_code)]
+      #[path = "../../shared/rle.rs"]
+      pub mod rle;
       pub mod session;
+      pub mod trace;
+      pub mod vision_link;
+      pub mod vision_stream;
     MOD
   end
 
@@ -31,8 +47,44 @@ def generate_reflex(base_dir, protocols)
     end
     f.puts('}')
     f.puts('')
+    f.puts(<<~EVENT_SINCE)
+      impl Resource {
+          pub fn event_since(&self) -> fn(u16) -> u32 {
+              match self {
+    EVENT_SINCE
+    protocols.each do |protocol|
+      protocol.interfaces.each do |interface|
+        f.puts("            Resource::#{camel_case(interface.name)}(_) => super::#{protocol.name}::#{interface.name}::event_since,")
+      end
+    end
+    f.puts(<<~EVENT_SINCE)
+              }
+          }
+
+          pub fn interface(&self) -> &'static crate::protocol::trace::Interface {
+              match self {
+    EVENT_SINCE
+    protocols.each do |protocol|
+      protocol.interfaces.each do |interface|
+        f.puts("            Resource::#{camel_case(interface.name)}(_) => &super::#{protocol.name}::#{interface.name}::INTERFACE,")
+      end
+    end
+    f.puts(<<~INTERFACE)
+              }
+          }
+      }
+
+    INTERFACE
+    interfaces = protocols.flat_map { |protocol| protocol.interfaces.map { |interface| [protocol, interface] } }
+    f.puts('// Every interface, for looking them up by name.')
+    f.puts("pub static INTERFACES: [&crate::protocol::trace::Interface; #{interfaces.size}] = [")
+    interfaces.each do |protocol, interface|
+      f.puts("    &super::#{protocol.name}::#{interface.name}::INTERFACE,")
+    end
+    f.puts('];')
+    f.puts('')
     f.puts(<<~DISPATCH_REQUEST)
-      pub fn dispatch_request(resource: Resource, session: crate::protocol::session::Session, sender_object_id: u32, opcode: u16, args: Vec<u8>) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
+      pub fn dispatch_request(resource: Resource, session: crate::protocol::session::Session, sender_object_id: u32, opcode: u16, args: bytes::Bytes) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
           match resource {
     DISPATCH_REQUEST
     protocols.each do |protocol|
@@ -94,12 +146,20 @@ def generate_reflex(base_dir, protocols)
           f.puts("pub struct #{camel_case(event.name)} {")
           f.puts('    pub sender_object_id: u32,')
           event.args.each do |arg|
-            f.puts("    pub #{arg.name}: #{arg.rust_type}, // #{arg.type}: #{arg.summary}")
+            f.puts("    pub #{arg.name}: #{arg.event_rust_type}, // #{arg.type}: #{arg.summary}")
           end
           f.puts('}')
           f.puts('')
           f.puts("impl super::super::super::event::Event for #{camel_case(event.name)} {")
           f.puts(event.encode)
+          f.puts('')
+          if event.fds
+            f.puts(event.fds)
+            f.puts('')
+          end
+          f.puts("    fn interface(&self) -> Option<&'static crate::protocol::trace::Interface> {")
+          f.puts('        Some(&super::INTERFACE)')
+          f.puts('    }')
           f.puts('}')
         end
       end
@@ -129,10 +189,16 @@ def generate_reflex(base_dir, protocols)
           #[allow(dead_code)]
           pub const VERSION: u32 = #{interface.version};
 
+          // The interface version in which each event was introduced.
+          #[allow(dead_code)]
+          #{interface.event_since}
+
+          #{interface.trace}
+
           #[allow(unused_variables)]
           #[allow(dead_code)]
         CODE
-        f.puts("pub fn dispatch_request(#{interface.dispatch_context_mut}context: crate::protocol::session::Context<#{interface.receiver_type}>, opcode: u16, args: Vec<u8>) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {")
+        f.puts("pub fn dispatch_request(context: crate::protocol::session::Context<#{interface.receiver_type}>, opcode: u16, args: bytes::Bytes) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {")
         f.puts(interface.decode)
         f.puts('}')
         f.puts(<<~INTO)
@@ -176,7 +242,7 @@ def generate_reflex(base_dir, protocols)
             f.puts("    pub fn #{request.rust_name}(")
             f.puts("        context: Context<#{interface.short_receiver_type}>,")
             request.args.each do |arg|
-              f.print("        _#{arg.name}: #{arg.rust_type}, // #{arg.type}: #{arg.summary}\n")
+              f.print("        _#{arg.name}: #{arg.request_rust_type}, // #{arg.type}: #{arg.summary}\n")
             end
             f.puts(<<FUNC_BODY)
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
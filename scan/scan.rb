@@ -116,17 +116,51 @@ class Interface
     end
   end
 
-  def dispatch_context_mut
-    fd_arg = (@requests || []).find do |request|
-      request.args.find do |arg|
-        arg.is_a?(FdArg)
-      end
+  def event_since
+    versioned_events = (@events || []).sort_by(&:index).select do |event|
+      event.since > 1
     end
-    if fd_arg
-      'mut '
-    else
-      ''
+    return "pub fn event_since(_opcode: u16) -> u32 {\n    1\n}\n" if versioned_events.empty?
+
+    result = "pub fn event_since(opcode: u16) -> u32 {\n"
+    result += "    match opcode {\n"
+    versioned_events.each do |event|
+      result += "        #{event.index} => #{event.since},\n"
+    end
+    result += "        _ => 1,\n"
+    result += "    }\n"
+    result += "}\n"
+    result
+  end
+
+  def trace
+    result = "// Requests and events as WAYLAND_DEBUG prints them, see protocol::trace.\n"
+    [['format_request', @requests], ['format_event', @events]].each do |fn, messages|
+      result += "#[allow(dead_code)]\n"
+      unless messages
+        result += "pub fn #{fn}(_opcode: u16, _args: &mut crate::protocol::trace::Args) -> Option<String> {\n    None\n}\n\n"
+        next
+      end
+
+      args = messages.any? { |message| !message.args.empty? } ? 'args' : '_args'
+      result += "pub fn #{fn}(opcode: u16, #{args}: &mut crate::protocol::trace::Args) -> Option<String> {\n"
+      result += "    match opcode {\n"
+      messages.sort_by(&:index).each do |message|
+        result += "        #{message.trace}\n"
+      end
+      result += "        _ => None,\n"
+      result += "    }\n"
+      result += "}\n\n"
     end
+    result += <<~INTERFACE
+      #[allow(dead_code)]
+      pub static INTERFACE: crate::protocol::trace::Interface = crate::protocol::trace::Interface {
+          name: "#{@name}",
+          format_request,
+          format_event,
+      };
+    INTERFACE
+    result
   end
 
   def decode
@@ -238,6 +272,12 @@ class Request
 
         @description = Description.new(child)
       when 'arg'
+        if child.attributes['type'] == 'new_id' && !child.attributes['interface']
+          # A new_id without a fixed interface (wl_registry.bind) is sent as
+          # interface name, version and id on the wire.
+          @args << StringArg.new('interface', 'interface of the bound object', 'string', interface_name)
+          @args << UintArg.new('version', 'version of the bound object', 'uint', interface_name)
+        end
         arg = Arg.create(child, interface_name)
         @args << arg
       else
@@ -246,6 +286,12 @@ class Request
     end
   end
 
+  def trace
+    return "#{@index} => Some(\"#{@name}()\".to_owned())," if @args.empty?
+
+    "#{@index} => Some(format!(\"#{@name}(#{(['{}'] * @args.size).join(', ')})\", #{@args.map(&:trace).join(', ')})),"
+  end
+
   def encode_vision
     result = <<FN_ENCODE
     {
@@ -257,14 +303,14 @@ FN_ENCODE
     result += ";\n"
     result += <<HEADER
         if total_len > 0xffff {
-            println!("Oops! total_len={}", total_len);
+            log::error!("Oops! total_len={}", total_len);
             return Box::new(futures::future::err(()));
         }
 
         let mut dst: Vec<u8> = Vec::new();
         dst.resize(total_len, 0);
 
-        NativeEndian::write_u32(&mut dst[0..], sender_object_id);
+        NativeEndian::write_u32(&mut dst[0..], crate::protocol::link::to_link_id(context.client_id, sender_object_id));
         NativeEndian::write_u32(&mut dst[4..], (total_len << 16) as u32 | u32::from(opcode));
 
         #[allow(unused_mut)] let mut encode_offset = 8;
@@ -332,12 +378,24 @@ end
 
 class Arg
   attr_reader :name, :summary, :serialize_len, :type, :rust_type, :dynamic_len, :interface_name
+  # The interface of an object or new_id argument, if the protocol fixes it.
+  attr_accessor :object_interface
+
+  # The type a request handler takes the argument as.
+  def request_rust_type
+    rust_type
+  end
+
+  # The type of the argument in the events reflex sends.
+  def event_rust_type
+    rust_type
+  end
 
   def self.create(elem, interface_name)
     name = elem.attributes['name']
     summary = elem.attributes['summary']
     type = elem.attributes['type']
-    case type
+    arg = case type
     when 'uint'
       UintArg.new(name, summary, type, interface_name)
     when 'int'
@@ -357,6 +415,15 @@ class Arg
     else
       raise "unhandled type: #{@type}"
     end
+    arg.object_interface = elem.attributes['interface']&.strip
+    arg
+  end
+
+  # How protocol::trace shows the argument.
+  def trace
+    return "args.#{type}()" unless %w[object new_id].include?(type)
+
+    "args.#{type}(#{object_interface ? "Some(\"#{object_interface}\")" : 'None'})"
   end
 
   def deserialize_return_error
@@ -447,6 +514,11 @@ class ObjectArg < Arg
     "NativeEndian::write_u32(&mut dst[encode_offset..], #{prefix}#{name});"
   end
 
+  # Object ids are relayed in the link-wide id space, see link::to_link_id.
+  def serialize_vision
+    "NativeEndian::write_u32(&mut dst[encode_offset..], crate::protocol::link::to_link_id(context.client_id, arg_#{name}));"
+  end
+
   def deserialize
     <<~DESERIAliZE
                   let arg_#{name} = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
@@ -524,30 +596,33 @@ class FdArg < Arg
     @interface_name = interface_name
   end
 
+  def request_rust_type
+    'crate::protocol::fd_queue::OwnedFd'
+  end
+
+  # Owned by the event, which is sent with a copy of it.
+  def event_rust_type
+    'std::fs::File'
+  end
+
   def serialize_vision_len
     '4'
   end
 
   def serialize_vision
-    "NativeEndian::write_i32(&mut dst[encode_offset..], arg_#{name});"
+    "NativeEndian::write_i32(&mut dst[encode_offset..], arg_#{name}.as_raw_fd());"
   end
 
   def serialize(_prefix = 'self.')
-    # "NativeEndian::write_i32(&mut dst[encode_offset..], self.#{name});"
-    '// unimplemented!();'
-    'println!("UNIMPLEMENTED!!!!!");'
+    '// The fd goes as ancillary data, not in the bytes.'
   end
 
   def deserialize
     <<-DESERIAliZE
-      if context.fds.is_empty() {
+      let arg_#{name} = if let Some(x) = context.fds.pop() {
+          x
+      } else {
           #{deserialize_return_error}
-      }
-      let arg_#{name} = {
-          let rest = context.fds.split_off(1);
-          let first = context.fds.pop().expect("fds");
-          context.fds = rest;
-          first
       };
     DESERIAliZE
   end
@@ -578,6 +653,11 @@ class NewIdArg < Arg
     "NativeEndian::write_u32(&mut dst[encode_offset..], #{prefix}#{name});"
   end
 
+  # See ObjectArg#serialize_vision.
+  def serialize_vision
+    "NativeEndian::write_u32(&mut dst[encode_offset..], crate::protocol::link::to_link_id(context.client_id, arg_#{name}));"
+  end
+
   def deserialize
     <<-DESERIAliZE
       let arg_#{name} = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
@@ -621,8 +701,8 @@ class ArrayArg < Arg
   def initialize(name, summary, type, interface_name)
     @name = name
     @summary = summary
-    @serialize_len =        "{4 + (self.#{name}.len() + 1 + 3) / 4 * 4}"
-    @serialize_vision_len = "{4 + ( arg_#{name}.len() + 1 + 3) / 4 * 4}"
+    @serialize_len =        "{4 + (self.#{name}.len() + 3) / 4 * 4}"
+    @serialize_vision_len = "{4 + ( arg_#{name}.len() + 3) / 4 * 4}"
     @dynamic_len = true
     @type = type
     @rust_type = 'Vec<u8>'
@@ -665,11 +745,12 @@ SERIALIZE
 end
 
 class Event
-  attr_reader :name, :description, :args, :index
+  attr_reader :name, :description, :args, :index, :since
 
   def initialize(elem, index)
     @name = elem.attributes['name'].strip
     @index = index
+    @since = (elem.attributes['since'] || '1').strip.to_i
     @args = []
     elem.select { |elem| elem.node_type == :element }.each do |child|
       case child.name
@@ -686,6 +767,12 @@ class Event
     end
   end
 
+  def trace
+    return "#{@index} => Some(\"#{@name}()\".to_owned())," if @args.empty?
+
+    "#{@index} => Some(format!(\"#{@name}(#{(['{}'] * @args.size).join(', ')})\", #{@args.map(&:trace).join(', ')})),"
+  end
+
   def encode
     result = <<FN_ENCODE
     fn encode(&self, dst: &mut bytes::BytesMut) -> Result<(), std::io::Error> {
@@ -722,6 +809,19 @@ HEADER
     FOOTER
     result
   end
+
+  # Event::fds, for events that carry fds.
+  def fds
+    fd_args = @args.select { |arg| arg.type == 'fd' }
+    return nil if fd_args.empty?
+
+    <<~FN_FDS
+      fn fds(&self) -> Vec<std::os::unix::io::RawFd> {
+          use std::os::unix::io::AsRawFd;
+          vec![#{fd_args.map { |arg| "self.#{arg.name}.as_raw_fd()" }.join(', ')}]
+      }
+    FN_FDS
+  end
 end
 
 class Description
@@ -8,9 +8,15 @@ def generate_vision(base_dir, protocols)
     end
     f.puts <<~MOD
       pub mod codec;
+      pub mod control;
       pub mod event;
+      pub mod link;
+      pub mod pixels;
       pub mod request;
       pub mod resource;
+      #[allow(dead_code)]
+      #[path = "../../shared/rle.rs"]
+      pub mod rle;
       pub mod session;
     MOD
   end
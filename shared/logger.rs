@@ -0,0 +1,328 @@
+use futures::{Future, Poll, Stream};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::cell::Cell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::timer::Interval;
+
+// A log file is rotated once it reaches this size, and this many rotated
+// files are kept as <path>.1 (the newest) to <path>.N.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+const KEPT_FILES: usize = 4;
+// How often the level file is looked at for changes.
+const LEVEL_FILE_INTERVAL: Duration = Duration::from_secs(1);
+
+thread_local! {
+    // The client whose work the thread is doing, put on every line.
+    static CLIENT: Cell<Option<u32>> = Cell::default();
+}
+
+// Which records are logged: a default level and levels for targets, written
+// as "info,protocol::request_stream=debug". A target is a module path of this
+// crate, or of a dependency when it starts with the crate name, and covers
+// its submodules; the longest match wins. Dependencies log no more than
+// warnings unless they are named. "protocol::trace@3=trace" only applies to
+// the work of client 3, and "@3=debug" to all of it.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    default: LevelFilter,
+    targets: Vec<Target>,
+}
+
+#[derive(Clone, Debug)]
+struct Target {
+    path: String,
+    client_id: Option<u32>,
+    level: LevelFilter,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Filter, String> {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        };
+        for part in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let mut pair = part.splitn(2, '=');
+            let first = pair.next().unwrap_or("");
+            let level = |value: &str| {
+                value
+                    .parse::<LevelFilter>()
+                    .map_err(|_| format!("invalid log level: {}", value))
+            };
+            let value = match pair.next() {
+                Some(value) => value,
+                None => {
+                    filter.default = level(first)?;
+                    continue;
+                }
+            };
+            let (path, client_id) = match first.find('@') {
+                Some(index) => {
+                    let client_id = first[index + 1..]
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid client id: {}", &first[index + 1..]))?;
+                    (&first[..index], Some(client_id))
+                }
+                None => (first, None),
+            };
+            filter.targets.push(Target {
+                path: path.to_owned(),
+                client_id,
+                level: level(value)?,
+            });
+        }
+        // Longest first, and for one client before for all of them.
+        filter.targets.sort_by_key(|target| {
+            (
+                std::cmp::Reverse(target.path.len()),
+                target.client_id.is_none(),
+            )
+        });
+        Ok(filter)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        let own = own_path(target);
+        let path = own.unwrap_or(target);
+        let client_id = CLIENT.with(Cell::get);
+        for target in &self.targets {
+            if target.client_id.is_some() && target.client_id != client_id {
+                continue;
+            }
+            let prefix = target.path.as_str();
+            if prefix.is_empty()
+                || path == prefix
+                || (path.starts_with(prefix) && path[prefix.len()..].starts_with("::"))
+            {
+                return target.level;
+            }
+        }
+        match own {
+            Some(_) => self.default,
+            None => std::cmp::min(self.default, LevelFilter::Warn),
+        }
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|target| target.level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+enum Output {
+    Stdout,
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+    },
+}
+
+impl Output {
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().write_all(line.as_bytes()),
+            Output::File { path, file, size } => {
+                if *size + line.len() as u64 > MAX_FILE_SIZE {
+                    *file = rotate(path)?;
+                    *size = 0;
+                }
+                file.write_all(line.as_bytes())?;
+                *size += line.len() as u64;
+                Ok(())
+            }
+        }
+    }
+}
+
+struct Logger {
+    filter: Arc<RwLock<Filter>>,
+    output: Mutex<Output>,
+    pid: u32,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let client = match CLIENT.with(Cell::get) {
+            Some(client_id) => client_id.to_string(),
+            None => "-".to_owned(),
+        };
+        let line = format!(
+            "{}.{:03} pid={} client={} {:<5} {}: {}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            self.pid,
+            client,
+            record.level(),
+            short_target(record.target()),
+            record.args()
+        );
+        // Nowhere is left to report a failed write to.
+        let _ = self.output.lock().unwrap().write(&line);
+    }
+
+    fn flush(&self) {
+        if let Output::File { file, .. } = &mut *self.output.lock().unwrap() {
+            let _ = file.flush();
+        }
+    }
+}
+
+// Changes what is logged while running.
+#[derive(Clone)]
+pub struct FilterHandle {
+    filter: Arc<RwLock<Filter>>,
+}
+
+impl FilterHandle {
+    pub fn set(&self, filter: Filter) {
+        log::set_max_level(filter.max());
+        *self.filter.write().unwrap() = filter;
+    }
+}
+
+// Log to stdout, or to a file rotated by size.
+pub fn init(filter: Filter, path: Option<&Path>) -> io::Result<FilterHandle> {
+    let output = match path {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let size = file.metadata()?.len();
+            Output::File {
+                path: path.to_owned(),
+                file,
+                size,
+            }
+        }
+        None => Output::Stdout,
+    };
+    let max = filter.max();
+    let handle = FilterHandle {
+        filter: Arc::new(RwLock::new(filter)),
+    };
+    let logger: &'static Logger = Box::leak(Box::new(Logger {
+        filter: handle.filter.clone(),
+        output: Mutex::new(output),
+        pid: std::process::id(),
+    }));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max);
+    }
+    Ok(handle)
+}
+
+// Read the filter from a file whenever the file changes, so that the level
+// can be raised and lowered without a restart.
+pub fn watch_level_file(path: PathBuf, handle: FilterHandle) -> impl Future<Item = (), Error = ()> {
+    let mut modified = None;
+    Interval::new(Instant::now(), LEVEL_FILE_INTERVAL)
+        .map_err(|err| log::error!("level file timer err: {:?}", err))
+        .for_each(move |_| {
+            let now_modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            if now_modified.is_none() || now_modified == modified {
+                return Ok(());
+            }
+            modified = now_modified;
+            match fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|spec| Filter::parse(&spec))
+            {
+                Ok(filter) => {
+                    log::info!("log filter {:?} from {}", filter, path.display());
+                    handle.set(filter);
+                }
+                Err(err) => log::warn!("level file {}: {}", path.display(), err),
+            }
+            Ok(())
+        })
+}
+
+// Runs a future with the lines it logs tagged with a client.
+pub struct WithClient<F> {
+    client_id: u32,
+    inner: F,
+}
+
+impl<F: Future> Future for WithClient<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let _client = enter_client(self.client_id);
+        self.inner.poll()
+    }
+}
+
+pub fn with_client<F: Future>(client_id: u32, inner: F) -> WithClient<F> {
+    WithClient { client_id, inner }
+}
+
+// Tags the lines logged on this thread with a client until dropped.
+pub struct ClientGuard {
+    previous: Option<u32>,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        CLIENT.with(|client| client.set(self.previous));
+    }
+}
+
+pub fn enter_client(client_id: u32) -> ClientGuard {
+    ClientGuard {
+        previous: CLIENT.with(|client| client.replace(Some(client_id))),
+    }
+}
+
+// The module path within this crate, empty for the crate root, or None for
+// a dependency.
+fn own_path(target: &str) -> Option<&str> {
+    let name = module_path!().split("::").next().unwrap_or("");
+    if target == name {
+        Some("")
+    } else if target.starts_with(name) && target[name.len()..].starts_with("::") {
+        Some(&target[name.len() + 2..])
+    } else {
+        None
+    }
+}
+
+// What a line shows as its target.
+fn short_target(target: &str) -> &str {
+    match own_path(target) {
+        Some(path) if !path.is_empty() => path,
+        _ => target,
+    }
+}
+
+fn rotate(path: &Path) -> io::Result<File> {
+    let rotated = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    for n in (1..KEPT_FILES).rev() {
+        let _ = fs::rename(rotated(n), rotated(n + 1));
+    }
+    fs::rename(path, rotated(1))?;
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,169 @@
+use std::io;
+
+// Lossless run-length coding of 4-byte pixels, used for the rects of PIXELS
+// frames once both ends have enabled FEATURE_RLE. The pixels are coded as
+// packets, each led by a control byte c:
+//
+//   c < 0x80   c + 1 literal pixels follow
+//   c >= 0x80  the one pixel that follows repeats c - 0x80 + 2 times
+//
+// Flat areas, which make up most of a terminal or an editor, shrink to 5
+// bytes per 129 pixels, while noise grows by 1 byte per 128 pixels.
+const PIXEL_SIZE: usize = 4;
+const MAX_LITERAL: usize = 128;
+const MIN_RUN: usize = 2;
+const MAX_RUN: usize = 129;
+
+pub fn encode(pixels: &[u8]) -> Vec<u8> {
+    let count = pixels.len() / PIXEL_SIZE;
+    let pixel = |i: usize| &pixels[i * PIXEL_SIZE..(i + 1) * PIXEL_SIZE];
+    let mut dst = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i < count {
+        let mut run = 1;
+        while i + run < count && run < MAX_RUN && pixel(i + run) == pixel(i) {
+            run += 1;
+        }
+        if run >= MIN_RUN {
+            push_literal(&mut dst, pixels, literal_start, i);
+            dst.push((0x80 + run - MIN_RUN) as u8);
+            dst.extend_from_slice(pixel(i));
+            i += run;
+            literal_start = i;
+        } else {
+            i += 1;
+            if i - literal_start == MAX_LITERAL {
+                push_literal(&mut dst, pixels, literal_start, i);
+                literal_start = i;
+            }
+        }
+    }
+    push_literal(&mut dst, pixels, literal_start, count);
+    dst
+}
+
+// Pixels start..end, at most MAX_LITERAL of them.
+fn push_literal(dst: &mut Vec<u8>, pixels: &[u8], start: usize, end: usize) {
+    if start < end {
+        dst.push((end - start - 1) as u8);
+        dst.extend_from_slice(&pixels[start * PIXEL_SIZE..end * PIXEL_SIZE]);
+    }
+}
+
+pub fn decode(data: &[u8], count: usize) -> io::Result<Vec<u8>> {
+    let mut dst = Vec::with_capacity(count * PIXEL_SIZE);
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i] as usize;
+        i += 1;
+        let (pixels, repeat) = if control < 0x80 {
+            (control + 1, 1)
+        } else {
+            (1, control - 0x80 + MIN_RUN)
+        };
+        let end = i + pixels * PIXEL_SIZE;
+        if end > data.len() || dst.len() + pixels * repeat * PIXEL_SIZE > count * PIXEL_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "run-length data overruns the rect",
+            ));
+        }
+        for _ in 0..repeat {
+            dst.extend_from_slice(&data[i..end]);
+        }
+        i = end;
+    }
+    if dst.len() != count * PIXEL_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "run-length data ends before the rect",
+        ));
+    }
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(pixels: &[u8]) -> Vec<u8> {
+        let coded = encode(pixels);
+        assert_eq!(decode(&coded, pixels.len() / PIXEL_SIZE).unwrap(), pixels);
+        coded
+    }
+
+    // count pixels, pixel i being [i, i, i, i] so that no two follow that
+    // are the same.
+    fn noise(count: usize) -> Vec<u8> {
+        (0..count).flat_map(|i| vec![i as u8; PIXEL_SIZE]).collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(round_trip(&[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn single_pixel() {
+        assert_eq!(round_trip(&[1, 2, 3, 4]), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn long_run() {
+        let pixels = [7; 300 * PIXEL_SIZE];
+        // 129 + 129 + 42 pixels.
+        assert_eq!(
+            round_trip(&pixels),
+            vec![0xff, 7, 7, 7, 7, 0xff, 7, 7, 7, 7, 0xa8, 7, 7, 7, 7]
+        );
+    }
+
+    #[test]
+    fn runs_and_literals() {
+        let mut pixels = noise(3);
+        pixels.extend_from_slice(&[9; 5 * PIXEL_SIZE]);
+        pixels.extend(noise(200));
+        pixels.extend_from_slice(&[1; 2 * PIXEL_SIZE]);
+        pixels.extend(noise(1));
+        let coded = round_trip(&pixels);
+        // A literal of 3, a run of 5, literals of 128 and 72, a run of 2 and
+        // a literal of 1.
+        let controls = [0x02, 0x83, 0x7f, 0x47, 0x80, 0x00];
+        let mut i = 0;
+        for control in controls.iter() {
+            assert_eq!(coded[i], *control);
+            i += 1 + if *control < 0x80 {
+                (*control as usize + 1) * PIXEL_SIZE
+            } else {
+                PIXEL_SIZE
+            };
+        }
+        assert_eq!(i, coded.len());
+    }
+
+    #[test]
+    fn flat_tile() {
+        let pixels = [0x20; 64 * 64 * PIXEL_SIZE];
+        let coded = round_trip(&pixels);
+        assert!(coded.len() * 100 < pixels.len(), "{} bytes", coded.len());
+    }
+
+    #[test]
+    fn truncated() {
+        let pixels = noise(10);
+        let coded = encode(&pixels);
+        assert!(decode(&coded[..coded.len() - 1], 10).is_err());
+        assert!(decode(&coded, 11).is_err());
+        assert!(decode(&[0x85], 7).is_err());
+    }
+
+    #[test]
+    fn overlong() {
+        let pixels = noise(10);
+        let mut coded = encode(&pixels);
+        assert!(decode(&coded, 9).is_err());
+        coded.extend_from_slice(&[0x80, 1, 2, 3, 4]);
+        assert!(decode(&coded, 11).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::{self, Read, Write};
+
+// The pre-shared token reflex presents to vision on a TCP link, where the
+// two may be on different machines. The link is not encrypted, so the token
+// goes over the wire as it is:
+//
+//   "UDTK", token size: u32, token     reflex to vision
+//   status: u32                        vision to reflex
+//
+// all little endian.
+pub const TOKEN_MAGIC: [u8; 4] = *b"UDTK";
+pub const TOKEN_ACCEPTED: u32 = 0;
+pub const TOKEN_REJECTED: u32 = 1;
+pub const MAX_TOKEN_LEN: usize = 1024;
+
+// Read the magic and the token sent by reflex, and answer whether it matches.
+// Blocks until reflex has sent them; the caller sets a timeout.
+pub fn verify_token<S: Read + Write>(stream: &mut S, token: &[u8]) -> io::Result<()> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let len = LittleEndian::read_u32(&header[4..]) as usize;
+    let accepted = if header[..4] == TOKEN_MAGIC && len <= MAX_TOKEN_LEN {
+        let mut received = vec![0; len];
+        stream.read_exact(&mut received)?;
+        constant_time_eq(&received, token)
+    } else {
+        false
+    };
+
+    let mut status = [0; 4];
+    LittleEndian::write_u32(
+        &mut status,
+        if accepted {
+            TOKEN_ACCEPTED
+        } else {
+            TOKEN_REJECTED
+        },
+    );
+    stream.write_all(&status)?;
+    if accepted {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "invalid token",
+        ))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// The token is kept in a file rather than on the command line, where other
+// users could read it.
+pub fn read_token(path: &str) -> Result<Vec<u8>, String> {
+    let mut token =
+        std::fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    while token.last() == Some(&b'\n') || token.last() == Some(&b'\r') {
+        token.pop();
+    }
+    if token.is_empty() || token.len() > MAX_TOKEN_LEN {
+        return Err(format!(
+            "the token in {} must be 1 to {} bytes",
+            path, MAX_TOKEN_LEN
+        ));
+    }
+    Ok(token)
+}
@@ -0,0 +1,89 @@
+use crate::logger::Filter;
+use crate::server_socket::Endpoint;
+use crate::token::read_token;
+use std::path::PathBuf;
+
+const DEFAULT_LISTEN: &str = "unix:c:\\Temp\\reflex.unix";
+
+pub struct Config {
+    pub listen: Endpoint,
+    // Pre-shared token expected from reflex, required for tcp endpoints.
+    pub token: Option<Vec<u8>>,
+    // What is logged, as "<level>[,<module path>[@<client id>]=<level>]...".
+    pub log_filter: Filter,
+    // Logs go to stdout when not given.
+    pub log_file: Option<PathBuf>,
+    // A file whose contents replace log_filter whenever it changes.
+    pub log_level_file: Option<PathBuf>,
+}
+
+pub fn usage() -> String {
+    "usage: ultimate-desktop-vision [--listen unix:<path>|tcp:<host>:<port>] [--token-file <path>]\n\
+     \x20      [--log-level <level>[,<module>[@<client>]=<level>]...] [--log-file <path>]\n\
+     \x20      [--log-level-file <path>]\n\
+     levels: off, error, warn, info, debug, trace\n\
+     the token is sent in plaintext: use tcp only on a trusted network or a tunnel"
+        .to_owned()
+}
+
+impl Config {
+    pub fn from_args<I>(args: I) -> Result<Config, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut listen = Endpoint::parse(DEFAULT_LISTEN)?;
+        let mut token = None;
+        let mut log_filter = Filter::parse("info")?;
+        let mut log_file = None;
+        let mut log_level_file = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--listen" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--listen requires a value".to_owned())?;
+                    listen = Endpoint::parse(&value)?;
+                }
+                "--token-file" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--token-file requires a value".to_owned())?;
+                    token = Some(read_token(&value)?);
+                }
+                "--log-level" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--log-level requires a value".to_owned())?;
+                    log_filter = Filter::parse(&value)?;
+                }
+                "--log-file" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--log-file requires a value".to_owned())?;
+                    log_file = Some(PathBuf::from(value));
+                }
+                "--log-level-file" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| "--log-level-file requires a value".to_owned())?;
+                    log_level_file = Some(PathBuf::from(value));
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+
+        if let Endpoint::Tcp(_) = listen {
+            if token.is_none() {
+                return Err("--token-file is required for tcp endpoints".to_owned());
+            }
+        }
+        Ok(Config {
+            listen,
+            token,
+            log_filter,
+            log_file,
+            log_level_file,
+        })
+    }
+}
@@ -2,8 +2,11 @@ use futures::future::Future;
 use futures::sink::Sink;
 use futures::stream::Stream;
 use protocol::codec::Codec;
+use protocol::control::ControlMessage;
 use protocol::event::Event;
-use protocol::request::Request;
+use protocol::link;
+use protocol::pixels::SurfaceImage;
+use protocol::request::{LinkRequest, Request};
 use protocol::resource::Resource;
 use protocol::session::Session;
 use protocol::wayland::wl_compositor::WlCompositor;
@@ -15,20 +18,19 @@ use protocol::wayland::wl_shm::WlShm;
 use protocol::xdg_shell::xdg_wm_base::XdgWmBase;
 use server_socket::ServerSocket;
 use std::collections::HashMap;
-use std::convert::TryInto;
 use std::sync::{Arc, RwLock};
 use tokio::codec::Decoder;
-use tokio::runtime::Runtime;
-use winapi::shared::minwindef::TRUE;
-use winapi::shared::winerror::ERROR_NOT_ENOUGH_MEMORY;
-use winapi::um::winuser::{
-    DispatchMessageW, GetMessageW, IsGUIThread, TranslateMessage, MSG, WM_APP,
-};
-
-pub const WM_APP_CREATE_WINDOW: u32 = WM_APP + 1;
+use tokio::runtime::{Runtime, TaskExecutor};
+use tokio::sync::mpsc::Sender;
 
+mod config;
+#[path = "../../shared/logger.rs"]
+mod logger;
 mod protocol;
 mod server_socket;
+#[path = "../../shared/token.rs"]
+mod token;
+mod window;
 
 #[derive(Clone)]
 struct Global {
@@ -40,41 +42,6 @@ struct Global {
     xdg_wm_base: Arc<RwLock<XdgWmBase>>,
 }
 
-unsafe fn gui_thread(gui_thread_init: tokio::sync::oneshot::Sender<()>) {
-    // https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-isguithread
-    let conversion_result = IsGUIThread(TRUE);
-    if conversion_result == 0 {
-        panic!("Failed to convert thread to GUI thread");
-    } else if conversion_result == ERROR_NOT_ENOUGH_MEMORY.try_into().unwrap() {
-        panic!(
-            "Failed to convert thread to GUI thread: {}",
-            conversion_result
-        );
-    }
-    gui_thread_init.send(()).unwrap();
-
-    loop {
-        let mut msg = MSG::default();
-        match GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) {
-            0 => {
-                break;
-            }
-            -1 => {
-                // error
-                break;
-            }
-            _ => {}
-        }
-        if msg.hwnd == std::ptr::null_mut() {
-            if msg.message == WM_APP_CREATE_WINDOW {
-                //
-            }
-        }
-        TranslateMessage(&msg);
-        DispatchMessageW(&msg);
-    }
-}
-
 fn handle_client_input(
     mut session: Session,
     req: Request,
@@ -92,9 +59,11 @@ fn handle_client_input(
                 req.sender_object_id, req.opcode, req.args
             ),
         };
-        println!(
+        log::warn!(
             "object_id={} opcode={} args={:?} not found",
-            req.sender_object_id, req.opcode, req.args
+            req.sender_object_id,
+            req.opcode,
+            req.args
         );
         let f: Box<dyn Future<Item = Session, Error = std::io::Error> + Send> = Box::new(
             tx.send(Box::new(error))
@@ -116,54 +85,226 @@ fn handle_client_input(
     f
 }
 
-fn handle_client(runtime: &mut Runtime, stream: tokio::net::TcpStream, global: Global) {
+// Why a link ended, with the sessions of the clients it still had.
+type LinkEnd = (std::io::Error, HashMap<u32, Session>);
+type LinkFuture = Box<dyn Future<Item = HashMap<u32, Session>, Error = LinkEnd> + Send>;
+
+// Every client of reflex is multiplexed over one link; each gets its own
+// Session once reflex announces it.
+fn handle_link_request(
+    mut sessions: HashMap<u32, Session>,
+    req: LinkRequest,
+    global: &Global,
+    link_tx: &Sender<(u32, Box<dyn Event + Send>)>,
+) -> LinkFuture {
+    match req {
+        LinkRequest::ClientConnected(client_id) => {
+            log::info!("client {} connected", client_id);
+            let (tx0, rx0) = tokio::sync::mpsc::channel::<Box<dyn Event + Send>>(48000);
+            let tagged = rx0
+                .map(move |event| (client_id, event))
+                .map_err(|err| log::warn!("err={:?}", err))
+                .forward(
+                    link_tx
+                        .clone()
+                        .sink_map_err(|err| log::warn!("err={:?}", err)),
+                )
+                .map(|_| ());
+            tokio::spawn(logger::with_client(client_id, tagged));
+
+            let mut session = Session {
+                wl_display: global.wl_display.clone(),
+                wl_registry: global.wl_registry.clone(),
+                wl_compositor: global.wl_compositor.clone(),
+                wl_shm: global.wl_shm.clone(),
+                wl_data_device_manager: global.wl_data_device_manager.clone(),
+                xdg_wm_base: global.xdg_wm_base.clone(),
+                resources: HashMap::new(),
+                tx: tx0,
+                callback_data: 0,
+            };
+            session.resources.insert(
+                link::to_link_id(client_id, 1),
+                Resource::WlDisplay(session.wl_display.clone()),
+            );
+            sessions.insert(client_id, session);
+            Box::new(futures::future::ok(sessions))
+        }
+        LinkRequest::ClientDisconnected(client_id) => {
+            log::info!("client {} disconnected", client_id);
+            sessions.remove(&client_id);
+            window::client_gone(client_id);
+            Box::new(futures::future::ok(sessions))
+        }
+        LinkRequest::Message(client_id, req) => {
+            let session = match sessions.remove(&client_id) {
+                Some(session) => session,
+                None => {
+                    log::warn!("client {} is unknown, request dropped", client_id);
+                    return Box::new(futures::future::ok(sessions));
+                }
+            };
+            Box::new(
+                handle_client_input(session, req).then(move |result| match result {
+                    Ok(session) => {
+                        sessions.insert(client_id, session);
+                        Ok(sessions)
+                    }
+                    // The session is lost with the error, and so are its
+                    // windows.
+                    Err(err) => {
+                        window::client_gone(client_id);
+                        Err((err, sessions))
+                    }
+                }),
+            )
+        }
+        LinkRequest::Pixels(client_id, pixels) => {
+            let surface = sessions
+                .get_mut(&client_id)
+                .and_then(|session| session.resources.get_mut(&pixels.surface_id));
+            match surface {
+                Some(Resource::WlSurface(surface)) => surface.pending_pixels.push(pixels),
+                _ => log::warn!(
+                    "client {} has no surface {:#x}, pixels dropped",
+                    client_id,
+                    pixels.surface_id
+                ),
+            }
+            Box::new(futures::future::ok(sessions))
+        }
+        // Requests are handled one at a time, so everything reflex sent
+        // before is done; the answer goes after the events it caused.
+        LinkRequest::Sync(client_id, serial) => {
+            let sync_done: Box<dyn Event + Send> = Box::new(ControlMessage::SyncDone { serial });
+            let sent: Box<dyn Future<Item = (), Error = std::io::Error> + Send> =
+                match sessions.get(&client_id) {
+                    Some(session) => Box::new(
+                        session
+                            .tx
+                            .clone()
+                            .send(sync_done)
+                            .map(|_| ())
+                            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
+                    ),
+                    None => Box::new(
+                        link_tx
+                            .clone()
+                            .send((client_id, sync_done))
+                            .map(|_| ())
+                            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
+                    ),
+                };
+            Box::new(sent.then(move |result| match result {
+                Ok(()) => Ok(sessions),
+                Err(err) => Err((err, sessions)),
+            }))
+        }
+        // Held back by reflex for commits that have been handled already.
+        LinkRequest::HeldPixels(client_id, pixels) => {
+            let surface_id = pixels.surface_id;
+            let session = match sessions.get_mut(&client_id) {
+                Some(session) => session,
+                None => {
+                    log::warn!("client {} is unknown, pixels dropped", client_id);
+                    return Box::new(futures::future::ok(sessions));
+                }
+            };
+            match session.resources.get_mut(&surface_id) {
+                Some(Resource::WlSurface(surface)) => {
+                    surface.image = Some(SurfaceImage::apply(surface.image.take(), pixels))
+                }
+                _ => {
+                    log::warn!(
+                        "client {} has no surface {:#x}, pixels dropped",
+                        client_id,
+                        surface_id
+                    );
+                    return Box::new(futures::future::ok(sessions));
+                }
+            }
+            let frame_done = ControlMessage::FrameDone { surface_id };
+            Box::new(session.tx.clone().send(Box::new(frame_done)).then(
+                move |result| match result {
+                    Ok(_) => Ok(sessions),
+                    Err(err) => Err((
+                        std::io::Error::new(std::io::ErrorKind::Other, err),
+                        sessions,
+                    )),
+                },
+            ))
+        }
+    }
+}
+
+fn handle_client(executor: &TaskExecutor, stream: tokio::net::TcpStream, global: Global) {
     let (writer0, reader0) = Codec::new().framed(stream).split();
 
-    let (tx0, rx0) = tokio::sync::mpsc::channel::<Box<dyn Event + Send>>(48000);
+    let (link_tx0, link_rx0) = tokio::sync::mpsc::channel::<(u32, Box<dyn Event + Send>)>(48000);
 
-    let output_session = rx0
+    let output_session = link_rx0
         .map_err(|err| {
-            println!("err={:?}", err);
+            log::warn!("err={:?}", err);
             std::io::Error::new(std::io::ErrorKind::Other, "Oops!")
         })
         .forward(writer0)
-        .map_err(|err| println!("err={:?}", err))
+        .map_err(|err| log::warn!("err={:?}", err))
         .and_then(|_| Ok(()));
-    runtime.spawn(output_session);
-
-    let (gui_thread_init_tx0, gui_thread_init_rx0) = tokio::sync::oneshot::channel::<()>();
+    executor.spawn(output_session);
 
-    let mut session0 = Session {
-        wl_display: global.wl_display,
-        wl_registry: global.wl_registry,
-        wl_compositor: global.wl_compositor,
-        wl_shm: global.wl_shm,
-        wl_data_device_manager: global.wl_data_device_manager,
-        xdg_wm_base: global.xdg_wm_base,
-        resources: HashMap::new(),
-        tx: tx0,
-        callback_data: 0,
-    };
-
-    session0
-        .resources
-        .insert(1, Resource::WlDisplay(session0.wl_display.clone()));
-
-    std::thread::spawn(move || unsafe { gui_thread(gui_thread_init_tx0) });
-
-    let input_session0 = gui_thread_init_rx0
-        .and_then(|_| {
-            reader0
-                .fold(session0, handle_client_input)
-                .map_err(|err| println!("err: {:?}", err))
-                .then(|_| futures::future::ok(()))
+    // The errors of the link are items, so that the sessions it still has
+    // come out of the fold either way.
+    let input_session0 = reader0
+        .then(Ok::<_, LinkEnd>)
+        .fold(HashMap::new(), move |sessions, req| -> LinkFuture {
+            let req = match req {
+                Ok(req) => req,
+                Err(err) => return Box::new(futures::future::err((err, sessions))),
+            };
+            let client_id = req.client_id();
+            let _client = logger::enter_client(client_id);
+            Box::new(logger::with_client(
+                client_id,
+                handle_link_request(sessions, req, &global, &link_tx0),
+            ))
         })
-        .map_err(|err| println!("err: {:?}", err));
-    runtime.spawn(input_session0);
+        .then(|result| {
+            let sessions = match result {
+                Ok(sessions) => sessions,
+                Err((err, sessions)) => {
+                    log::warn!("err: {:?}", err);
+                    sessions
+                }
+            };
+            // The clients go with the link; reflex announces them again if
+            // it reconnects.
+            for client_id in sessions.keys() {
+                window::client_gone(*client_id);
+            }
+            futures::future::ok(())
+        });
+    executor.spawn(input_session0);
 }
 
 fn main() {
+    let config = match config::Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, config::usage());
+            std::process::exit(2);
+        }
+    };
+    let log_filter = match logger::init(config.log_filter.clone(), config.log_file.as_deref()) {
+        Ok(log_filter) => log_filter,
+        Err(err) => {
+            eprintln!("failed to open the log file: {}", err);
+            std::process::exit(1);
+        }
+    };
     let mut runtime = Runtime::new().unwrap();
+    if let Some(path) = config.log_level_file.clone() {
+        runtime.spawn(logger::watch_level_file(path, log_filter));
+    }
     let global = Global {
         wl_display: Arc::new(RwLock::new(WlDisplay {})),
         wl_compositor: Arc::new(RwLock::new(WlCompositor {})),
@@ -173,12 +314,37 @@ fn main() {
         xdg_wm_base: Arc::new(RwLock::new(XdgWmBase {})),
     };
 
-    let mut server_socket = ServerSocket::bind().unwrap();
-    loop {
-        if let Some(stream) = server_socket.accept() {
-            handle_client(&mut runtime, stream, global.clone());
-        } else {
-            eprintln!("Oops!");
+    // One GUI thread owns the windows of every client.
+    let (gui_thread_init_tx, gui_thread_init_rx) = tokio::sync::oneshot::channel::<()>();
+    std::thread::spawn(move || unsafe { window::gui_thread(gui_thread_init_tx) });
+    gui_thread_init_rx.wait().expect("gui thread init");
+
+    let mut server_socket = match ServerSocket::bind(&config.listen, config.token) {
+        Some(server_socket) => server_socket,
+        None => {
+            log::error!("failed to listen on {:?}", config.listen);
+            std::process::exit(1);
         }
+    };
+    let executor = runtime.executor();
+    loop {
+        let accepted = match server_socket.accept() {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log::error!("accept failed: {}", err);
+                continue;
+            }
+        };
+        // The handshake waits on reflex, which must not hold up the next
+        // connection.
+        let executor = executor.clone();
+        let global = global.clone();
+        std::thread::spawn(move || {
+            let peer = accepted.peer().to_owned();
+            match accepted.handshake() {
+                Ok(stream) => handle_client(&executor, stream, global),
+                Err(err) => log::warn!("handshake with {} failed: {}", peer, err),
+            }
+        });
     }
 }
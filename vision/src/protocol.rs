@@ -1,7 +1,13 @@
 pub mod codec;
+pub mod control;
 pub mod event;
+pub mod link;
+pub mod pixels;
 pub mod request;
 pub mod resource;
+#[allow(dead_code)]
+#[path = "../../shared/rle.rs"]
+pub mod rle;
 pub mod session;
 pub mod wayland;
 pub mod xdg_shell;
@@ -1,74 +1,131 @@
 use super::event::Event;
-use super::request::Request;
-use byteorder::{NativeEndian, ReadBytesExt};
+use super::link;
+use super::pixels::SurfacePixels;
+use super::request::{LinkRequest, Request};
+use byteorder::{ByteOrder, NativeEndian, ReadBytesExt};
 use bytes::BytesMut;
+use std::collections::VecDeque;
 use std::io::{Cursor, Read};
 
-pub struct Codec;
+pub struct Codec {
+    // Messages of a MESSAGES frame that have not been handed out yet.
+    pending: VecDeque<LinkRequest>,
+}
 
 impl Codec {
     pub fn new() -> Codec {
-        Codec {}
+        Codec {
+            pending: VecDeque::new(),
+        }
     }
 }
 
 impl tokio::codec::Encoder for Codec {
-    type Item = Box<dyn Event + Send>;
+    type Item = (u32, Box<dyn Event + Send>);
     type Error = std::io::Error;
 
     fn encode(
         &mut self,
-        res: Box<dyn Event + Send>,
+        (client_id, res): (u32, Box<dyn Event + Send>),
         dst: &mut BytesMut,
     ) -> Result<(), Self::Error> {
-        let response_type: [u8; 4] = [0, 0, 0, 0];
-        dst.extend_from_slice(&response_type);
+        let mut header = [0; 8];
+        NativeEndian::write_u32(&mut header[0..], res.response_type());
+        NativeEndian::write_u32(&mut header[4..], client_id);
+        dst.extend_from_slice(&header);
         res.encode(dst)
     }
 }
 
 impl tokio::codec::Decoder for Codec {
-    type Item = Request;
+    type Item = LinkRequest;
     type Error = std::io::Error;
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Request>, Self::Error> {
-        // println!("decode: {:?}", &src);
-        let header_size = 8;
-        if src.is_empty() || src.len() < header_size {
-            return Ok(None);
-        }
-
-        // https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Wire-Format
-        let (req, message_size) = {
-            let mut cursor = Cursor::new(&src);
-            let sender_object_id = cursor.read_u32::<NativeEndian>().unwrap();
-            let message_size_and_opcode = cursor.read_u32::<NativeEndian>().unwrap();
-            let message_size = (message_size_and_opcode >> 16) as usize;
-            if src.len() < message_size {
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<LinkRequest>, Self::Error> {
+        loop {
+            if let Some(req) = self.pending.pop_front() {
+                return Ok(Some(req));
+            }
+            if src.len() < link::REQUEST_HEADER_SIZE {
                 return Ok(None);
             }
-
-            let opcode = (0x0000ffff & message_size_and_opcode) as u16;
-            if message_size < header_size {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Oops!"));
+            let request_type = NativeEndian::read_u32(&src[0..]);
+            let client_id = NativeEndian::read_u32(&src[4..]);
+            let data_len = NativeEndian::read_u32(&src[8..]) as usize;
+            if src.len() < link::REQUEST_HEADER_SIZE + data_len {
+                return Ok(None);
             }
-            let mut args = Vec::new();
-            args.resize(message_size - header_size, 0);
-            cursor.read_exact(&mut args).unwrap();
-            println!(
-                "decode: id={} opcode={} args={:?}",
-                sender_object_id, opcode, &args
-            );
-            (
-                Request {
-                    sender_object_id,
-                    opcode,
-                    args,
-                },
-                message_size,
-            )
-        };
-        src.advance(message_size);
-        return Ok(Some(req));
+            let frame = src.split_to(link::REQUEST_HEADER_SIZE + data_len);
+            match request_type {
+                link::REQUEST_CLIENT_CONNECTED => {
+                    return Ok(Some(LinkRequest::ClientConnected(client_id)))
+                }
+                link::REQUEST_CLIENT_DISCONNECTED => {
+                    return Ok(Some(LinkRequest::ClientDisconnected(client_id)))
+                }
+                link::REQUEST_PIXELS => {
+                    let pixels = SurfacePixels::decode(&frame[link::REQUEST_HEADER_SIZE..])?;
+                    return Ok(Some(LinkRequest::Pixels(client_id, pixels)));
+                }
+                link::REQUEST_HELD_PIXELS => {
+                    let pixels = SurfacePixels::decode(&frame[link::REQUEST_HEADER_SIZE..])?;
+                    return Ok(Some(LinkRequest::HeldPixels(client_id, pixels)));
+                }
+                link::REQUEST_SYNC => {
+                    let data = &frame[link::REQUEST_HEADER_SIZE..];
+                    if data.len() != 4 {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("sync of {} bytes", data.len()),
+                        ));
+                    }
+                    let serial = NativeEndian::read_u32(data);
+                    return Ok(Some(LinkRequest::Sync(client_id, serial)));
+                }
+                link::REQUEST_MESSAGES => {
+                    let data = &frame[link::REQUEST_HEADER_SIZE..];
+                    for req in decode_messages(data)? {
+                        self.pending.push_back(LinkRequest::Message(client_id, req));
+                    }
+                }
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("unknown request_type={}", request_type),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+// https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-Wire-Format
+fn decode_messages(data: &[u8]) -> Result<Vec<Request>, std::io::Error> {
+    let header_size = 8;
+    let mut requests = Vec::new();
+    let mut cursor = Cursor::new(data);
+    while (cursor.position() as usize) < data.len() {
+        let sender_object_id = cursor.read_u32::<NativeEndian>()?;
+        let message_size_and_opcode = cursor.read_u32::<NativeEndian>()?;
+        let message_size = (message_size_and_opcode >> 16) as usize;
+        let opcode = (0x0000ffff & message_size_and_opcode) as u16;
+        if message_size < header_size {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Oops!"));
+        }
+        let mut args = Vec::new();
+        args.resize(message_size - header_size, 0);
+        cursor.read_exact(&mut args)?;
+        log::debug!(
+            "decode: id={} opcode={} args={:?}",
+            sender_object_id,
+            opcode,
+            &args
+        );
+        requests.push(Request {
+            sender_object_id,
+            opcode,
+            args,
+        });
     }
+    Ok(requests)
 }
@@ -0,0 +1,305 @@
+use super::event::Event;
+use super::link::RESPONSE_CONTROL;
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use bytes::BytesMut;
+use std::io::{self, Cursor, Read};
+
+// Control messages tell reflex what happened on the host display, as opposed
+// to Wayland events relayed for a client. Framing after the response type:
+// kind, payload length, payload, all native-endian.
+pub const CONTROL_HEADER_SIZE: usize = 8;
+pub const MAX_CONTROL_PAYLOAD: usize = 64 * 1024;
+
+pub const KIND_WINDOW_CLOSED: u32 = 0;
+pub const KIND_WINDOW_RESIZED: u32 = 1;
+pub const KIND_FOCUS_CHANGED: u32 = 2;
+pub const KIND_POINTER_MOTION: u32 = 3;
+pub const KIND_POINTER_BUTTON: u32 = 4;
+pub const KIND_KEY: u32 = 5;
+pub const KIND_OUTPUT_CHANGED: u32 = 6;
+pub const KIND_CLIPBOARD_CHANGED: u32 = 7;
+pub const KIND_FRAME_DONE: u32 = 8;
+pub const KIND_SYNC_DONE: u32 = 9;
+pub const KIND_KEYBOARD_FOCUS: u32 = 10;
+pub const KIND_POINTER_FOCUS: u32 = 11;
+
+// Object ids are link ids, see link::to_link_id.
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlMessage {
+    // The close button of the host window was pressed.
+    WindowClosed {
+        toplevel_id: u32,
+    },
+    WindowResized {
+        xdg_surface_id: u32,
+        toplevel_id: u32,
+        width: i32,
+        height: i32,
+    },
+    FocusChanged {
+        xdg_surface_id: u32,
+        toplevel_id: u32,
+        focused: bool,
+    },
+    // surface_x and surface_y are wl_fixed (24.8).
+    PointerMotion {
+        pointer_id: u32,
+        time: u32,
+        surface_x: u32,
+        surface_y: u32,
+    },
+    PointerButton {
+        pointer_id: u32,
+        time: u32,
+        button: u32,
+        state: u32,
+    },
+    Key {
+        keyboard_id: u32,
+        time: u32,
+        key: u32,
+        state: u32,
+    },
+    // A monitor of the host was plugged, unplugged or changed its mode.
+    // refresh is in mHz.
+    OutputChanged {
+        output_id: u32,
+        connected: bool,
+        width: i32,
+        height: i32,
+        refresh: i32,
+        scale: i32,
+    },
+    ClipboardChanged {
+        mime_types: Vec<String>,
+    },
+    // The pixels last sent for the surface are on screen; gives back its
+    // credit, see flow.rs in reflex.
+    FrameDone {
+        surface_id: u32,
+    },
+    // The answer to a SYNC request: the frames of the client sent before it
+    // have been handled, and their events come before this.
+    SyncDone {
+        serial: u32,
+    },
+    // The wl_keyboard has the keyboard focus on surface_id from now on, or
+    // on no surface when it is 0.
+    KeyboardFocus {
+        keyboard_id: u32,
+        surface_id: u32,
+    },
+    // The same for the wl_pointer, which is at surface_x and surface_y
+    // (wl_fixed) on the surface.
+    PointerFocus {
+        pointer_id: u32,
+        surface_id: u32,
+        surface_x: u32,
+        surface_y: u32,
+    },
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[allow(dead_code)]
+impl ControlMessage {
+    pub fn kind(&self) -> u32 {
+        match self {
+            ControlMessage::WindowClosed { .. } => KIND_WINDOW_CLOSED,
+            ControlMessage::WindowResized { .. } => KIND_WINDOW_RESIZED,
+            ControlMessage::FocusChanged { .. } => KIND_FOCUS_CHANGED,
+            ControlMessage::PointerMotion { .. } => KIND_POINTER_MOTION,
+            ControlMessage::PointerButton { .. } => KIND_POINTER_BUTTON,
+            ControlMessage::Key { .. } => KIND_KEY,
+            ControlMessage::OutputChanged { .. } => KIND_OUTPUT_CHANGED,
+            ControlMessage::ClipboardChanged { .. } => KIND_CLIPBOARD_CHANGED,
+            ControlMessage::FrameDone { .. } => KIND_FRAME_DONE,
+            ControlMessage::SyncDone { .. } => KIND_SYNC_DONE,
+            ControlMessage::KeyboardFocus { .. } => KIND_KEYBOARD_FOCUS,
+            ControlMessage::PointerFocus { .. } => KIND_POINTER_FOCUS,
+        }
+    }
+
+    fn encode_payload(&self) -> Vec<u8> {
+        let mut dst = Vec::new();
+        let words: Vec<u32> = match self {
+            ControlMessage::WindowClosed { toplevel_id } => vec![*toplevel_id],
+            ControlMessage::WindowResized {
+                xdg_surface_id,
+                toplevel_id,
+                width,
+                height,
+            } => vec![*xdg_surface_id, *toplevel_id, *width as u32, *height as u32],
+            ControlMessage::FocusChanged {
+                xdg_surface_id,
+                toplevel_id,
+                focused,
+            } => vec![*xdg_surface_id, *toplevel_id, *focused as u32],
+            ControlMessage::PointerMotion {
+                pointer_id,
+                time,
+                surface_x,
+                surface_y,
+            } => vec![*pointer_id, *time, *surface_x, *surface_y],
+            ControlMessage::PointerButton {
+                pointer_id,
+                time,
+                button,
+                state,
+            } => vec![*pointer_id, *time, *button, *state],
+            ControlMessage::Key {
+                keyboard_id,
+                time,
+                key,
+                state,
+            } => vec![*keyboard_id, *time, *key, *state],
+            ControlMessage::OutputChanged {
+                output_id,
+                connected,
+                width,
+                height,
+                refresh,
+                scale,
+            } => vec![
+                *output_id,
+                *connected as u32,
+                *width as u32,
+                *height as u32,
+                *refresh as u32,
+                *scale as u32,
+            ],
+            ControlMessage::FrameDone { surface_id } => vec![*surface_id],
+            ControlMessage::SyncDone { serial } => vec![*serial],
+            ControlMessage::KeyboardFocus {
+                keyboard_id,
+                surface_id,
+            } => vec![*keyboard_id, *surface_id],
+            ControlMessage::PointerFocus {
+                pointer_id,
+                surface_id,
+                surface_x,
+                surface_y,
+            } => vec![*pointer_id, *surface_id, *surface_x, *surface_y],
+            ControlMessage::ClipboardChanged { mime_types } => {
+                dst.write_u32::<NativeEndian>(mime_types.len() as u32)
+                    .unwrap();
+                for mime_type in mime_types {
+                    dst.write_u32::<NativeEndian>(mime_type.len() as u32)
+                        .unwrap();
+                    dst.extend_from_slice(mime_type.as_bytes());
+                }
+                return dst;
+            }
+        };
+        for word in words {
+            dst.write_u32::<NativeEndian>(word).unwrap();
+        }
+        dst
+    }
+
+    pub fn decode(kind: u32, payload: &[u8]) -> io::Result<ControlMessage> {
+        let mut cursor = Cursor::new(payload);
+        let message = match kind {
+            KIND_WINDOW_CLOSED => ControlMessage::WindowClosed {
+                toplevel_id: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_WINDOW_RESIZED => ControlMessage::WindowResized {
+                xdg_surface_id: cursor.read_u32::<NativeEndian>()?,
+                toplevel_id: cursor.read_u32::<NativeEndian>()?,
+                width: cursor.read_i32::<NativeEndian>()?,
+                height: cursor.read_i32::<NativeEndian>()?,
+            },
+            KIND_FOCUS_CHANGED => ControlMessage::FocusChanged {
+                xdg_surface_id: cursor.read_u32::<NativeEndian>()?,
+                toplevel_id: cursor.read_u32::<NativeEndian>()?,
+                focused: cursor.read_u32::<NativeEndian>()? != 0,
+            },
+            KIND_POINTER_MOTION => ControlMessage::PointerMotion {
+                pointer_id: cursor.read_u32::<NativeEndian>()?,
+                time: cursor.read_u32::<NativeEndian>()?,
+                surface_x: cursor.read_u32::<NativeEndian>()?,
+                surface_y: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_POINTER_BUTTON => ControlMessage::PointerButton {
+                pointer_id: cursor.read_u32::<NativeEndian>()?,
+                time: cursor.read_u32::<NativeEndian>()?,
+                button: cursor.read_u32::<NativeEndian>()?,
+                state: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_KEY => ControlMessage::Key {
+                keyboard_id: cursor.read_u32::<NativeEndian>()?,
+                time: cursor.read_u32::<NativeEndian>()?,
+                key: cursor.read_u32::<NativeEndian>()?,
+                state: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_OUTPUT_CHANGED => ControlMessage::OutputChanged {
+                output_id: cursor.read_u32::<NativeEndian>()?,
+                connected: cursor.read_u32::<NativeEndian>()? != 0,
+                width: cursor.read_i32::<NativeEndian>()?,
+                height: cursor.read_i32::<NativeEndian>()?,
+                refresh: cursor.read_i32::<NativeEndian>()?,
+                scale: cursor.read_i32::<NativeEndian>()?,
+            },
+            KIND_FRAME_DONE => ControlMessage::FrameDone {
+                surface_id: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_SYNC_DONE => ControlMessage::SyncDone {
+                serial: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_KEYBOARD_FOCUS => ControlMessage::KeyboardFocus {
+                keyboard_id: cursor.read_u32::<NativeEndian>()?,
+                surface_id: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_POINTER_FOCUS => ControlMessage::PointerFocus {
+                pointer_id: cursor.read_u32::<NativeEndian>()?,
+                surface_id: cursor.read_u32::<NativeEndian>()?,
+                surface_x: cursor.read_u32::<NativeEndian>()?,
+                surface_y: cursor.read_u32::<NativeEndian>()?,
+            },
+            KIND_CLIPBOARD_CHANGED => {
+                let count = cursor.read_u32::<NativeEndian>()? as usize;
+                let mut mime_types = Vec::new();
+                for _ in 0..count {
+                    let len = cursor.read_u32::<NativeEndian>()? as usize;
+                    if len > payload.len() {
+                        return Err(invalid_data(format!("mime type of {} bytes", len)));
+                    }
+                    let mut buf = vec![0; len];
+                    cursor.read_exact(&mut buf)?;
+                    let mime_type = String::from_utf8(buf)
+                        .map_err(|err| invalid_data(format!("mime type: {}", err)))?;
+                    mime_types.push(mime_type);
+                }
+                ControlMessage::ClipboardChanged { mime_types }
+            }
+            _ => return Err(invalid_data(format!("unknown control kind {}", kind))),
+        };
+        if cursor.position() as usize != payload.len() {
+            return Err(invalid_data(format!(
+                "control kind {} has {} trailing bytes",
+                kind,
+                payload.len() - cursor.position() as usize
+            )));
+        }
+        Ok(message)
+    }
+}
+
+impl Event for ControlMessage {
+    fn response_type(&self) -> u32 {
+        RESPONSE_CONTROL
+    }
+
+    fn encode(&self, dst: &mut BytesMut) -> Result<(), std::io::Error> {
+        let payload = self.encode_payload();
+        let mut header = Vec::with_capacity(CONTROL_HEADER_SIZE);
+        header.write_u32::<NativeEndian>(self.kind())?;
+        header.write_u32::<NativeEndian>(payload.len() as u32)?;
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
@@ -1,5 +1,10 @@
 use bytes::BytesMut;
 
 pub trait Event {
+    // Written by the codec in front of the encoded message.
+    fn response_type(&self) -> u32 {
+        super::link::RESPONSE_EVENT
+    }
+
     fn encode(&self, dst: &mut BytesMut) -> Result<(), std::io::Error>;
 }
@@ -0,0 +1,93 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::{Read, Write};
+
+// Exchanged before any Wayland message on the reflex-vision link.
+pub const HELLO_MAGIC: [u8; 4] = *b"UDHL";
+// Bumped whenever the framing or the meaning of messages on the link changes.
+pub const LINK_VERSION: u32 = 7;
+
+pub const HELLO_ACCEPTED: u32 = 0;
+pub const HELLO_VERSION_MISMATCH: u32 = 1;
+
+// Optional behaviour both ends have to agree on.
+// Rects of PIXELS frames may be run-length coded.
+pub const FEATURE_RLE: u32 = 1 << 0;
+pub const SUPPORTED_FEATURES: u32 = FEATURE_RLE;
+
+// What this display can do, reported to reflex.
+pub const CAPABILITY_WINDOWS: u32 = 1 << 0;
+pub const CAPABILITIES: u32 = CAPABILITY_WINDOWS;
+
+// Prefix of every message sent to reflex after the hello: either a Wayland
+// event for the client or a control message about this display.
+pub const RESPONSE_EVENT: u32 = 0;
+pub const RESPONSE_CONTROL: u32 = 1;
+
+// Prefix of every frame reflex sends after the hello, followed by the client
+// id and the length of the data.
+pub const REQUEST_MESSAGES: u32 = 0;
+pub const REQUEST_CLIENT_CONNECTED: u32 = 1;
+pub const REQUEST_CLIENT_DISCONNECTED: u32 = 2;
+pub const REQUEST_PIXELS: u32 = 3;
+// Pixels held back while the surface had no credit, for commits vision has
+// already seen: shown at once rather than at the next commit.
+pub const REQUEST_HELD_PIXELS: u32 = 4;
+// A serial that vision sends back in SYNC_DONE once it has handled every
+// earlier frame of the client.
+pub const REQUEST_SYNC: u32 = 5;
+pub const REQUEST_HEADER_SIZE: usize = 12;
+
+// All clients share one link, so reflex puts the client id in the top bits
+// of every object id. Vision keeps the ids as they are, one id space for the
+// windows of all clients.
+pub const LOCAL_ID_BITS: u32 = 20;
+
+pub fn to_link_id(client_id: u32, local_id: u32) -> u32 {
+    if local_id == 0 {
+        0
+    } else {
+        client_id << LOCAL_ID_BITS | local_id
+    }
+}
+
+// reflex -> vision: magic, version, features
+// vision -> reflex: magic, status, version, features, capabilities
+// All fields are little-endian. Returns the features enabled on the link.
+// Blocks until reflex has sent its hello; the caller sets a timeout.
+pub fn accept_hello(stream: &mut std::net::TcpStream) -> std::io::Result<u32> {
+    let mut request = [0; 12];
+    stream.read_exact(&mut request)?;
+
+    if request[..4] != HELLO_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "reflex did not send a hello; is it an older build?",
+        ));
+    }
+    let version = LittleEndian::read_u32(&request[4..]);
+    let features = LittleEndian::read_u32(&request[8..]) & SUPPORTED_FEATURES;
+    let status = if version == LINK_VERSION {
+        HELLO_ACCEPTED
+    } else {
+        HELLO_VERSION_MISMATCH
+    };
+
+    let mut reply = [0; 20];
+    reply[..4].copy_from_slice(&HELLO_MAGIC);
+    LittleEndian::write_u32(&mut reply[4..], status);
+    LittleEndian::write_u32(&mut reply[8..], LINK_VERSION);
+    LittleEndian::write_u32(&mut reply[12..], features);
+    LittleEndian::write_u32(&mut reply[16..], CAPABILITIES);
+    stream.write_all(&reply)?;
+
+    if status != HELLO_ACCEPTED {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "link version mismatch: vision speaks {}, reflex speaks {}",
+                LINK_VERSION, version
+            ),
+        ));
+    }
+    Ok(features)
+}
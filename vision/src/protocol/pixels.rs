@@ -0,0 +1,152 @@
+use super::rle;
+use byteorder::{NativeEndian, ReadBytesExt};
+use std::io::{self, Cursor, Read};
+
+// Reflex reads the pixels of shm buffers, which vision cannot map, and sends
+// them in PIXELS frames:
+//
+//   surface (link id), format, width, height, stride, rect count
+//   per rect: x, y, width, height, encoding, length, then length bytes of
+//   height rows of width pixels, coded as the encoding says
+//
+// Rects are in buffer coordinates; width, height and stride are those of
+// the buffer. Both formats reflex accepts, argb8888 and xrgb8888, have 4
+// bytes per pixel.
+pub const BYTES_PER_PIXEL: usize = 4;
+
+pub const ENCODING_RAW: u32 = 0;
+// Only sent when the link has FEATURE_RLE, see rle.rs.
+pub const ENCODING_RLE: u32 = 1;
+
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct SurfacePixels {
+    pub surface_id: u32,
+    pub format: u32,
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub rects: Vec<(Rect, Vec<u8>)>,
+}
+
+impl SurfacePixels {
+    pub fn decode(data: &[u8]) -> io::Result<SurfacePixels> {
+        let mut cursor = Cursor::new(data);
+        let surface_id = cursor.read_u32::<NativeEndian>()?;
+        let format = cursor.read_u32::<NativeEndian>()?;
+        let width = cursor.read_u32::<NativeEndian>()? as usize;
+        let height = cursor.read_u32::<NativeEndian>()? as usize;
+        let stride = cursor.read_u32::<NativeEndian>()? as usize;
+        let rect_count = cursor.read_u32::<NativeEndian>()?;
+        if stride < width * BYTES_PER_PIXEL {
+            return Err(invalid(format!("stride {} for width {}", stride, width)));
+        }
+        let mut rects = Vec::new();
+        for _ in 0..rect_count {
+            let x = cursor.read_i32::<NativeEndian>()?;
+            let y = cursor.read_i32::<NativeEndian>()?;
+            let rect_width = cursor.read_i32::<NativeEndian>()?;
+            let rect_height = cursor.read_i32::<NativeEndian>()?;
+            let encoding = cursor.read_u32::<NativeEndian>()?;
+            let length = cursor.read_u32::<NativeEndian>()? as usize;
+            if x < 0
+                || y < 0
+                || rect_width <= 0
+                || rect_height <= 0
+                || x as usize + rect_width as usize > width
+                || y as usize + rect_height as usize > height
+            {
+                return Err(invalid(format!(
+                    "rect {},{} {}x{} outside of {}x{}",
+                    x, y, rect_width, rect_height, width, height
+                )));
+            }
+            let rect = Rect {
+                x: x as usize,
+                y: y as usize,
+                width: rect_width as usize,
+                height: rect_height as usize,
+            };
+            let pixel_count = rect.width * rect.height;
+            if length > data.len() - cursor.position() as usize {
+                return Err(invalid(format!("rect of {} bytes past the end", length)));
+            }
+            let mut coded = vec![0; length];
+            cursor.read_exact(&mut coded)?;
+            let rows = match encoding {
+                ENCODING_RAW if length == pixel_count * BYTES_PER_PIXEL => coded,
+                ENCODING_RAW => {
+                    return Err(invalid(format!(
+                        "{} bytes for {} pixels",
+                        length, pixel_count
+                    )))
+                }
+                ENCODING_RLE => rle::decode(&coded, pixel_count)?,
+                _ => return Err(invalid(format!("unknown encoding {}", encoding))),
+            };
+            rects.push((rect, rows));
+        }
+        if cursor.position() as usize != data.len() {
+            return Err(invalid("trailing bytes".to_string()));
+        }
+        Ok(SurfacePixels {
+            surface_id,
+            format,
+            width,
+            height,
+            stride,
+            rects,
+        })
+    }
+}
+
+// What a surface shows, rebuilt from the PIXELS frames reflex sends.
+pub struct SurfaceImage {
+    pub format: u32,
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl SurfaceImage {
+    // A buffer of another size or layout starts a new image; the rects of
+    // the frame are then expected to cover it.
+    pub fn apply(image: Option<SurfaceImage>, pixels: SurfacePixels) -> SurfaceImage {
+        let mut image = match image {
+            Some(image)
+                if image.format == pixels.format
+                    && image.width == pixels.width
+                    && image.height == pixels.height
+                    && image.stride == pixels.stride =>
+            {
+                image
+            }
+            _ => SurfaceImage {
+                format: pixels.format,
+                width: pixels.width,
+                height: pixels.height,
+                stride: pixels.stride,
+                data: vec![0; pixels.stride * pixels.height],
+            },
+        };
+        for (rect, rows) in pixels.rects {
+            let row_len = rect.width * BYTES_PER_PIXEL;
+            for row in 0..rect.height {
+                let start = (rect.y + row) * image.stride + rect.x * BYTES_PER_PIXEL;
+                image.data[start..start + row_len]
+                    .copy_from_slice(&rows[row * row_len..(row + 1) * row_len]);
+            }
+        }
+        image
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
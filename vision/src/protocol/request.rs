@@ -1,5 +1,31 @@
+use super::pixels::SurfacePixels;
+
 pub struct Request {
     pub sender_object_id: u32,
     pub opcode: u16,
     pub args: Vec<u8>,
 }
+
+// A frame from reflex, with the Wayland messages of a MESSAGES frame split up.
+pub enum LinkRequest {
+    ClientConnected(u32),
+    ClientDisconnected(u32),
+    Message(u32, Request),
+    Pixels(u32, SurfacePixels),
+    HeldPixels(u32, SurfacePixels),
+    // client id, serial
+    Sync(u32, u32),
+}
+
+impl LinkRequest {
+    pub fn client_id(&self) -> u32 {
+        match self {
+            LinkRequest::ClientConnected(client_id)
+            | LinkRequest::ClientDisconnected(client_id)
+            | LinkRequest::Message(client_id, _)
+            | LinkRequest::Pixels(client_id, _)
+            | LinkRequest::HeldPixels(client_id, _)
+            | LinkRequest::Sync(client_id, _) => *client_id,
+        }
+    }
+}
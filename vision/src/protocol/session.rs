@@ -71,10 +71,29 @@ where
     pub fn ok(
         self,
     ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("ok");
+        log::debug!("ok");
         Box::new(futures::future::ok((self.into(), NextAction::Relay)))
     }
 
+    pub fn destroy(
+        self,
+    ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
+        // wl_display.delete_id is sent by reflex, which owns the object id space.
+        log::debug!("destroy {}", self.sender_object_id);
+        let session = Session {
+            resources: self.resources,
+            wl_display: self.wl_display,
+            wl_registry: self.wl_registry,
+            wl_compositor: self.wl_compositor,
+            wl_shm: self.wl_shm,
+            wl_data_device_manager: self.wl_data_device_manager,
+            xdg_wm_base: self.xdg_wm_base,
+            tx: self.tx,
+            callback_data: self.callback_data,
+        };
+        Box::new(futures::future::ok((session, NextAction::Nop)))
+    }
+
     fn create_invalid_method_error(
         &self,
         message: String,
@@ -91,7 +110,7 @@ where
         self,
         message: String,
     ) -> Box<dyn futures::future::Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("invalid method {}", &message);
+        log::warn!("invalid method {}", &message);
         let tx = self.tx.clone();
         let error = self.create_invalid_method_error(message);
         let session: Session = self.into();
@@ -106,7 +125,7 @@ where
         self,
         message: String,
     ) -> Box<dyn futures::future::Future<Item = Session, Error = ()> + Send> {
-        println!("invalid method {}", &message);
+        log::warn!("invalid method {}", &message);
         let tx = self.tx.clone();
         let error = self.create_invalid_method_error(message);
         let session: Session = self.into();
@@ -61,6 +61,6 @@ impl WlBuffer {
     pub fn destroy(
         context: Context<WlBuffer>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_buffer::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 }
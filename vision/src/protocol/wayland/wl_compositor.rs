@@ -65,6 +65,8 @@ impl WlCompositor {
             id,
             wayland::wl_surface::WlSurface {
                 xdg_surface_id: None,
+                pending_pixels: Vec::new(),
+                image: None,
             }
             .into(),
         );
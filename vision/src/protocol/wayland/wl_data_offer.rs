@@ -78,7 +78,7 @@ impl WlDataOffer {
     pub fn destroy(
         context: Context<WlDataOffer>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_data_offer::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // the offer will no longer be used
@@ -59,48 +59,12 @@ impl WlDisplay {
         mut context: Context<Arc<RwLock<WlDisplay>>>,
         registry: u32, // new_id: global registry object
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlDisplay::get_registry({})", registry);
+        log::debug!("WlDisplay::get_registry({})", registry);
+        // Globals are advertised by reflex, which owns the registry.
         context
             .resources
             .insert(registry, context.wl_registry.clone().into());
-
-        Box::new(
-            futures::future::ok(context.tx.clone())
-                .and_then(move |tx| {
-                    tx.send(Box::new(
-                        crate::protocol::wayland::wl_registry::events::Global {
-                            sender_object_id: registry,
-                            name: crate::protocol::wayland::wl_compositor::GLOBAL_SINGLETON_NAME,
-                            interface: "wl_compositor".to_owned(),
-                            version: crate::protocol::wayland::wl_compositor::VERSION,
-                        },
-                    ))
-                    .map_err(|err| println!("err={:?}", err))
-                })
-                .and_then(move |tx| {
-                    tx.send(Box::new(
-                        crate::protocol::wayland::wl_registry::events::Global {
-                            sender_object_id: registry,
-                            name: crate::protocol::wayland::wl_shm::GLOBAL_SINGLETON_NAME,
-                            interface: "wl_shm".to_owned(),
-                            version: crate::protocol::wayland::wl_shm::VERSION,
-                        },
-                    ))
-                    .map_err(|err| println!("err={:?}", err))
-                })
-                .and_then(move |tx| {
-                    tx.send(Box::new(
-                        crate::protocol::wayland::wl_registry::events::Global {
-                            sender_object_id: registry,
-                            name: crate::protocol::xdg_shell::xdg_wm_base::GLOBAL_SINGLETON_NAME,
-                            interface: "xdg_wm_base".to_owned(),
-                            version: crate::protocol::xdg_shell::xdg_wm_base::VERSION,
-                        },
-                    ))
-                    .map_err(|err| println!("err={:?}", err))
-                })
-                .and_then(|_| context.ok()),
-        )
+        context.ok()
     }
 
     // asynchronous roundtrip
@@ -120,7 +84,7 @@ impl WlDisplay {
         mut context: Context<Arc<RwLock<WlDisplay>>>,
         callback: u32, // new_id: callback object for the sync request
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlDisplay::sync({})", callback);
+        log::debug!("WlDisplay::sync({})", callback);
         context.callback_data += 1;
         let tx = context.tx.clone();
         Box::new(
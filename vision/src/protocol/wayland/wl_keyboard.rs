@@ -48,6 +48,7 @@ impl WlKeyboard {
     pub fn release(
         context: Context<WlKeyboard>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_keyboard::release is not implemented yet".to_string())
+        crate::window::keyboard_removed(context.sender_object_id);
+        context.destroy()
     }
 }
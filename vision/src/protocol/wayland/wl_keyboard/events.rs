@@ -39,7 +39,7 @@ pub struct Enter {
 
 impl super::super::super::event::Event for Enter {
     fn encode(&self, dst: &mut bytes::BytesMut) -> Result<(), std::io::Error> {
-        let total_len = 8 + 4 + 4 + { 4 + (self.keys.len() + 1 + 3) / 4 * 4 };
+        let total_len = 8 + 4 + 4 + { 4 + (self.keys.len() + 3) / 4 * 4 };
         if total_len > 0xffff {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Oops!"));
         }
@@ -70,7 +70,7 @@ impl super::super::super::event::Event for Enter {
                 .copy_from_slice(&aligned_keys[..]);
         }
 
-        encode_offset += { 4 + (self.keys.len() + 1 + 3) / 4 * 4 };
+        encode_offset += { 4 + (self.keys.len() + 3) / 4 * 4 };
         let _ = encode_offset;
         Ok(())
     }
@@ -153,7 +153,7 @@ impl super::super::super::event::Event for Keymap {
         encode_offset += 8;
         NativeEndian::write_u32(&mut dst[encode_offset..], self.format);
         encode_offset += 4;
-        println!("UNIMPLEMENTED!!!!!");
+        // The fd goes as ancillary data, not in the bytes.
         encode_offset += 0;
         NativeEndian::write_u32(&mut dst[encode_offset..], self.size);
         encode_offset += 4;
@@ -60,7 +60,7 @@ impl WlPointer {
     pub fn release(
         context: Context<WlPointer>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_pointer::release is not implemented yet".to_string())
+        context.destroy()
     }
 
     // set the pointer surface
@@ -63,7 +63,7 @@ impl WlRegion {
     pub fn destroy(
         context: Context<WlRegion>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_region::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // subtract rectangle from region
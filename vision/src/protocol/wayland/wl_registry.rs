@@ -24,84 +24,13 @@
 // SOFTWARE.
 
 use crate::protocol::session::{Context, NextAction, Session};
-use byteorder::{NativeEndian, ReadBytesExt};
 use futures::future::{ok, Future};
 use futures::sink::Sink;
-use std::convert::TryInto;
-use std::io::{Cursor, Read};
 use std::sync::{Arc, RwLock};
 
 pub mod events;
 mod lib;
-pub use lib::{GLOBAL_SINGLETON_NAME, VERSION};
-
-pub fn dispatch_request(
-    context: crate::protocol::session::Context<
-        Arc<RwLock<crate::protocol::wayland::wl_registry::WlRegistry>>,
-    >,
-    opcode: u16,
-    args: Vec<u8>,
-) -> Box<dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()> + Send> {
-    if opcode != 0 || args.len() <= 8 {
-        return lib::dispatch_request(context, opcode, args);
-    }
-
-    let mut cursor = Cursor::new(&args);
-    let name = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-    let name_buf_len = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x as usize
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-    let name_buf_len_with_pad = (name_buf_len + 3) / 4 * 4;
-    let mut name_buf = Vec::new();
-    name_buf.resize(name_buf_len, 0);
-    cursor.read_exact(&mut name_buf).unwrap();
-    cursor.set_position(cursor.position() + (name_buf_len_with_pad - name_buf_len) as u64);
-    let _version = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-
-    let id = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
-        x
-    } else {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    };
-
-    if Ok(cursor.position()) != args.len().try_into() {
-        return context
-            .invalid_method_dispatch(format!("opcode={} args={:?} not found", opcode, &args));
-    }
-
-    Box::new(WlRegistry::bind(context, name, id).and_then(
-        |(session, next_action)| -> Box<
-            dyn futures::future::Future<Item = crate::protocol::session::Session, Error = ()>
-                + Send,
-        > {
-            match next_action {
-                NextAction::Nop => Box::new(futures::future::ok(session)),
-                NextAction::Relay => {
-                    Box::new(futures::future::ok(()).and_then(|_| futures::future::ok(session)))
-                }
-                NextAction::RelayWait => Box::new(
-                    futures::future::ok(())
-                        .and_then(|_| futures::future::ok(()))
-                        .and_then(|_| futures::future::ok(session)),
-                ),
-            }
-        },
-    ))
-}
+pub use lib::*;
 
 // global registry object
 //
@@ -134,44 +63,66 @@ impl WlRegistry {
     // specified name as the identifier.
     pub fn bind(
         mut context: Context<Arc<RwLock<WlRegistry>>>,
-        name: u32, // uint: unique numeric name of the object
-        id: u32,   // new_id: bounded object
+        name: u32,         // uint: unique numeric name of the object
+        interface: String, // string: interface of the bound object
+        version: u32,      // uint: version of the bound object
+        id: u32,           // new_id: bounded object
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlRegistry::bind(name: {}, id: {})", name, id);
+        // The interface and version have already been validated by reflex.
+        log::debug!(
+            "WlRegistry::bind(name: {}, interface: {}, version: {}, id: {})",
+            name,
+            interface.trim_end_matches('\0'),
+            version,
+            id
+        );
         let tx = context.tx.clone();
 
-        match name {
-            crate::protocol::wayland::wl_registry::GLOBAL_SINGLETON_NAME => {
+        // Global names are allocated by reflex, so objects are looked up by
+        // their interface.
+        match interface.trim_end_matches('\0') {
+            "wl_compositor" => {
                 context
                     .resources
-                    .insert(id, context.wl_registry.clone().into());
+                    .insert(id, context.wl_compositor.clone().into());
                 return context.ok();
             }
-            crate::protocol::wayland::wl_display::GLOBAL_SINGLETON_NAME => {
+            "wl_data_device_manager" => {
                 context
                     .resources
-                    .insert(id, context.wl_display.clone().into());
+                    .insert(id, context.wl_data_device_manager.clone().into());
                 return context.ok();
             }
-            crate::protocol::wayland::wl_compositor::GLOBAL_SINGLETON_NAME => {
+            "wl_seat" => {
                 context
                     .resources
-                    .insert(id, context.wl_compositor.clone().into());
-                return context.ok();
+                    .insert(id, crate::protocol::wayland::wl_seat::WlSeat {}.into());
+                return Box::new(
+                    tx.send(Box::new(
+                        crate::protocol::wayland::wl_seat::events::Capabilities {
+                            sender_object_id: id,
+                            capabilities:
+                                crate::protocol::wayland::wl_seat::enums::Capability::Keyboard
+                                    as u32,
+                        },
+                    ))
+                    .map_err(|_| ())
+                    .and_then(|_| context.ok()),
+                );
             }
-            crate::protocol::wayland::wl_data_device_manager::GLOBAL_SINGLETON_NAME => {
+            "wl_output" => {
                 context
                     .resources
-                    .insert(id, context.wl_data_device_manager.clone().into());
+                    .insert(id, crate::protocol::wayland::wl_output::WlOutput {}.into());
                 return context.ok();
             }
-            crate::protocol::xdg_shell::xdg_wm_base::GLOBAL_SINGLETON_NAME => {
+            "xdg_wm_base" => {
                 context
                     .resources
                     .insert(id, context.xdg_wm_base.clone().into());
                 return context.ok();
             }
-            crate::protocol::wayland::wl_shm::GLOBAL_SINGLETON_NAME => {
+            "wl_shm" => {
                 context.resources.insert(id, context.wl_shm.clone().into());
                 return Box::new(
                     ok(tx)
@@ -203,8 +154,11 @@ impl WlRegistry {
                     object_id: context.sender_object_id,
                     code: crate::protocol::wayland::wl_display::enums::Error::InvalidMethod as u32,
                     message: format!(
-                        "wl_registry@{} name={} id={} not found",
-                        context.sender_object_id, name, id
+                        "wl_registry@{} name={} interface={} id={} not found",
+                        context.sender_object_id,
+                        name,
+                        interface.trim_end_matches('\0'),
+                        id
                     ),
                 },
             ))
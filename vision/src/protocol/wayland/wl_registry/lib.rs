@@ -62,6 +62,43 @@ pub fn dispatch_request(
                     opcode, args
                 ));
             };
+            let arg_interface = {
+                let buf_len = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
+                    x
+                } else {
+                    return context.invalid_method_dispatch(format!(
+                        "opcode={} args={:?} not found",
+                        opcode, args
+                    ));
+                };
+                let padded_buf_len = (buf_len + 3) / 4 * 4;
+                let mut buf = Vec::new();
+                buf.resize(buf_len as usize, 0);
+                if cursor.read_exact(&mut buf).is_err() {
+                    return context.invalid_method_dispatch(format!(
+                        "opcode={} args={:?} not found",
+                        opcode, args
+                    ));
+                }
+                let s = if let Ok(x) = String::from_utf8(buf) {
+                    x
+                } else {
+                    return context.invalid_method_dispatch(format!(
+                        "opcode={} args={:?} not found",
+                        opcode, args
+                    ));
+                };
+                cursor.set_position(cursor.position() + u64::from(padded_buf_len - buf_len));
+                s
+            };
+            let arg_version = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
+                x
+            } else {
+                return context.invalid_method_dispatch(format!(
+                    "opcode={} args={:?} not found",
+                    opcode, args
+                ));
+            };
             let arg_id = if let Ok(x) = cursor.read_u32::<NativeEndian>() {
                 x
             } else {
@@ -77,14 +114,17 @@ pub fn dispatch_request(
                     opcode, args
                 ));
             }
-            return Box::new(super::WlRegistry::bind(context, arg_name, arg_id).and_then(
-                |(session, next_action)| -> Box<
-                    dyn futures::future::Future<
-                            Item = crate::protocol::session::Session,
-                            Error = (),
-                        > + Send,
-                > { Box::new(futures::future::ok(session)) },
-            ));
+            return Box::new(
+                super::WlRegistry::bind(context, arg_name, arg_interface, arg_version, arg_id)
+                    .and_then(
+                        |(session, next_action)| -> Box<
+                            dyn futures::future::Future<
+                                    Item = crate::protocol::session::Session,
+                                    Error = (),
+                                > + Send,
+                        > { Box::new(futures::future::ok(session)) },
+                    ),
+            );
         }
         _ => {}
     };
@@ -56,10 +56,16 @@ impl WlSeat {
     // It is a protocol violation to issue this request on a seat that has
     // never had the keyboard capability.
     pub fn get_keyboard(
-        context: Context<WlSeat>,
-        _id: u32, // new_id: seat keyboard
+        mut context: Context<WlSeat>,
+        id: u32, // new_id: seat keyboard
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_seat::get_keyboard is not implemented yet".to_string())
+        context.resources.insert(
+            id,
+            crate::protocol::wayland::wl_keyboard::WlKeyboard {}.into(),
+        );
+        // Given the keyboard focus with the host windows of the client.
+        crate::window::keyboard_added(id);
+        context.ok()
     }
 
     // return pointer object
@@ -101,6 +107,6 @@ impl WlSeat {
     pub fn release(
         context: Context<WlSeat>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_seat::release is not implemented yet".to_string())
+        context.destroy()
     }
 }
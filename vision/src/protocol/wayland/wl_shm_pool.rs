@@ -72,9 +72,14 @@ impl WlShmPool {
         stride: i32, // int: number of bytes from the beginning of one row to the beginning of the next row
         format: u32, // uint: buffer pixel format
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!(
+        log::debug!(
             "WlShmPool::create_buffer({}, {}, {}, {}, {}, {})",
-            id, offset, width, height, stride, format
+            id,
+            offset,
+            width,
+            height,
+            stride,
+            format
         );
         context.resources.insert(
             id,
@@ -100,7 +105,7 @@ impl WlShmPool {
     pub fn destroy(
         context: Context<WlShmPool>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_shm_pool::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // change the size of the pool mapping
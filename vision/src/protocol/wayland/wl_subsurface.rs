@@ -100,7 +100,7 @@ impl WlSubsurface {
     pub fn destroy(
         context: Context<WlSubsurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_subsurface::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // restack the sub-surface
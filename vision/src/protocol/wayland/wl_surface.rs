@@ -23,6 +23,8 @@
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::protocol::control::ControlMessage;
+use crate::protocol::pixels::{SurfaceImage, SurfacePixels};
 #[allow(unused_imports)]
 use crate::protocol::session::{Context, NextAction, Session};
 #[allow(unused_imports)]
@@ -80,6 +82,9 @@ pub use lib::*;
 // switching is not allowed).
 pub struct WlSurface {
     pub xdg_surface_id: Option<u32>,
+    // Sent by reflex ahead of the commit that shows them.
+    pub pending_pixels: Vec<SurfacePixels>,
+    pub image: Option<SurfaceImage>,
 }
 
 impl WlSurface {
@@ -126,11 +131,12 @@ impl WlSurface {
     // following wl_surface.commit will remove the surface content.
     pub fn attach(
         context: Context<WlSurface>,
-        _buffer: u32, // object: buffer of surface contents
-        _x: i32,      // int: surface-local x coordinate
-        _y: i32,      // int: surface-local y coordinate
+        buffer: u32, // object: buffer of surface contents
+        x: i32,      // int: surface-local x coordinate
+        y: i32,      // int: surface-local y coordinate
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::attach is not implemented yet".to_string())
+        log::debug!("WlSurface::attach(buffer={}, x={}, y={})", buffer, x, y);
+        context.ok()
     }
 
     // commit pending surface state
@@ -153,10 +159,35 @@ impl WlSurface {
     //
     // Other interfaces may add further double-buffered surface state.
     pub fn commit(
-        context: Context<WlSurface>,
+        mut context: Context<WlSurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("WlSurface::commit");
-        context.ok()
+        log::debug!("WlSurface::commit");
+        let surface = &mut context.sender_object;
+        let shown = !surface.pending_pixels.is_empty();
+        for pixels in surface.pending_pixels.drain(..) {
+            surface.image = Some(SurfaceImage::apply(surface.image.take(), pixels));
+        }
+        if let Some(image) = &surface.image {
+            log::debug!(
+                "WlSurface::commit image {}x{} format={:#x}",
+                image.width,
+                image.height,
+                image.format
+            );
+        }
+        if !shown {
+            return context.ok();
+        }
+        // Reflex sends no more pixels for the surface until it gets this.
+        let frame_done = ControlMessage::FrameDone {
+            surface_id: context.sender_object_id,
+        };
+        let tx = context.tx.clone();
+        Box::new(
+            tx.send(Box::new(frame_done))
+                .map_err(|_| ())
+                .and_then(|_| context.ok()),
+        )
     }
 
     // mark part of the surface damaged
@@ -242,7 +273,7 @@ impl WlSurface {
     pub fn destroy(
         context: Context<WlSurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // request a frame throttling hint
@@ -313,9 +344,10 @@ impl WlSurface {
     // raised.
     pub fn set_buffer_scale(
         context: Context<WlSurface>,
-        _scale: i32, // int: positive scale for interpreting buffer contents
+        scale: i32, // int: positive scale for interpreting buffer contents
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("wl_surface::set_buffer_scale is not implemented yet".to_string())
+        log::debug!("WlSurface::set_buffer_scale({})", scale);
+        context.ok()
     }
 
     // sets the buffer transformation
@@ -351,10 +383,10 @@ impl WlSurface {
     // is raised.
     pub fn set_buffer_transform(
         context: Context<WlSurface>,
-        _transform: i32, // int: transform for interpreting buffer contents
+        transform: i32, // int: transform for interpreting buffer contents
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context
-            .invalid_method("wl_surface::set_buffer_transform is not implemented yet".to_string())
+        log::debug!("WlSurface::set_buffer_transform({})", transform);
+        context.ok()
     }
 
     // set input region
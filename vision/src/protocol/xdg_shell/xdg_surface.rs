@@ -119,7 +119,7 @@ impl XdgSurface {
     pub fn destroy(
         context: Context<XdgSurface>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_surface::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // assign the xdg_popup surface role
@@ -152,7 +152,7 @@ impl XdgSurface {
         mut context: Context<XdgSurface>,
         id: u32, // new_id:
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("XdgSurface::get_toplevel(id={})", id);
+        log::debug!("XdgSurface::get_toplevel(id={})", id);
         context.resources.insert(
             id,
             crate::protocol::xdg_shell::xdg_toplevel::XdgToplevel {
@@ -160,6 +160,12 @@ impl XdgSurface {
             }
             .into(),
         );
+        crate::window::create(crate::window::Window {
+            surface_id: context.sender_object.wl_surface_id,
+            xdg_surface_id: context.sender_object_id,
+            toplevel_id: id,
+            tx: context.tx.clone(),
+        });
         context.ok()
     }
 
@@ -71,7 +71,7 @@ impl XdgWmBase {
     pub fn destroy(
         context: Context<Arc<RwLock<XdgWmBase>>>,
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        context.invalid_method("xdg_wm_base::destroy is not implemented yet".to_string())
+        context.destroy()
     }
 
     // create a shell surface from a surface
@@ -92,7 +92,7 @@ impl XdgWmBase {
         id: u32,      // new_id:
         surface: u32, // object:
     ) -> Box<dyn Future<Item = (Session, NextAction), Error = ()> + Send> {
-        println!("XdgWmBase::get_xdg_surface(id={}, surface={})", id, surface);
+        log::debug!("XdgWmBase::get_xdg_surface(id={}, surface={})", id, surface);
         if let Some(Resource::WlSurface(wl_surface)) = context.resources.get_mut(&surface) {
             wl_surface.xdg_surface_id = Some(id);
         } else {
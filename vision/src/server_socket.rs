@@ -1,92 +1,185 @@
-use std::os::raw::{c_char, c_int};
-use std::os::windows::io::FromRawSocket;
-use tokio::reactor::Handle;
-use winapi::shared::minwindef::MAKEWORD;
-use winapi::shared::ws2def::{ADDRESS_FAMILY, AF_UNIX, SOCKADDR};
-use winapi::um::winsock2::*;
-
-#[repr(C)]
-struct SOCKADDR_UN {
-    sun_family: ADDRESS_FAMILY,
-    sun_path: [c_char; 108],
-}
-
-pub struct ServerSocket {
-    native_socket: SOCKET,
-}
-
-impl ServerSocket {
-    pub fn bind() -> Option<ServerSocket> {
-        let mut sockaddr = SOCKADDR_UN {
-            sun_family: AF_UNIX as ADDRESS_FAMILY,
-            sun_path: [0; 108],
-        };
-
-        let socket_path = "c:\\Temp\\reflex.unix";
-        let mut sun_path = format!("{}\0", socket_path)
-            .as_bytes()
-            .iter()
-            .map(|c| *c as i8)
-            .collect::<Vec<_>>();
-        sun_path.resize(sockaddr.sun_path.len(), 0);
-        sockaddr.sun_path.copy_from_slice(&sun_path);
-
-        unsafe {
-            let mut wsa_data = WSADATA::default();
-            let x = WSAStartup(MAKEWORD(2, 2), &mut wsa_data);
-            if x != 0 {
-                return None;
-            };
-
-            let server_socket = socket(AF_UNIX, SOCK_STREAM, 0);
-            if server_socket == INVALID_SOCKET {
-                return None;
-            }
-
-            let _ = std::fs::remove_file(socket_path);
-            let b = bind(
-                server_socket,
-                &sockaddr as *const SOCKADDR_UN as *const SOCKADDR,
-                std::mem::size_of::<SOCKADDR_UN>() as c_int,
-            );
-            if b == SOCKET_ERROR {
-                return None;
-            }
-
-            let l = listen(server_socket, SOMAXCONN);
-            if l == SOCKET_ERROR {
-                panic!("listen");
-            }
-
-            Some(ServerSocket {
-                native_socket: server_socket,
-            })
-        }
-    }
-
-    pub fn accept(&mut self) -> Option<tokio::net::TcpStream> {
-        let client_socket = unsafe {
-            accept(
-                self.native_socket,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-            )
-        };
-        if client_socket == INVALID_SOCKET {
-            return None;
-        }
-        let std_stream = unsafe {
-            std::net::TcpStream::from_raw_socket(client_socket as std::os::windows::raw::SOCKET)
-        };
-
-        Some(tokio::net::TcpStream::from_std(std_stream, &Handle::default()).unwrap())
-    }
-}
-
-impl Drop for ServerSocket {
-    fn drop(&mut self) {
-        unsafe {
-            closesocket(self.native_socket);
-        }
-    }
-}
+use crate::token::verify_token;
+use std::os::raw::{c_char, c_int};
+use std::os::windows::io::FromRawSocket;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::reactor::Handle;
+use winapi::shared::minwindef::MAKEWORD;
+use winapi::shared::ws2def::{ADDRESS_FAMILY, AF_UNIX, SOCKADDR};
+use winapi::um::winsock2::*;
+
+#[repr(C)]
+struct SOCKADDR_UN {
+    sun_family: ADDRESS_FAMILY,
+    sun_path: [c_char; 108],
+}
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    Unix(String),
+    Tcp(String),
+}
+
+impl Endpoint {
+    // "unix:c:\path\to\socket" or "tcp:host:port".
+    pub fn parse(value: &str) -> Result<Endpoint, String> {
+        let mut parts = value.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("unix"), Some(path)) if !path.is_empty() => Ok(Endpoint::Unix(path.to_owned())),
+            (Some("tcp"), Some(address)) => match address.rfind(':') {
+                Some(i) if i > 0 && address[i + 1..].parse::<u16>().is_ok() => {
+                    Ok(Endpoint::Tcp(address.to_owned()))
+                }
+                _ => Err(format!("expected tcp:<host>:<port>: {}", value)),
+            },
+            _ => Err(format!(
+                "expected unix:<path> or tcp:<host>:<port>: {}",
+                value
+            )),
+        }
+    }
+}
+
+enum Listener {
+    Unix(SOCKET),
+    Tcp(std::net::TcpListener, Arc<Vec<u8>>),
+}
+
+pub struct ServerSocket {
+    listener: Listener,
+}
+
+impl ServerSocket {
+    // A token is required for TCP, where reflex may be on another machine.
+    pub fn bind(endpoint: &Endpoint, token: Option<Vec<u8>>) -> Option<ServerSocket> {
+        match endpoint {
+            Endpoint::Unix(path) => ServerSocket::bind_unix(path),
+            Endpoint::Tcp(address) => {
+                let token = token?;
+                let listener = std::net::TcpListener::bind(address.as_str()).ok()?;
+                Some(ServerSocket {
+                    listener: Listener::Tcp(listener, Arc::new(token)),
+                })
+            }
+        }
+    }
+
+    fn bind_unix(socket_path: &str) -> Option<ServerSocket> {
+        let mut sockaddr = SOCKADDR_UN {
+            sun_family: AF_UNIX as ADDRESS_FAMILY,
+            sun_path: [0; 108],
+        };
+
+        let mut sun_path = format!("{}\0", socket_path)
+            .as_bytes()
+            .iter()
+            .map(|c| *c as i8)
+            .collect::<Vec<_>>();
+        sun_path.resize(sockaddr.sun_path.len(), 0);
+        sockaddr.sun_path.copy_from_slice(&sun_path);
+
+        unsafe {
+            let mut wsa_data = WSADATA::default();
+            let x = WSAStartup(MAKEWORD(2, 2), &mut wsa_data);
+            if x != 0 {
+                return None;
+            };
+
+            let server_socket = socket(AF_UNIX, SOCK_STREAM, 0);
+            if server_socket == INVALID_SOCKET {
+                return None;
+            }
+
+            let _ = std::fs::remove_file(socket_path);
+            let b = bind(
+                server_socket,
+                &sockaddr as *const SOCKADDR_UN as *const SOCKADDR,
+                std::mem::size_of::<SOCKADDR_UN>() as c_int,
+            );
+            if b == SOCKET_ERROR {
+                return None;
+            }
+
+            let l = listen(server_socket, SOMAXCONN);
+            if l == SOCKET_ERROR {
+                panic!("listen");
+            }
+
+            Some(ServerSocket {
+                listener: Listener::Unix(server_socket),
+            })
+        }
+    }
+
+    // Only takes the connection: the token and the hello are left to
+    // Accepted::handshake, which waits on reflex.
+    pub fn accept(&mut self) -> std::io::Result<Accepted> {
+        match &self.listener {
+            Listener::Unix(native_socket) => {
+                let client_socket =
+                    unsafe { accept(*native_socket, std::ptr::null_mut(), std::ptr::null_mut()) };
+                if client_socket == INVALID_SOCKET {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let stream = unsafe {
+                    std::net::TcpStream::from_raw_socket(
+                        client_socket as std::os::windows::raw::SOCKET,
+                    )
+                };
+                Ok(Accepted {
+                    stream,
+                    peer: "unix".to_owned(),
+                    token: None,
+                })
+            }
+            Listener::Tcp(listener, token) => {
+                let (stream, peer) = listener.accept()?;
+                Ok(Accepted {
+                    stream,
+                    peer: peer.to_string(),
+                    token: Some(token.clone()),
+                })
+            }
+        }
+    }
+}
+
+// A connection from reflex that has yet to present its token, for TCP, and
+// its hello.
+pub struct Accepted {
+    stream: std::net::TcpStream,
+    peer: String,
+    token: Option<Arc<Vec<u8>>>,
+}
+
+impl Accepted {
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    // Blocks for up to HANDSHAKE_TIMEOUT on a reflex that says nothing, so
+    // it is run off the accept loop.
+    pub fn handshake(mut self) -> std::io::Result<tokio::net::TcpStream> {
+        self.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        if let Some(token) = &self.token {
+            verify_token(&mut self.stream, token)?;
+            let _ = self.stream.set_nodelay(true);
+        }
+        let features = crate::protocol::link::accept_hello(&mut self.stream)?;
+        self.stream.set_read_timeout(None)?;
+        log::info!("link features={:#x} from {}", features, self.peer);
+        tokio::net::TcpStream::from_std(self.stream, &Handle::default())
+    }
+}
+
+impl Drop for ServerSocket {
+    fn drop(&mut self) {
+        if let Listener::Unix(native_socket) = self.listener {
+            unsafe {
+                closesocket(native_socket);
+            }
+        }
+    }
+}
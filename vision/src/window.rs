@@ -0,0 +1,324 @@
+use crate::protocol::control::ControlMessage;
+use crate::protocol::event::Event;
+use crate::protocol::link::LOCAL_ID_BITS;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::sync::mpsc::Sender;
+use winapi::shared::minwindef::{DWORD, HIWORD, LOWORD, LPARAM, LRESULT, TRUE, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::shared::winerror::ERROR_NOT_ENOUGH_MEMORY;
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::winuser::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetFocus, GetMessageW,
+    IsGUIThread, LoadCursorW, PostThreadMessageW, RegisterClassExW, ShowWindow, TranslateMessage,
+    CW_USEDEFAULT, IDC_ARROW, MSG, SW_SHOW, WM_APP, WM_CLOSE, WM_DESTROY, WM_KILLFOCUS,
+    WM_SETFOCUS, WM_SIZE, WNDCLASSEXW, WS_OVERLAPPEDWINDOW,
+};
+
+// Posted to the GUI thread, which owns the host windows of every client.
+const WM_APP_CREATE_WINDOW: UINT = WM_APP + 1;
+const WM_APP_DESTROY_WINDOW: UINT = WM_APP + 2;
+const WM_APP_CLIENT_GONE: UINT = WM_APP + 3;
+const WM_APP_KEYBOARD_ADDED: UINT = WM_APP + 4;
+const WM_APP_KEYBOARD_REMOVED: UINT = WM_APP + 5;
+
+const CLASS_NAME: &str = "ultimate-desktop-vision";
+
+static GUI_THREAD_ID: AtomicU32 = AtomicU32::new(0);
+
+// The host window of an xdg_toplevel. Ids are link ids.
+pub struct Window {
+    pub surface_id: u32,
+    pub xdg_surface_id: u32,
+    pub toplevel_id: u32,
+    // Where the control messages for the client go.
+    pub tx: Sender<Box<dyn Event + Send>>,
+}
+
+thread_local! {
+    static WINDOWS: RefCell<HashMap<HWND, Window>> = RefCell::new(HashMap::new());
+    // The wl_keyboards of every client, which get the keyboard focus with
+    // the windows of their client.
+    static KEYBOARDS: RefCell<Vec<u32>> = RefCell::new(Vec::new());
+}
+
+pub fn create(window: Window) {
+    let toplevel_id = window.toplevel_id;
+    let window = Box::into_raw(Box::new(window));
+    if !post(WM_APP_CREATE_WINDOW, 0, window as LPARAM) {
+        log::warn!("no host window for toplevel {:#x}", toplevel_id);
+        unsafe {
+            drop(Box::from_raw(window));
+        }
+    }
+}
+
+pub fn destroy(toplevel_id: u32) {
+    post(WM_APP_DESTROY_WINDOW, toplevel_id as WPARAM, 0);
+}
+
+pub fn client_gone(client_id: u32) {
+    post(WM_APP_CLIENT_GONE, client_id as WPARAM, 0);
+}
+
+pub fn keyboard_added(keyboard_id: u32) {
+    post(WM_APP_KEYBOARD_ADDED, keyboard_id as WPARAM, 0);
+}
+
+pub fn keyboard_removed(keyboard_id: u32) {
+    post(WM_APP_KEYBOARD_REMOVED, keyboard_id as WPARAM, 0);
+}
+
+fn post(message: UINT, wparam: WPARAM, lparam: LPARAM) -> bool {
+    let posted = unsafe {
+        PostThreadMessageW(
+            GUI_THREAD_ID.load(Ordering::SeqCst),
+            message,
+            wparam,
+            lparam,
+        )
+    };
+    posted != 0
+}
+
+fn client_of(link_id: u32) -> u32 {
+    link_id >> LOCAL_ID_BITS
+}
+
+fn wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(Some(0)).collect()
+}
+
+pub unsafe fn gui_thread(gui_thread_init: tokio::sync::oneshot::Sender<()>) {
+    // https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-isguithread
+    let conversion_result = IsGUIThread(TRUE);
+    if conversion_result == 0 {
+        panic!("Failed to convert thread to GUI thread");
+    } else if conversion_result == ERROR_NOT_ENOUGH_MEMORY.try_into().unwrap() {
+        panic!(
+            "Failed to convert thread to GUI thread: {}",
+            conversion_result
+        );
+    }
+    GUI_THREAD_ID.store(GetCurrentThreadId(), Ordering::SeqCst);
+
+    let class_name = wide(CLASS_NAME);
+    let class = WNDCLASSEXW {
+        cbSize: std::mem::size_of::<WNDCLASSEXW>() as UINT,
+        lpfnWndProc: Some(window_proc),
+        hInstance: GetModuleHandleW(std::ptr::null()),
+        hCursor: LoadCursorW(std::ptr::null_mut(), IDC_ARROW),
+        lpszClassName: class_name.as_ptr(),
+        ..Default::default()
+    };
+    if RegisterClassExW(&class) == 0 {
+        panic!(
+            "Failed to register the window class: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    gui_thread_init.send(()).unwrap();
+
+    loop {
+        let mut msg = MSG::default();
+        match GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) {
+            0 => {
+                break;
+            }
+            -1 => {
+                // error
+                break;
+            }
+            _ => {}
+        }
+        if msg.hwnd.is_null() {
+            handle_thread_message(&msg);
+            continue;
+        }
+        TranslateMessage(&msg);
+        DispatchMessageW(&msg);
+    }
+}
+
+unsafe fn handle_thread_message(msg: &MSG) {
+    match msg.message {
+        WM_APP_CREATE_WINDOW => create_window(*Box::from_raw(msg.lParam as *mut Window)),
+        WM_APP_DESTROY_WINDOW => {
+            let toplevel_id = msg.wParam as u32;
+            destroy_windows(|window| window.toplevel_id == toplevel_id);
+        }
+        WM_APP_CLIENT_GONE => {
+            let client_id = msg.wParam as u32;
+            KEYBOARDS.with(|keyboards| {
+                keyboards
+                    .borrow_mut()
+                    .retain(|keyboard_id| client_of(*keyboard_id) != client_id)
+            });
+            destroy_windows(|window| client_of(window.toplevel_id) == client_id);
+        }
+        WM_APP_KEYBOARD_ADDED => {
+            let keyboard_id = msg.wParam as u32;
+            KEYBOARDS.with(|keyboards| keyboards.borrow_mut().push(keyboard_id));
+            // A window of the client may have the focus already.
+            send_for(GetFocus(), |window| {
+                if client_of(window.toplevel_id) == client_of(keyboard_id) {
+                    vec![ControlMessage::KeyboardFocus {
+                        keyboard_id,
+                        surface_id: window.surface_id,
+                    }]
+                } else {
+                    Vec::new()
+                }
+            });
+        }
+        WM_APP_KEYBOARD_REMOVED => {
+            let removed = msg.wParam as u32;
+            KEYBOARDS.with(|keyboards| {
+                keyboards
+                    .borrow_mut()
+                    .retain(|keyboard_id| *keyboard_id != removed)
+            });
+        }
+        _ => {}
+    }
+}
+
+unsafe fn create_window(window: Window) {
+    let class_name = wide(CLASS_NAME);
+    let title = wide("");
+    let hwnd = CreateWindowExW(
+        0,
+        class_name.as_ptr(),
+        title.as_ptr(),
+        WS_OVERLAPPEDWINDOW,
+        CW_USEDEFAULT,
+        CW_USEDEFAULT,
+        CW_USEDEFAULT,
+        CW_USEDEFAULT,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        GetModuleHandleW(std::ptr::null()),
+        std::ptr::null_mut(),
+    );
+    if hwnd.is_null() {
+        log::warn!(
+            "no host window for toplevel {:#x}: {}",
+            window.toplevel_id,
+            std::io::Error::last_os_error()
+        );
+        return;
+    }
+    WINDOWS.with(|windows| windows.borrow_mut().insert(hwnd, window));
+    // Known from here on, so the size and the focus it gets on showing go
+    // to the client.
+    ShowWindow(hwnd, SW_SHOW);
+}
+
+// The windows are forgotten first, so that destroying them tells their
+// client nothing: it is gone, or destroyed the toplevel itself.
+unsafe fn destroy_windows<F>(matches: F)
+where
+    F: Fn(&Window) -> bool,
+{
+    let hwnds: Vec<HWND> = WINDOWS.with(|windows| {
+        let mut windows = windows.borrow_mut();
+        let hwnds: Vec<HWND> = windows
+            .iter()
+            .filter(|(_, window)| matches(window))
+            .map(|(hwnd, _)| *hwnd)
+            .collect();
+        for hwnd in &hwnds {
+            windows.remove(hwnd);
+        }
+        hwnds
+    });
+    for hwnd in hwnds {
+        DestroyWindow(hwnd);
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    message: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match message {
+        // Up to the client, which destroys the toplevel if it agrees.
+        WM_CLOSE => {
+            send_for(hwnd, |window| {
+                vec![ControlMessage::WindowClosed {
+                    toplevel_id: window.toplevel_id,
+                }]
+            });
+            return 0;
+        }
+        WM_SIZE => send_for(hwnd, |window| {
+            vec![ControlMessage::WindowResized {
+                xdg_surface_id: window.xdg_surface_id,
+                toplevel_id: window.toplevel_id,
+                width: i32::from(LOWORD(lparam as DWORD)),
+                height: i32::from(HIWORD(lparam as DWORD)),
+            }]
+        }),
+        WM_SETFOCUS | WM_KILLFOCUS => send_for(hwnd, |window| {
+            focus_messages(window, message == WM_SETFOCUS)
+        }),
+        WM_DESTROY => {
+            WINDOWS.with(|windows| windows.borrow_mut().remove(&hwnd));
+        }
+        _ => {}
+    }
+    DefWindowProcW(hwnd, message, wparam, lparam)
+}
+
+fn focus_messages(window: &Window, focused: bool) -> Vec<ControlMessage> {
+    let mut messages = vec![ControlMessage::FocusChanged {
+        xdg_surface_id: window.xdg_surface_id,
+        toplevel_id: window.toplevel_id,
+        focused,
+    }];
+    let client_id = client_of(window.toplevel_id);
+    KEYBOARDS.with(|keyboards| {
+        for keyboard_id in keyboards.borrow().iter() {
+            if client_of(*keyboard_id) == client_id {
+                messages.push(ControlMessage::KeyboardFocus {
+                    keyboard_id: *keyboard_id,
+                    surface_id: if focused { window.surface_id } else { 0 },
+                });
+            }
+        }
+    });
+    messages
+}
+
+// Sends what make_messages makes of the window of hwnd to its client. The
+// messages are made while the windows are borrowed and sent after, as
+// windows that are not known, such as one still being created, get none.
+fn send_for<F>(hwnd: HWND, make_messages: F)
+where
+    F: FnOnce(&Window) -> Vec<ControlMessage>,
+{
+    let made = WINDOWS.with(|windows| {
+        windows
+            .borrow()
+            .get(&hwnd)
+            .map(|window| (window.tx.clone(), make_messages(window)))
+    });
+    let (mut tx, messages) = match made {
+        Some(made) => made,
+        None => return,
+    };
+    for message in messages {
+        if let Err(err) = tx.try_send(Box::new(message)) {
+            log::warn!(
+                "control messages dropped, the client queue is {}",
+                if err.is_full() { "full" } else { "closed" }
+            );
+            return;
+        }
+    }
+}